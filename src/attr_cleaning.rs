@@ -1,6 +1,8 @@
+use std::ops::Range;
+
 use peeking_take_while::PeekableExt;
 use proc_macro2::{LineColumn, Span};
-use syn::punctuated::Punctuated;
+use syn::punctuated::{Pair, Punctuated};
 use syn::spanned::Spanned;
 use syn::visit::Visit;
 use syn::{Attribute, File, Ident, Meta, NestedMeta, Path};
@@ -12,28 +14,157 @@ pub struct CleanError {
     pub message: String,
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct CleanOptions {
+    // Only clean lint attributes that can affect Clippy lints (e.g. `clippy::all` or `warnings`).
+    // Lints that only affect rustc (e.g. `dead_code`) are left alone. Attributes with both kinds
+    // of lint have just the Clippy lints commented out.
+    pub preserve_rustc_lints: bool,
+}
+
+// A change to a source: the bytes in `range` are replaced by `replacement`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+// A section commented out by cleaning. The position of its start is in the same form as
+// `CleanError`'s: the line is 1-based and the column is 0-based in characters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CleanedAttr {
+    pub line: usize,
+    pub column: usize,
+    // The original text of the section (e.g. `#![allow(clippy::all)]`)
+    pub text: String,
+}
+
+pub struct CleanedSource {
+    pub source: String,
+    pub attrs: Vec<CleanedAttr>,
+}
+
+const COMMENT_START: &str = "/* cleaned by clippy_lint_tester ";
+const COMMENT_END: &str = " */";
+
 pub fn clean_source(source: &str) -> Result<Option<String>, CleanError> {
-    let file = syn::parse_file(source).map_err(|err| {
-        let span = err.span();
-        let position = span.start();
-        CleanError {
-            line: position.line,
-            column: position.column,
-            message: format!("{}", err),
-        }
-    })?;
+    clean_source_with_options(source, &CleanOptions::default())
+}
+
+pub fn clean_source_with_options(
+    source: &str,
+    options: &CleanOptions,
+) -> Result<Option<String>, CleanError> {
+    Ok(clean_source_detailed_with_options(source, options)?.map(|cleaned| cleaned.source))
+}
+
+// Like `clean_source` but also returns what was cleaned.
+pub fn clean_source_detailed(source: &str) -> Result<Option<CleanedSource>, CleanError> {
+    clean_source_detailed_with_options(source, &CleanOptions::default())
+}
 
-    let sections = get_bad_sections(&file);
+pub fn clean_source_detailed_with_options(
+    source: &str,
+    options: &CleanOptions,
+) -> Result<Option<CleanedSource>, CleanError> {
+    let file = parse_source(source)?;
+
+    let sections = get_bad_sections(&file, options);
     if sections.is_empty() {
         return Ok(None);
     }
 
+    let attrs = sections
+        .iter()
+        .zip(section_ranges(source, &sections))
+        .map(|([start, _], range)| CleanedAttr {
+            line: start.line,
+            column: start.column,
+            text: source[range].into(),
+        })
+        .collect();
     let cleaned = insert_comments(source, &sections);
 
-    Ok(Some(cleaned))
+    Ok(Some(CleanedSource {
+        source: cleaned,
+        attrs,
+    }))
+}
+
+// The edits cleaning would make without applying them. Nothing else in the source is changed so
+// applying them to a source with `\n` line endings that doesn't end with a newline gives the same
+// result as `clean_source`.
+pub fn clean_source_diff(source: &str) -> Result<Vec<Edit>, CleanError> {
+    clean_source_diff_with_options(source, &CleanOptions::default())
+}
+
+pub fn clean_source_diff_with_options(
+    source: &str,
+    options: &CleanOptions,
+) -> Result<Vec<Edit>, CleanError> {
+    let file = parse_source(source)?;
+
+    let sections = get_bad_sections(&file, options);
+    Ok(section_ranges(source, &sections)
+        .into_iter()
+        .flat_map(|range| [(range.start, COMMENT_START), (range.end, COMMENT_END)])
+        .map(|(offset, replacement)| Edit {
+            range: offset..offset,
+            replacement: replacement.into(),
+        })
+        .collect())
+}
+
+// The byte ranges of sections in the source.
+fn section_ranges(source: &str, sections: &[[LineColumn; 2]]) -> Vec<Range<usize>> {
+    let line_starts: Vec<usize> = [0]
+        .into_iter()
+        .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let offset = |position: LineColumn| {
+        let line_start = line_starts[position.line - 1];
+        let line = source[line_start..].split('\n').next().unwrap_or_default();
+        // Columns are in characters
+        line_start
+            + line
+                .char_indices()
+                .nth(position.column)
+                .map_or(line.len(), |(i, _)| i)
+    };
+
+    sections
+        .iter()
+        .map(|&[start, end]| offset(start)..offset(end))
+        .collect()
+}
+
+// Apply edits sorted by their position which don't overlap.
+#[must_use]
+pub fn apply_edits(source: &str, edits: &[Edit]) -> String {
+    let mut result = String::with_capacity(source.len());
+    let mut start = 0;
+    for edit in edits {
+        result.push_str(&source[start..edit.range.start]);
+        result.push_str(&edit.replacement);
+        start = edit.range.end;
+    }
+    result.push_str(&source[start..]);
+    result
+}
+
+fn parse_source(source: &str) -> Result<File, CleanError> {
+    syn::parse_file(source).map_err(|err| {
+        let span = err.span();
+        let position = span.start();
+        CleanError {
+            line: position.line,
+            column: position.column,
+            message: format!("{}", err),
+        }
+    })
 }
 
-fn get_bad_sections(file: &File) -> Vec<[LineColumn; 2]> {
+fn get_bad_sections(file: &File, options: &CleanOptions) -> Vec<[LineColumn; 2]> {
     let mut segments = Punctuated::new();
     segments.push(Ident::new("clippy", Span::call_site()).into());
     segments.push(Ident::new("msrv", Span::call_site()).into());
@@ -46,6 +177,7 @@ fn get_bad_sections(file: &File) -> Vec<[LineColumn; 2]> {
     let mut visitor = Cleaner {
         sections: vec![],
         msrv_path,
+        options: options.clone(),
     };
 
     visitor.visit_file(file);
@@ -55,6 +187,22 @@ fn get_bad_sections(file: &File) -> Vec<[LineColumn; 2]> {
 struct Cleaner {
     sections: Vec<[LineColumn; 2]>,
     msrv_path: Path,
+    options: CleanOptions,
+}
+
+// Whether a lint named in a lint attribute can affect Clippy's lints.
+fn affects_clippy(lint: &NestedMeta) -> bool {
+    match lint {
+        NestedMeta::Meta(Meta::Path(path)) => {
+            path.is_ident("warnings")
+                || path
+                    .segments
+                    .first()
+                    .is_some_and(|s| s.ident.to_string().starts_with("clippy"))
+        }
+        // Not a lint name so leave it to the compiler to complain about.
+        _ => true,
+    }
 }
 
 impl<'ast> Visit<'ast> for Cleaner {
@@ -76,17 +224,35 @@ impl<'ast> Visit<'ast> for Cleaner {
             }
 
             let path = current.path();
-            if path.is_ident("allow")
-                || path.is_ident("warn")
-                || path.is_ident("deny")
-                || *path == self.msrv_path
-            {
+            if *path == self.msrv_path {
                 self.sections.push([node.span().start(), node.span().end()]);
+            } else if path.is_ident("allow") || path.is_ident("warn") || path.is_ident("deny") {
+                match &current {
+                    Meta::List(meta_list) if self.options.preserve_rustc_lints => {
+                        let lints = &meta_list.nested;
+                        if lints.iter().all(affects_clippy) {
+                            self.sections.push([node.span().start(), node.span().end()]);
+                        } else {
+                            // Comment out each Clippy lint together with its trailing comma.
+                            for pair in lints.pairs().filter(|p| affects_clippy(p.value())) {
+                                let end = match pair {
+                                    Pair::Punctuated(_, comma) => comma.span().end(),
+                                    Pair::End(lint) => lint.span().end(),
+                                };
+                                self.sections.push([pair.value().span().start(), end]);
+                            }
+                        }
+                    }
+                    _ => self.sections.push([node.span().start(), node.span().end()]),
+                }
             }
         }
     }
 }
 
+// Sections are replaced by block comments rather than removed so an inner attribute stays where it
+// was, even when it's the only thing in a module. Block comments nest so comments inside an
+// attribute don't end the section early.
 fn insert_comments(source: &str, sections: &[[LineColumn; 2]]) -> String {
     enum InsertType {
         CommentStart,
@@ -129,8 +295,8 @@ fn insert_comments(source: &str, sections: &[[LineColumn; 2]]) -> String {
             }
 
             result.push_str(match insert_type {
-                InsertType::CommentStart => "/* cleaned by clippy_lint_tester ",
-                InsertType::CommentEnd => " */",
+                InsertType::CommentStart => COMMENT_START,
+                InsertType::CommentEnd => COMMENT_END,
             });
         }
 
@@ -146,7 +312,10 @@ fn insert_comments(source: &str, sections: &[[LineColumn; 2]]) -> String {
 #[allow(clippy::unwrap_used)]
 mod tests {
 
-    use super::clean_source;
+    use super::{
+        apply_edits, clean_source, clean_source_detailed_with_options, clean_source_diff,
+        clean_source_diff_with_options, clean_source_with_options, CleanOptions, CleanedAttr, Edit,
+    };
 
     use expect_test::expect;
     use indoc::indoc;
@@ -217,6 +386,23 @@ mod tests {
         expected.assert_eq(&result);
     }
 
+    #[test]
+    fn test_deny_warnings() {
+        let result = clean_source(indoc! {"
+            #![deny(warnings)]
+
+            fn f() { }
+        "})
+        .unwrap()
+        .unwrap();
+
+        let expected = expect![[r"
+            /* cleaned by clippy_lint_tester #![deny(warnings)] */
+
+            fn f() { }"]];
+        expected.assert_eq(&result);
+    }
+
     #[test]
     fn test_cfg_attr_feature() {
         let result = clean_source(indoc! {r##"
@@ -282,4 +468,290 @@ mod tests {
             /* cleaned by clippy_lint_tester #![clippy::msrv = "1.30.0"] */"##]];
         expected.assert_eq(&result);
     }
+
+    #[test]
+    fn functional_attributes_preserved() {
+        let result = clean_source(indoc! {r#"
+            #![no_std]
+            #![no_main]
+            #![crate_type = "lib"]
+            #![feature(register_tool)]
+            #![register_tool(tool)]
+            #![deny(clippy::all)]
+        "#})
+        .unwrap()
+        .unwrap();
+
+        let expected = expect![[r#"
+            #![no_std]
+            #![no_main]
+            #![crate_type = "lib"]
+            #![feature(register_tool)]
+            #![register_tool(tool)]
+            /* cleaned by clippy_lint_tester #![deny(clippy::all)] */"#]];
+        expected.assert_eq(&result);
+    }
+
+    #[test]
+    fn only_functional_attributes_unchanged() {
+        assert!(clean_source(indoc! {r#"
+                #![no_std]
+                #![no_main]
+                #![crate_type = "lib"]
+                #![feature(register_tool)]
+                #![register_tool(tool)]
+
+                #[no_mangle]
+                pub extern "C" fn f() {}
+            "#})
+        .unwrap()
+        .is_none());
+    }
+
+    #[test]
+    fn mixed_lints_cleaned_by_default() {
+        let result = clean_source(indoc! {"
+            #[allow(dead_code, clippy::all)]
+            fn f() { }
+        "})
+        .unwrap()
+        .unwrap();
+
+        let expected = expect![[r"
+            /* cleaned by clippy_lint_tester #[allow(dead_code, clippy::all)] */
+            fn f() { }"]];
+        expected.assert_eq(&result);
+    }
+
+    #[test]
+    fn mixed_lints_preserve_rustc_lints() {
+        let options = CleanOptions {
+            preserve_rustc_lints: true,
+        };
+        let result = clean_source_with_options(
+            indoc! {"
+                #[allow(clippy::all, dead_code, clippy::pedantic)]
+                fn f() { }
+            "},
+            &options,
+        )
+        .unwrap()
+        .unwrap();
+
+        let expected = expect![[r"
+            #[allow(/* cleaned by clippy_lint_tester clippy::all, */ dead_code, /* cleaned by clippy_lint_tester clippy::pedantic */)]
+            fn f() { }"]];
+        expected.assert_eq(&result);
+    }
+
+    #[test]
+    fn only_rustc_lints_preserved() {
+        let options = CleanOptions {
+            preserve_rustc_lints: true,
+        };
+        assert!(clean_source_with_options(
+            indoc! {"
+                #![allow(dead_code, unused_imports)]
+            "},
+            &options,
+        )
+        .unwrap()
+        .is_none());
+    }
+
+    #[test]
+    fn only_clippy_lints_cleaned_whole() {
+        let options = CleanOptions {
+            preserve_rustc_lints: true,
+        };
+        let result = clean_source_with_options(
+            indoc! {"
+                #![deny(warnings, clippy::pedantic)]
+            "},
+            &options,
+        )
+        .unwrap()
+        .unwrap();
+
+        let expected =
+            expect![[r"/* cleaned by clippy_lint_tester #![deny(warnings, clippy::pedantic)] */"]];
+        expected.assert_eq(&result);
+    }
+
+    #[test]
+    fn test_diff_edits() {
+        let source = "#![allow(clippy::all)]\nfn f() {}";
+        let edits = clean_source_diff(source).unwrap();
+        assert_eq!(
+            edits,
+            [
+                Edit {
+                    range: 0..0,
+                    replacement: "/* cleaned by clippy_lint_tester ".into(),
+                },
+                Edit {
+                    range: 22..22,
+                    replacement: " */".into(),
+                },
+            ]
+        );
+
+        assert!(clean_source_diff("fn f() {}").unwrap().is_empty());
+        assert!(clean_source_diff("fn f(").is_err());
+    }
+
+    #[test]
+    fn test_detailed() {
+        let source = "// é\n#![allow(\n    clippy::approx_constant,\n    dead_code,\n)]\nfn f() {}";
+        let cleaned = clean_source_detailed_with_options(
+            source,
+            &CleanOptions {
+                preserve_rustc_lints: true,
+            },
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            cleaned.attrs,
+            [CleanedAttr {
+                line: 3,
+                column: 4,
+                text: "clippy::approx_constant,".into(),
+            }]
+        );
+        assert_eq!(
+            Some(cleaned.source),
+            clean_source_with_options(
+                source,
+                &CleanOptions {
+                    preserve_rustc_lints: true,
+                }
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_diff_matches_clean_source() {
+        let sources = [
+            "#![allow(clippy::all)]\n\nfn f() {}",
+            "// é\n#![allow(\n    clippy::approx_constant,\n    dead_code,\n)]\nfn f() {}",
+            "/* ü */ #![cfg_attr(any_cfg, deny(clippy::all))] #![clippy::msrv = \"1.0\"]",
+            "#[warn(clippy::pedantic)]\nfn f() {\n    #[allow(clippy::approx_constant)]\n    let _ = 3.14;\n}",
+        ];
+        for options in [
+            CleanOptions::default(),
+            CleanOptions {
+                preserve_rustc_lints: true,
+            },
+        ] {
+            for source in sources {
+                let edits = clean_source_diff_with_options(source, &options).unwrap();
+                let cleaned = clean_source_with_options(source, &options)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(apply_edits(source, &edits), cleaned);
+            }
+        }
+    }
+
+    // Cleaned sources are built by the tester so must still parse.
+    fn clean_and_parse(source: &str, options: &CleanOptions) -> String {
+        let cleaned = clean_source_with_options(source, options).unwrap().unwrap();
+        if let Err(err) = syn::parse_file(&cleaned) {
+            panic!("cleaning `{source}` gave `{cleaned}` which doesn't parse: {err}");
+        }
+        cleaned
+    }
+
+    #[test]
+    fn inner_allow_after_feature() {
+        let result = clean_source(indoc! {"
+            #![feature(rustc_private)]
+            #![allow(clippy::all)]
+            #![feature(let_chains)]
+
+            fn f() { }
+        "})
+        .unwrap()
+        .unwrap();
+
+        let expected = expect![[r"
+            #![feature(rustc_private)]
+            /* cleaned by clippy_lint_tester #![allow(clippy::all)] */
+            #![feature(let_chains)]
+
+            fn f() { }"]];
+        expected.assert_eq(&result);
+
+        let file = syn::parse_file(&result).unwrap();
+        assert_eq!(file.attrs.len(), 2);
+    }
+
+    #[test]
+    fn inner_allow_in_nested_mod() {
+        let result = clean_source(indoc! {"
+            mod m {
+                #![allow(clippy::all)]
+                #![warn(clippy::pedantic)]
+
+                mod n {
+                    #![deny(warnings)]
+                    fn f() { }
+                }
+            }
+        "})
+        .unwrap()
+        .unwrap();
+
+        let expected = expect![[r"
+            mod m {
+                /* cleaned by clippy_lint_tester #![allow(clippy::all)] */
+                /* cleaned by clippy_lint_tester #![warn(clippy::pedantic)] */
+
+                mod n {
+                    /* cleaned by clippy_lint_tester #![deny(warnings)] */
+                    fn f() { }
+                }
+            }"]];
+        expected.assert_eq(&result);
+
+        syn::parse_file(&result).unwrap();
+    }
+
+    #[test]
+    fn inner_allow_only_content_of_mod() {
+        for source in [
+            "mod m { #![allow(clippy::all)] }",
+            "mod m {\n    #![allow(clippy::all)]\n}",
+            "mod m { #![allow(clippy::all)] #![clippy::msrv = \"1.0\"] }",
+            "mod m { mod n { #![allow(clippy::all)] } }",
+            "fn f() { #![allow(clippy::all)] }",
+        ] {
+            let cleaned = clean_and_parse(source, &CleanOptions::default());
+            assert!(clean_source(&cleaned).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn inner_attrs_still_parse() {
+        let sources = [
+            "#![feature(x)]\n#![allow(clippy::all)]",
+            "#![feature(x)] #![allow(clippy::all, dead_code)] #![feature(y)]\nfn f() {}",
+            "mod m {\n    #![feature(x)]\n    #![allow(clippy::all, dead_code)]\n}",
+            "mod m { #![allow(clippy::all /* reason */)] }",
+            "mod m { #![cfg_attr(any_cfg, allow(clippy::all))] fn f() {} }",
+            "#[allow(clippy::all)]\nmod m {\n    #![allow(clippy::pedantic)]\n}",
+        ];
+        for options in [
+            CleanOptions::default(),
+            CleanOptions {
+                preserve_rustc_lints: true,
+            },
+        ] {
+            for source in sources {
+                clean_and_parse(source, &options);
+            }
+        }
+    }
 }
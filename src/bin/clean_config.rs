@@ -2,19 +2,26 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use argh::FromArgs;
-use clippy_lint_tester::clean_config;
+use clippy_lint_tester::{clean_config_with_manifest, DEFAULT_MANIFEST_NAME};
 
 #[derive(FromArgs)]
 /// Modify the Cargo manifest and Clippy config for testing
 struct Args {
     #[argh(positional)]
     target: PathBuf,
+
+    #[argh(option, default = "PathBuf::from(DEFAULT_MANIFEST_NAME)")]
+    /// the path of the manifest relative to the crate's dir (default: `Cargo.toml`)
+    manifest_name: PathBuf,
 }
 
 fn main() -> Result<()> {
-    let Args { target } = argh::from_env();
+    let Args {
+        target,
+        manifest_name,
+    } = argh::from_env();
 
-    clean_config(&target)?;
+    clean_config_with_manifest(&target, &manifest_name)?;
 
     Ok(())
 }
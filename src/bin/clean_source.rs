@@ -1,8 +1,11 @@
-use std::path::PathBuf;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use argh::FromArgs;
-use clippy_lint_tester::clean_attrs;
+use clippy_lint_tester::attr_cleaning::CleanOptions;
+use clippy_lint_tester::{clean_attrs_into, clean_attrs_with_report, CleanedFile};
 
 #[derive(FromArgs)]
 /// Remove all attrs that might affect linting.
@@ -10,12 +13,76 @@ struct Args {
     #[argh(positional)]
     /// path to the file or dir to clean
     path: PathBuf,
+
+    #[argh(switch)]
+    /// only remove attrs that can affect Clippy lints
+    preserve_rustc_lints: bool,
+
+    #[argh(option)]
+    /// write a list of every attr removed with its location to a file
+    report: Option<PathBuf>,
+
+    #[argh(option)]
+    /// copy the path here and clean the copy, leaving the path untouched. It must not exist.
+    clean_to: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
-    let Args { path } = argh::from_env();
+    let Args {
+        path,
+        preserve_rustc_lints,
+        report,
+        clean_to,
+    } = argh::from_env();
+
+    let options = CleanOptions {
+        preserve_rustc_lints,
+    };
+    let (cleaned_path, (_, cleaned)) = match &clean_to {
+        Some(clean_to) => (clean_to, clean_attrs_into(&path, clean_to, &options)?),
+        None => (&path, clean_attrs_with_report(&path, &options)?),
+    };
 
-    clean_attrs(&path)?;
+    if let Some(report) = report {
+        let mut output = io::BufWriter::new(
+            fs::File::create(&report).with_context(|| format!("Creating {}", report.display()))?,
+        );
+        write_report(cleaned_path, &cleaned, &mut output)
+            .and_then(|()| output.flush().map_err(Into::into))
+            .with_context(|| format!("Writing {}", report.display()))?;
+    }
+
+    Ok(())
+}
 
+// One line per attr as `path:line:column text` followed by the total. Paths are relative to the
+// cleaned dir and attrs over several lines are joined into one.
+fn write_report(root: &Path, cleaned: &[CleanedFile], mut output: impl Write) -> Result<()> {
+    let mut total = 0;
+    for CleanedFile { path, attrs } in cleaned {
+        let path = path.strip_prefix(root).unwrap_or(path);
+        let path = if path.as_os_str().is_empty() {
+            // `root` is the cleaned file
+            root
+        } else {
+            path
+        };
+        for attr in attrs {
+            let text: Vec<_> = attr.text.split_whitespace().collect();
+            writeln!(
+                output,
+                "{}:{}:{} {}",
+                path.display(),
+                attr.line,
+                attr.column,
+                text.join(" ")
+            )?;
+        }
+        total += attrs.len();
+    }
+    if total > 0 {
+        writeln!(output)?;
+    }
+    writeln!(output, "Total: {total}")?;
     Ok(())
 }
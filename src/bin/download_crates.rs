@@ -3,24 +3,34 @@
 #![warn(clippy::pedantic)]
 #![warn(clippy::unwrap_used)]
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fmt::{self, Write};
 use std::fs;
-use std::io;
-use std::path::{Path, PathBuf};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 use std::time::{Duration, Instant};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use argh::FromArgs;
 use flate2::read::GzDecoder;
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use tar::Archive;
 use ureq::{Agent, AgentBuilder};
 
+use clippy_lint_tester::attr_cleaning::CleanOptions;
 use clippy_lint_tester::{
-    clean_attrs, clean_config, ensure_empty_dir, EnsureEmptyDirOutcome, FileCleanError, ProgressBar,
+    clean_attrs_with_options, clean_config, ensure_empty_dir_or_clear, verify_clean,
+    EnsureEmptyDirOutcome, FileCleanError, ProgressBar, ProgressMode, VerifyCleanOutcome,
 };
 
 #[derive(FromArgs)]
+#[allow(clippy::struct_excessive_bools)]
 /// Download the all-time most downloaded crates on crates.io and remove any settings
 /// (lint attributes, clippy.config, etc.) that may interfere with lint testing.
 /// Removing lint attributes is 'best effort'. Use `--show-attr-errors` to display errors.
@@ -33,9 +43,49 @@ struct Args {
     /// crates to exclude
     #[argh(option, short = 'x')]
     exclude: Vec<String>,
+    /// a file of crates to exclude, one per line. Text after a `#` is ignored.
+    #[argh(option)]
+    exclude_file: Option<PathBuf>,
     /// display attribute removal errors
     #[argh(switch)]
     show_attr_errors: bool,
+    /// only remove lint attributes that can affect Clippy lints
+    #[argh(switch)]
+    preserve_rustc_lints: bool,
+    /// check that each cleaned crate still compiles and report crates broken by the cleaning
+    #[argh(switch)]
+    verify_clean: bool,
+    /// the directory downloaded crate archives are cached in (default: the platform's cache dir,
+    /// e.g. `~/.cache/clippy_lint_tester/crates`)
+    #[argh(option)]
+    cache_dir: Option<PathBuf>,
+    /// the number of crates to download at the same time
+    #[argh(option, default = "4")]
+    download_jobs: usize,
+    /// remove the contents of the target directory if it isn't empty
+    #[argh(switch)]
+    force: bool,
+    /// keep the crates as downloaded without removing lint attributes, configs or lockfiles
+    #[argh(switch)]
+    no_clean: bool,
+    /// list the crates that would be downloaded without downloading them
+    #[argh(switch)]
+    dry_run: bool,
+    /// write the crates that failed to download to this file
+    #[argh(option)]
+    failure_report: Option<PathBuf>,
+    /// retry the crates in a `--failure-report` of an earlier download, adding them to the
+    /// existing target
+    #[argh(option)]
+    redownload_failed: Option<PathBuf>,
+    /// download the crates.io packages in a `Cargo.lock` at their locked versions instead of the
+    /// most downloaded crates. Crates already in the target are kept.
+    #[argh(option)]
+    from_lock: Option<PathBuf>,
+    /// when to show the progress bar: `auto` (default) when stderr is a terminal, `always` or
+    /// `never`
+    #[argh(option, default = "ProgressMode::Auto")]
+    progress: ProgressMode,
 }
 
 #[derive(Deserialize, Debug)]
@@ -48,6 +98,10 @@ struct Crate {
     name: String,
     max_version: String,
     max_stable_version: Option<String>,
+    // The SHA-256 of the archive if it's already known, e.g. from a lockfile. Otherwise it's
+    // looked up in the index.
+    #[serde(skip)]
+    checksum: Option<String>,
 }
 
 impl Crate {
@@ -60,21 +114,209 @@ impl Crate {
 
 const CRATES_IO_MAX_PER_PAGE: usize = 100;
 
+// These can be overridden to point the tool at a mock server.
+const DEFAULT_API_URL: &str = "https://crates.io/api/v1";
+const DEFAULT_STATIC_URL: &str = "https://static.crates.io";
+const DEFAULT_INDEX_URL: &str = "https://index.crates.io";
+
+fn url_from_env(var: &str, default: &str) -> String {
+    env::var(var).unwrap_or_else(|_| default.to_string())
+}
+
 fn main() -> Result<()> {
+    let args: Args = argh::from_env();
+    check_args(&args)?;
     let Args {
         target,
         number,
-        exclude,
+        mut exclude,
+        exclude_file,
         show_attr_errors,
-    } = argh::from_env();
+        preserve_rustc_lints,
+        verify_clean,
+        cache_dir,
+        download_jobs,
+        force,
+        no_clean,
+        dry_run,
+        failure_report,
+        redownload_failed,
+        from_lock,
+        progress,
+    } = args;
+
+    let clean_options = CleanOptions {
+        preserve_rustc_lints,
+    };
+    let verify_target_dir = verify_clean.then(|| target.join("_target"));
+
+    if let Some(exclude_file) = &exclude_file {
+        exclude.extend(read_exclude_file(exclude_file)?);
+    }
 
-    if number == 0 {
+    let api_url = url_from_env("CRATES_IO_API_URL", DEFAULT_API_URL);
+    let source = match (redownload_failed, from_lock) {
+        (Some(_), Some(_)) => bail!("`--redownload-failed` can't be used with `--from-lock`"),
+        (Some(report), None) => CrateSource::FailureReport(report),
+        (None, Some(lockfile)) => CrateSource::Lockfile(lockfile),
+        (None, None) => CrateSource::TopCrates,
+    };
+    if dry_run {
+        if !matches!(source, CrateSource::TopCrates) {
+            bail!("`--dry-run` can't be used with `--redownload-failed` or `--from-lock`");
+        }
+        return print_top_crates(&api_url, &exclude, number);
+    }
+
+    prepare_target(&target, &source, force)?;
+
+    let cache_dir = cache_dir
+        .or_else(default_cache_dir)
+        .context("Failed to find a cache dir. Use `--cache-dir` to set one.")?;
+    fs::create_dir_all(&cache_dir)
+        .with_context(|| format!("Failed to create cache dir {}", cache_dir.display()))?;
+
+    let static_url = url_from_env("CRATES_IO_STATIC_URL", DEFAULT_STATIC_URL);
+    let index_url = url_from_env("CRATES_IO_INDEX_URL", DEFAULT_INDEX_URL);
+
+    // The listing is rate limited so it's done up front, one page at a time. The downloads
+    // come from the CDN and can run concurrently afterwards.
+    let listing_start = Instant::now();
+    let crates = match &source {
+        CrateSource::TopCrates => list_top_crates(&api_url, &exclude, number)?.crates,
+        CrateSource::FailureReport(report) => {
+            skip_downloaded(read_failure_report(report)?, &target)
+        }
+        CrateSource::Lockfile(lockfile) => {
+            let mut crates = read_lockfile(lockfile)?;
+            crates.retain(|krate| !exclude.contains(&krate.name));
+            skip_downloaded(crates, &target)
+        }
+    };
+    let listing_time = listing_start.elapsed();
+
+    let download_start = Instant::now();
+    let mut progress_bar = ProgressBar::new(progress);
+    progress_bar.display_progress(crates.len(), "Starting...");
+    let progress_bar = Mutex::new(progress_bar);
+
+    let failures = download_crates(
+        &crates,
+        download_jobs,
+        &CrateSettings {
+            static_url: &static_url,
+            index_url: &index_url,
+            cache_dir: &cache_dir,
+            target: &target,
+            clean_options: &clean_options,
+            show_attr_errors,
+            verify_target_dir: verify_target_dir.as_deref(),
+            clean: !no_clean,
+        },
+        &progress_bar,
+    );
+    drop(progress_bar);
+    let download_time = download_start.elapsed();
+
+    print_download_summary(
+        &source,
+        crates.len(),
+        &failures,
+        listing_time,
+        download_time,
+    );
+    if let Some(failure_report) = &failure_report {
+        write_failure_report(failure_report, &failures)?;
+    }
+
+    Ok(())
+}
+
+// The checks of the arguments that argh can't do.
+fn check_args(args: &Args) -> Result<()> {
+    if args.number == 0 {
         bail!("The number of crates must be positive.")
     }
+    if args.download_jobs == 0 {
+        bail!("The number of download jobs must be positive.")
+    }
+    if args.no_clean {
+        for (set, arg) in [
+            (args.show_attr_errors, "--show-attr-errors"),
+            (args.preserve_rustc_lints, "--preserve-rustc-lints"),
+            (args.verify_clean, "--verify-clean"),
+        ] {
+            if set {
+                bail!("`{arg}` can't be used with `--no-clean`");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_download_summary(
+    source: &CrateSource,
+    crate_count: usize,
+    failures: &[CrateFailure],
+    listing_time: Duration,
+    download_time: Duration,
+) {
+    if matches!(source, CrateSource::TopCrates) {
+        println!(
+            "Listed {} crates in {:.1}s",
+            crate_count,
+            listing_time.as_secs_f64()
+        );
+    }
+    println!(
+        "Downloaded {} crates in {:.1}s",
+        crate_count - failures.len(),
+        download_time.as_secs_f64()
+    );
+    print_failure_summary(failures);
+    if matches!(source, CrateSource::FailureReport(_)) {
+        println!(
+            "Resolved {} of {} failed crates",
+            crate_count - failures.len(),
+            crate_count
+        );
+    }
+}
 
-    match ensure_empty_dir(&target)? {
+// Where the crates to download come from.
+enum CrateSource {
+    // The most downloaded crates on crates.io
+    TopCrates,
+    // The crates in a `--failure-report`, added to the earlier download
+    FailureReport(PathBuf),
+    // The crates in a `Cargo.lock`, added to the crates already in the target
+    Lockfile(PathBuf),
+}
+
+// Create the target, or clear it if the crates aren't added to an existing download.
+fn prepare_target(target: &Path, source: &CrateSource, force: bool) -> Result<()> {
+    match source {
+        CrateSource::FailureReport(_) => {
+            if !target.is_dir() {
+                bail!(
+                    "Target {} doesn't exist - `--redownload-failed` adds to an existing download",
+                    target.display()
+                );
+            }
+            return Ok(());
+        }
+        CrateSource::Lockfile(_) => {
+            return fs::create_dir_all(target).context("Failed to create target");
+        }
+        CrateSource::TopCrates => {}
+    }
+
+    match ensure_empty_dir_or_clear(target, force)? {
         EnsureEmptyDirOutcome::Created => println!("Target directory created"),
-        EnsureEmptyDirOutcome::NonEmpty => bail!("Target exists and not empty"),
+        EnsureEmptyDirOutcome::Cleared => println!("Target directory cleared"),
+        EnsureEmptyDirOutcome::NonEmpty => {
+            bail!("Target exists and not empty - use `--force` to clear it")
+        }
         EnsureEmptyDirOutcome::Empty => {}
     }
 
@@ -86,62 +328,419 @@ fn main() -> Result<()> {
         }
         Err(err) => match err.kind() {
             io::ErrorKind::NotFound => {
-                fs::create_dir_all(&target).context("Failed to create target")?;
+                fs::create_dir_all(target).context("Failed to create target")?;
             }
             _ => return Err(err).context("Failed to read target"),
         },
     }
+    Ok(())
+}
+
+// The crate names in an `--exclude-file`. Blank lines and `#` comments are skipped.
+fn read_exclude_file(path: &Path) -> Result<Vec<String>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read exclude file {}", path.display()))?;
+    Ok(contents
+        .lines()
+        .map(|line| line.split_once('#').map_or(line, |(name, _)| name).trim())
+        .filter(|name| !name.is_empty())
+        .map(Into::into)
+        .collect())
+}
+
+#[derive(Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    // `None` for path dependencies and the workspace's own packages
+    source: Option<String>,
+    checksum: Option<String>,
+}
+
+// The crates.io packages in a `Cargo.lock` at their locked versions. Path and git packages and
+// other registries are skipped.
+fn read_lockfile(path: &Path) -> Result<Vec<Crate>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read lockfile {}", path.display()))?;
+    let lockfile: Lockfile = toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse lockfile {}", path.display()))?;
+
+    let mut locked = BTreeSet::new();
+    let mut crates = vec![];
+    for package in lockfile.package {
+        if !package.source.as_deref().is_some_and(is_crates_io_source) {
+            continue;
+        }
+        // The same version can't be downloaded twice.
+        if locked.insert((package.name.clone(), package.version.clone())) {
+            crates.push(Crate {
+                name: package.name,
+                max_version: package.version,
+                max_stable_version: None,
+                checksum: package.checksum,
+            });
+        }
+    }
+    Ok(crates)
+}
+
+// Whether a lockfile `source` is crates.io, either through the git or the sparse index.
+fn is_crates_io_source(source: &str) -> bool {
+    source == "registry+https://github.com/rust-lang/crates.io-index"
+        || source == "sparse+https://index.crates.io/"
+}
+
+// Removes the crates already in the target so they aren't downloaded again.
+fn skip_downloaded(mut crates: Vec<Crate>, target: &Path) -> Vec<Crate> {
+    crates.retain(|krate| {
+        let downloaded = target.join(crate_dir_name(krate)).exists();
+        if downloaded {
+            println!("Skipping '{}'. Already downloaded.", &krate.name);
+        }
+        !downloaded
+    });
+    crates
+}
+
+// The cause of a crate failing to download. Used to group the failures in the summary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum FailureKind {
+    // The request failed or timed out, or the server returned an error
+    Network,
+    // The crate's archive doesn't exist
+    NotFound,
+    // The downloaded archive doesn't match the registry checksum
+    Checksum,
+    // Unpacking, cleaning or otherwise writing the crate failed
+    Io,
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FailureKind::Network => "Network",
+            FailureKind::NotFound => "Not found",
+            FailureKind::Checksum => "Checksum",
+            FailureKind::Io => "Unpack/IO",
+        })
+    }
+}
 
-    let mut progress_bar = ProgressBar::new();
-    progress_bar.display_progress(number, "Starting...");
+struct DownloadError {
+    kind: FailureKind,
+    error: anyhow::Error,
+}
 
-    let mut agent: Agent = AgentBuilder::new().build();
+impl From<anyhow::Error> for DownloadError {
+    fn from(error: anyhow::Error) -> Self {
+        DownloadError {
+            kind: FailureKind::Io,
+            error,
+        }
+    }
+}
+
+trait FailureContext<T> {
+    fn failure_kind(self, kind: FailureKind) -> Result<T, DownloadError>;
+}
 
-    let mut downloaded_crates = BTreeSet::new();
-    for krate in list_crates(&exclude).take(number) {
-        let krate = krate?;
-        let crate_path = &target.join(format!("{}-{}", &krate.name, &krate.version()));
+impl<T> FailureContext<T> for Result<T> {
+    fn failure_kind(self, kind: FailureKind) -> Result<T, DownloadError> {
+        self.map_err(|error| DownloadError { kind, error })
+    }
+}
+
+// A crate that failed to download and the cause.
+struct CrateFailure {
+    name: String,
+    version: String,
+    kind: FailureKind,
+}
 
-        progress_bar.inc_progress(&krate.name);
-        if downloaded_crates.contains(&krate.name) {
+// Each failed crate on a line as `name version # kind`. An empty report is still written so that
+// the report of an earlier download isn't left behind.
+fn write_failure_report(path: &Path, failures: &[CrateFailure]) -> Result<()> {
+    let mut report =
+        String::from("# Crates that failed to download. Retry them with `--redownload-failed`.\n");
+    for failure in failures {
+        writeln!(
+            report,
+            "{} {} # {}",
+            failure.name, failure.version, failure.kind
+        )
+        .expect("Write to string succeeds");
+    }
+    fs::write(path, report)
+        .with_context(|| format!("Failed to write failure report {}", path.display()))
+}
+
+// The crates in a `--failure-report`. Blank lines and `#` comments are skipped.
+fn read_failure_report(path: &Path) -> Result<Vec<Crate>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read failure report {}", path.display()))?;
+    let mut crates = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split_once('#').map_or(line, |(krate, _)| krate);
+        let mut words = line.split_whitespace();
+        let (name, version) = match (words.next(), words.next(), words.next()) {
+            (None, ..) => continue,
+            (Some(name), Some(version), None) => (name, version),
+            _ => bail!(
+                "Line {} of failure report {} isn't `name version`",
+                i + 1,
+                path.display()
+            ),
+        };
+        crates.push(Crate {
+            name: name.into(),
+            max_version: version.into(),
+            max_stable_version: None,
+            checksum: None,
+        });
+    }
+    Ok(crates)
+}
+
+fn print_failure_summary(failures: &[CrateFailure]) {
+    if failures.is_empty() {
+        return;
+    }
+
+    let mut by_kind: BTreeMap<FailureKind, Vec<&str>> = BTreeMap::new();
+    for failure in failures {
+        by_kind.entry(failure.kind).or_default().push(&failure.name);
+    }
+    println!("Failed to download {} crates:", failures.len());
+    for (kind, mut names) in by_kind {
+        names.sort_unstable();
+        println!("- {} ({}): {}", kind, names.len(), names.join(", "));
+    }
+}
+
+// Settings shared by every crate download.
+struct CrateSettings<'a> {
+    static_url: &'a str,
+    index_url: &'a str,
+    cache_dir: &'a Path,
+    target: &'a Path,
+    clean_options: &'a CleanOptions,
+    show_attr_errors: bool,
+    verify_target_dir: Option<&'a Path>,
+    // `false` to leave the crates as downloaded
+    clean: bool,
+}
+
+/// Downloads and cleans `crates` using up to `jobs` threads. A failed crate is reported and
+/// removed without stopping the others.
+fn download_crates(
+    crates: &[Crate],
+    jobs: usize,
+    settings: &CrateSettings<'_>,
+    progress_bar: &Mutex<ProgressBar>,
+) -> Vec<CrateFailure> {
+    let agent: Agent = AgentBuilder::new().build();
+    let next_crate = AtomicUsize::new(0);
+    let failures = Mutex::new(vec![]);
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(crates.len()) {
+            scope.spawn(|| {
+                while let Some(krate) = crates.get(next_crate.fetch_add(1, Ordering::SeqCst)) {
+                    let Err(DownloadError { kind, error }) =
+                        download_and_clean_crate(&agent, krate, settings, progress_bar)
+                    else {
+                        continue;
+                    };
+                    lock(progress_bar).println(
+                        &krate.name,
+                        format!("error: Failed to download '{}' - {:#}", krate.name, error),
+                    );
+                    // Don't leave a partly cleaned crate behind to be linted. It may not have
+                    // been unpacked at all.
+                    let _ = fs::remove_dir_all(settings.target.join(crate_dir_name(krate)));
+                    failures
+                        .lock()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner)
+                        .push(CrateFailure {
+                            name: krate.name.clone(),
+                            version: krate.version().into(),
+                            kind,
+                        });
+                }
+            });
+        }
+    });
+    failures
+        .into_inner()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+fn crate_dir_name(krate: &Crate) -> String {
+    format!("{}-{}", &krate.name, &krate.version())
+}
+
+fn download_and_clean_crate(
+    agent: &Agent,
+    krate: &Crate,
+    settings: &CrateSettings<'_>,
+    progress_bar: &Mutex<ProgressBar>,
+) -> Result<(), DownloadError> {
+    let CrateSettings {
+        static_url,
+        index_url,
+        cache_dir,
+        target,
+        clean,
+        ..
+    } = *settings;
+
+    lock(progress_bar).inc_progress(&krate.name);
+    // Usually `name-version`, but that's only a convention of the archives.
+    let crate_path = &download_crate(agent, krate, static_url, index_url, cache_dir, target)?;
+    if !clean {
+        return Ok(());
+    }
+    clean_crate(krate, crate_path, settings, progress_bar).inspect_err(|_| {
+        // Only a crate in the conventional dir is removed by the caller.
+        let _ = fs::remove_dir_all(crate_path);
+    })
+}
+
+fn clean_crate(
+    krate: &Crate,
+    crate_path: &Path,
+    settings: &CrateSettings<'_>,
+    progress_bar: &Mutex<ProgressBar>,
+) -> Result<(), DownloadError> {
+    let CrateSettings {
+        clean_options,
+        show_attr_errors,
+        verify_target_dir,
+        ..
+    } = *settings;
+
+    clean_config(crate_path)?;
+
+    let errors = clean_attrs_with_options(crate_path, clean_options)?;
+    if show_attr_errors {
+        let mut progress_bar = lock(progress_bar);
+        for FileCleanError { path, error } in errors {
             progress_bar.println(
                 &krate.name,
-                &format!(
-                    "Skipping '{}'. Listed twice by crates.io. (Possibly the changed position during downloading.)",
-                    &krate.name
+                format!(
+                    "error: Attribute removal failed at {}:{}:{} - {}",
+                    path.display(),
+                    error.line,
+                    error.column,
+                    error.message,
                 ),
             );
-            continue;
-        }
-        download_crate(&mut agent, &krate, &target)?;
-        clean_config(crate_path)?;
-
-        let errors = clean_attrs(crate_path)?;
-        if show_attr_errors {
-            for FileCleanError { path, error } in errors {
-                progress_bar.println(
-                    &krate.name,
-                    &format!(
-                        "error: Attribute removal failed at {}:{}:{} - {}",
-                        path.display(),
-                        error.line,
-                        error.column,
-                        error.message,
-                    ),
-                );
-            }
         }
+    }
 
-        remove_cargo_config(crate_path)?;
-        remove_cargo_lock(crate_path)?;
+    remove_cargo_config(crate_path)?;
+    remove_cargo_lock(crate_path)?;
 
-        downloaded_crates.insert(krate.name);
+    if let Some(verify_target_dir) = verify_target_dir {
+        match verify_clean(crate_path, verify_target_dir)? {
+            VerifyCleanOutcome::Compiles => {}
+            VerifyCleanOutcome::AlreadyBroken => lock(progress_bar).println(
+                &krate.name,
+                format!(
+                    "warning: '{}' doesn't compile even before cleaning",
+                    krate.name
+                ),
+            ),
+            VerifyCleanOutcome::BrokenByCleaning(errors) => lock(progress_bar).println(
+                &krate.name,
+                format!(
+                    "error: Cleaning broke the build of '{}':\n{}",
+                    krate.name, errors
+                ),
+            ),
+        }
+        // Checking generates a lock file.
+        remove_cargo_lock(crate_path)?;
     }
 
     Ok(())
 }
 
-fn list_crates(exclude: &[String]) -> impl Iterator<Item = Result<Crate>> + '_ {
+fn lock(progress_bar: &Mutex<ProgressBar>) -> std::sync::MutexGuard<'_, ProgressBar> {
+    // A poisoned bar is still fine to draw on.
+    progress_bar
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+// Only lists the crates for `--dry-run`. Neither the target nor the cache are touched.
+fn print_top_crates(api_url: &str, exclude: &[String], number: usize) -> Result<()> {
+    let TopCrates { crates, duplicates } = list_top_crates(api_url, exclude, number)?;
+    println!("Would download:");
+    for krate in &crates {
+        println!("- {}", crate_dir_name(krate));
+    }
+    println!("\nTotal: {}", crates.len());
+    if !duplicates.is_empty() {
+        println!("\nSkipped as listed twice:");
+        for name in &duplicates {
+            println!("- {name}");
+        }
+    }
+    Ok(())
+}
+
+struct TopCrates {
+    crates: Vec<Crate>,
+    // The names of the crates skipped for being listed a second time
+    duplicates: Vec<String>,
+}
+
+// List the `number` most downloaded crates. If the listing fails part way the crates listed so far
+// are used.
+fn list_top_crates(api_url: &str, exclude: &[String], number: usize) -> Result<TopCrates> {
+    let mut crates = Vec::with_capacity(number);
+    let mut duplicates = vec![];
+    let mut listed_names = BTreeSet::new();
+    for krate in list_crates(api_url, exclude) {
+        let krate = match krate {
+            Ok(krate) => krate,
+            // Nothing can be downloaded without a listing.
+            Err(err) if crates.is_empty() => return Err(err),
+            Err(err) => {
+                println!(
+                    "error: Listing stopped after {} crates - {:#}",
+                    crates.len(),
+                    err
+                );
+                break;
+            }
+        };
+        if !listed_names.insert(krate.name.clone()) {
+            println!(
+                "Skipping '{}'. Listed twice by crates.io. (Possibly the changed position during listing.)",
+                &krate.name
+            );
+            duplicates.push(krate.name);
+            continue;
+        }
+        crates.push(krate);
+        if crates.len() == number {
+            break;
+        }
+    }
+    Ok(TopCrates { crates, duplicates })
+}
+
+fn list_crates<'a>(
+    api_url: &'a str,
+    exclude: &'a [String],
+) -> impl Iterator<Item = Result<Crate>> + 'a {
     // We're using crates.io API.
     // We need to conform to https://crates.io/policies#crawlers.
 
@@ -166,8 +765,8 @@ fn list_crates(exclude: &[String]) -> impl Iterator<Item = Result<Crate>> + '_ {
                 }
             }
             let url = format!(
-                "https://crates.io/api/v1/crates?page={}&per_page={}&sort=downloads",
-                page_num, CRATES_IO_MAX_PER_PAGE,
+                "{}/crates?page={}&per_page={}&sort=downloads",
+                api_url, page_num, CRATES_IO_MAX_PER_PAGE,
             );
             let response = agent.get(&url).call().context("Failed to get crate page");
             last_request_time = Some(Instant::now());
@@ -196,24 +795,175 @@ fn list_crates(exclude: &[String]) -> impl Iterator<Item = Result<Crate>> + '_ {
         })
 }
 
-fn download_crate(agent: &mut Agent, krate: &Crate, path: &Path) -> Result<()> {
-    let reader = agent
-        .get(&format!(
-            "https://static.crates.io/crates/{name}/{name}-{version}.crate",
-            name = krate.name,
-            version = krate.version(),
-        ))
-        .call()
-        .with_context(|| format!("Failed to download crate '{}'", krate.name))?
-        .into_reader();
+// The per-user cache dir following the platform's conventions.
+fn default_cache_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| Path::new(&home).join("Library").join("Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    base.map(|base| base.join("clippy_lint_tester").join("crates"))
+}
+
+// Unpack the crate archive into `path`. The archive is taken from `cache_dir` if a copy
+// matching the registry's checksum is there, otherwise it's downloaded, checked and stored in
+// `cache_dir` first.
+fn download_crate(
+    agent: &Agent,
+    krate: &Crate,
+    static_url: &str,
+    index_url: &str,
+    cache_dir: &Path,
+    path: &Path,
+) -> Result<PathBuf, DownloadError> {
+    let archive_name = format!("{}-{}.crate", krate.name, krate.version());
+    let cache_path = cache_dir.join(&archive_name);
+    let checksum = match &krate.checksum {
+        Some(checksum) => checksum.clone(),
+        None => index_checksum(agent, index_url, krate)?,
+    };
 
-    let decoder = GzDecoder::new(reader);
+    let bytes = match fs::read(&cache_path) {
+        Ok(bytes) if sha256_hex(&bytes) == checksum => bytes,
+        _ => {
+            let mut bytes = vec![];
+            let response = agent
+                .get(&format!(
+                    "{static_url}/crates/{name}/{archive_name}",
+                    name = krate.name,
+                ))
+                .call();
+            let kind = match &response {
+                Err(ureq::Error::Status(404, _)) => FailureKind::NotFound,
+                _ => FailureKind::Network,
+            };
+            response
+                .map_err(anyhow::Error::from)
+                .and_then(|response| Ok(response.into_reader().read_to_end(&mut bytes)?))
+                .with_context(|| format!("Failed to download crate '{}'", krate.name))
+                .failure_kind(kind)?;
+            let actual = sha256_hex(&bytes);
+            if actual != checksum {
+                return Err(anyhow!(
+                    "Downloaded crate '{}' doesn't match the registry checksum: expected {}, got {}",
+                    krate.name,
+                    checksum,
+                    actual
+                ))
+                .failure_kind(FailureKind::Checksum);
+            }
+
+            // Written to a temporary file first so that an interrupted write can't leave a
+            // partial archive in the cache.
+            let temp_path = cache_dir.join(format!("{}.{}.part", archive_name, process::id()));
+            fs::write(&temp_path, &bytes)
+                .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+            fs::rename(&temp_path, &cache_path)
+                .with_context(|| format!("Failed to write {}", cache_path.display()))?;
+            bytes
+        }
+    };
 
-    let mut archive = Archive::new(decoder);
+    let root = archive_root(&bytes)
+        .with_context(|| format!("Unexpected layout of crate '{}'", krate.name))?;
+    let mut archive = Archive::new(GzDecoder::new(&bytes[..]));
     archive.set_overwrite(false);
     archive
         .unpack(path)
-        .with_context(|| format!("Failed to unpack crate '{}'", krate.name))
+        .with_context(|| format!("Failed to unpack crate '{}'", krate.name))?;
+    Ok(path.join(root))
+}
+
+// The directory the archive unpacks to. Every entry must be inside the same one.
+fn archive_root(bytes: &[u8]) -> Result<PathBuf> {
+    let mut roots = BTreeSet::new();
+    let mut archive = Archive::new(GzDecoder::new(bytes));
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?;
+        let mut components = path.components();
+        let Some(Component::Normal(root)) = components.next() else {
+            bail!("entry `{}` is outside of a directory", path.display());
+        };
+        if components.next().is_none() && !entry.header().entry_type().is_dir() {
+            bail!("entry `{}` is outside of a directory", path.display());
+        }
+        roots.insert(PathBuf::from(root));
+    }
+    match roots.len() {
+        0 => bail!("the archive is empty"),
+        1 => Ok(roots.into_iter().next().expect("one root")),
+        _ => bail!(
+            "the archive has multiple top-level directories: {}",
+            roots
+                .iter()
+                .map(|root| format!("`{}`", root.display()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct IndexEntry {
+    vers: String,
+    cksum: String,
+}
+
+// The SHA-256 of the crate's archive from its file in the sparse index. The file has one JSON
+// entry per published version.
+fn index_checksum(agent: &Agent, index_url: &str, krate: &Crate) -> Result<String, DownloadError> {
+    let response = agent
+        .get(&format!("{index_url}/{}", index_path(&krate.name)))
+        .call();
+    let kind = match &response {
+        Err(ureq::Error::Status(404, _)) => FailureKind::NotFound,
+        _ => FailureKind::Network,
+    };
+    let entries = response
+        .map_err(anyhow::Error::from)
+        .and_then(|response| Ok(response.into_string()?))
+        .with_context(|| format!("Failed to get the index entry of crate '{}'", krate.name))
+        .failure_kind(kind)?;
+    for line in entries.lines().filter(|line| !line.trim().is_empty()) {
+        let entry: IndexEntry = serde_json::from_str(line)
+            .with_context(|| format!("Failed to parse the index entry of crate '{}'", krate.name))
+            .failure_kind(FailureKind::Network)?;
+        if entry.vers == krate.version() {
+            return Ok(entry.cksum);
+        }
+    }
+    Err(anyhow!(
+        "Version {} of crate '{}' isn't in the index",
+        krate.version(),
+        krate.name
+    ))
+    .failure_kind(FailureKind::NotFound)
+}
+
+// The path of a crate's file in the index, e.g. `se/rd/serde` or `3/s/syn`.
+fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{name}"),
+        2 => format!("2/{name}"),
+        3 => format!("3/{}/{name}", &name[..1]),
+        _ => format!("{}/{}/{name}", &name[..2], &name[2..4]),
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            write!(hex, "{byte:02x}").expect("Write to string succeeds");
+            hex
+        })
 }
 
 fn remove_cargo_config(crate_path: &Path) -> Result<()> {
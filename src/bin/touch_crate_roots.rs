@@ -2,19 +2,32 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use argh::FromArgs;
-use clippy_lint_tester::touch_crate_roots;
+use clippy_lint_tester::{touch_crate_roots_with_extra_roots, DEFAULT_MANIFEST_NAME};
 
 #[derive(FromArgs)]
 /// Touch crate roots to force recompilation
 struct Args {
     #[argh(positional)]
     target: PathBuf,
+
+    #[argh(option, default = "PathBuf::from(DEFAULT_MANIFEST_NAME)")]
+    /// the path of the manifest relative to the crate's dir (default: `Cargo.toml`)
+    manifest_name: PathBuf,
+
+    #[argh(option)]
+    /// another file to touch if it exists, relative to the manifest's dir (e.g. `lib.rs`). The
+    /// defaults are `src/lib.rs`, `src/main.rs` and those declared in the manifest
+    extra_root: Vec<PathBuf>,
 }
 
 fn main() -> Result<()> {
-    let Args { target } = argh::from_env();
+    let Args {
+        target,
+        manifest_name,
+        extra_root,
+    } = argh::from_env();
 
-    touch_crate_roots(&target)?;
+    touch_crate_roots_with_extra_roots(&target, &manifest_name, &extra_root)?;
 
     Ok(())
 }
@@ -1,7 +1,8 @@
 use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::fs;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use anyhow::{bail, Context, Result};
@@ -17,23 +18,120 @@ struct RustToolchain {
     channel: String,
 }
 
+// The part of the output of `cargo metadata` that's used.
+#[derive(Debug, Deserialize)]
+struct CargoMetadata {
+    target_directory: PathBuf,
+}
+
 pub struct ClippyWorkspace {
     // The toolchain arg (e.g. +nightly-2021-03-25)
     toolchain_arg: OsString,
-    // The manifest arg (e.g. --manifest-path=/home/mike/projects/rust-clippy/Cargo.toml)
-    manifest_arg: OsString,
+    // The manifest arg (e.g. --manifest-path=/home/mike/projects/rust-clippy/Cargo.toml). `None`
+    // when using the Clippy installed with the toolchain.
+    manifest_arg: Option<OsString>,
+    // The directory Clippy's binaries are built in (e.g.
+    // /home/mike/projects/rust-clippy/target/release). `None` when using the Clippy installed with
+    // the toolchain.
+    release_dir: Option<PathBuf>,
+    // The toolchain's name (e.g. nightly-2021-03-25)
+    toolchain: String,
+    options: ClippyBuildOptions,
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct ClippyBuildOptions {
+    // Pass `--offline` to every Cargo command so that nothing is downloaded.
+    pub offline: bool,
+    // The number of times to retry building Clippy after a network failure.
+    pub build_retries: usize,
+    // Return a `ClippyBuildFailed` error when building Clippy fails so that the caller can skip the
+    // workspace and carry on with others.
+    pub keep_going: bool,
+}
+
+// A Clippy workspace that couldn't be built. Only returned with `ClippyBuildOptions::keep_going`,
+// get it with `anyhow::Error::downcast_ref`.
+#[derive(Debug)]
+pub struct ClippyBuildFailed {
+    pub clippy_source: PathBuf,
+    pub stderr: String,
+}
+
+impl fmt::Display for ClippyBuildFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to build Clippy in `{}`\nstderr: {}",
+            self.clippy_source.display(),
+            self.stderr
+        )
+    }
+}
+
+impl std::error::Error for ClippyBuildFailed {}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum ClippyBin {
     CargoClippy,
     ClippyDriver,
 }
 
-// Builds clippy in release mode and ensure that it works.
+impl ClippyBin {
+    fn name(self) -> &'static str {
+        match self {
+            ClippyBin::CargoClippy => "cargo-clippy",
+            ClippyBin::ClippyDriver => "clippy-driver",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+    Forbid,
+}
+
+impl LintLevel {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "allow" => Some(LintLevel::Allow),
+            "warn" => Some(LintLevel::Warn),
+            "deny" => Some(LintLevel::Deny),
+            "forbid" => Some(LintLevel::Forbid),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LintLevel::Allow => "allow",
+            LintLevel::Warn => "warn",
+            LintLevel::Deny => "deny",
+            LintLevel::Forbid => "forbid",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LintInfo {
+    // The lint name with underscores (e.g. clippy::approx_constant)
+    pub name: String,
+    // The lint group other than `clippy::all` (e.g. clippy::correctness)
+    pub group: Option<String>,
+    pub default_level: LintLevel,
+}
+
+// Builds clippy in release mode and ensure that it works. `retry_callback` is called before the
+// build is retried after a network failure.
 pub fn prepare_clippy(
     clippy_source: &Path,
+    options: &ClippyBuildOptions,
     pre_compile_callback: impl Fn(),
+    retry_callback: impl Fn(),
 ) -> Result<ClippyWorkspace> {
     assert!(
         clippy_source.is_absolute(),
@@ -62,54 +160,606 @@ pub fn prepare_clippy(
     let toolchain_file: RustToolchainFile =
         toml::from_str(&toolchain_contents).context("Parsing rust-toolchain toml")?;
 
+    let toolchain = toolchain_file.toolchain.channel;
     let mut toolchain_arg: OsString = "+".into();
-    toolchain_arg.push(toolchain_file.toolchain.channel);
+    toolchain_arg.push(&toolchain);
 
     let mut manifest_arg: OsString = "--manifest-path=".into();
     manifest_arg.push(clippy_source.join("Cargo.toml"));
 
+    let mut workspace = ClippyWorkspace {
+        toolchain_arg,
+        manifest_arg: Some(manifest_arg),
+        // Set once Clippy is built.
+        release_dir: None,
+        toolchain,
+        options: options.clone(),
+    };
+
     pre_compile_callback();
 
-    let output = Command::new("cargo")
-        .arg(&toolchain_arg)
-        .arg("build")
-        .arg(&manifest_arg)
-        .arg("--release")
+    let mut retries_remaining = options.build_retries;
+    loop {
+        // Run in the workspace so that its Cargo config (e.g. `build.target-dir`) applies.
+        let output = workspace
+            .make_build_command()
+            .expect("built from source")
+            .current_dir(clippy_source)
+            .output()
+            .expect("command succeeds");
+
+        if output.status.success() {
+            workspace.release_dir = Some(target_dir(&workspace, clippy_source)?.join("release"));
+            return Ok(workspace);
+        }
+
+        let stderr =
+            std::str::from_utf8(&output.stderr).context("Converting Cargo output to str")?;
+        if retries_remaining > 0 && is_network_build_failure(stderr) {
+            retries_remaining -= 1;
+            retry_callback();
+            continue;
+        }
+        if options.keep_going {
+            return Err(ClippyBuildFailed {
+                clippy_source: clippy_source.to_owned(),
+                stderr: stderr.into(),
+            }
+            .into());
+        }
+        bail!("Failed to build Clippy\nstderr: {}", stderr);
+    }
+}
+
+// Cargo errors caused by failing to fetch Clippy's dependencies rather than by Clippy itself.
+// These are worth retrying.
+const NETWORK_BUILD_ERRORS: &[&str] = &[
+    "spurious network error",
+    "failed to download",
+    "failed to fetch",
+    "failed to update registry",
+    "failed to get `",
+    "failed to query replaced source registry",
+    "Couldn't resolve host",
+    "Connection reset",
+    "Connection refused",
+    "Operation timed out",
+    "SSL connect error",
+];
+
+fn is_network_build_failure(errors: &str) -> bool {
+    // Genuine compile errors are never retried, even if network noise is also present.
+    !errors.contains("error[E")
+        && !errors.contains("error: could not compile")
+        && NETWORK_BUILD_ERRORS.iter().any(|e| errors.contains(e))
+}
+
+// Uses the Clippy installed with a rustup toolchain (e.g. `stable`) rather than building it, and
+// ensure that it works.
+pub fn installed_clippy(toolchain: &str, options: &ClippyBuildOptions) -> Result<ClippyWorkspace> {
+    let mut toolchain_arg: OsString = "+".into();
+    toolchain_arg.push(toolchain);
+
+    let workspace = ClippyWorkspace {
+        toolchain_arg,
+        manifest_arg: None,
+        release_dir: None,
+        toolchain: toolchain.into(),
+        options: options.clone(),
+    };
+
+    let output = workspace
+        .make_clippy_command(ClippyBin::CargoClippy)
+        .arg("--version")
         .output()
-        .expect("command succeeds");
+        .context("Running Cargo")?;
 
     if !output.status.success() {
         bail!(
-            "Failed to build Clippy\nstderr: {}",
-            std::str::from_utf8(&output.stderr).context("Converting Cargo output to str")?
+            "Clippy isn't installed for toolchain `{toolchain}` - install it with `rustup \
+            component add clippy --toolchain {toolchain}`\nstderr: {}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
         );
     }
 
-    Ok(ClippyWorkspace {
-        toolchain_arg,
-        manifest_arg,
-    })
+    Ok(workspace)
 }
 
 impl ClippyWorkspace {
+    // Lists the lints provided by Clippy by parsing the output of `clippy-driver -W help`. The
+    // command runs through `cargo run` when Clippy is built from source, which can fail
+    // transiently (e.g. waiting on a lock) so it's tried twice.
+    pub fn available_lints(&self) -> Result<Vec<LintInfo>> {
+        let mut attempts_remaining = LIST_LINTS_ATTEMPTS;
+        let output = loop {
+            attempts_remaining -= 1;
+            let output = self
+                .make_clippy_command(ClippyBin::ClippyDriver)
+                .arg("-W")
+                .arg("help")
+                .output()
+                .context("Running Clippy driver help")?;
+            if output.status.success() || attempts_remaining == 0 {
+                break output;
+            }
+        };
+
+        if !output.status.success() {
+            bail!(
+                "Command to list lints failed after {LIST_LINTS_ATTEMPTS} attempts\nstderr: {}",
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+        }
+
+        let stdout =
+            std::str::from_utf8(&output.stdout).context("Converting Cargo output to str")?;
+        parse_lint_help(stdout)
+    }
+
+    // The version reported by `clippy-driver --version` (e.g. `clippy 0.1.60 (0abcdef 2022-01-01)`).
+    pub fn version(&self) -> Result<String> {
+        let output = self
+            .make_clippy_command(ClippyBin::ClippyDriver)
+            .arg("--version")
+            .output()
+            .context("Running Clippy driver")?;
+
+        if !output.status.success() {
+            bail!(
+                "Command to get the Clippy version failed\nstderr: {}",
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+        }
+
+        let stdout = String::from_utf8(output.stdout).context("Converting Clippy output to str")?;
+        Ok(stdout.trim().into())
+    }
+
+    // The command used to build Clippy in release mode. `None` when using an installed Clippy as
+    // there's nothing to build.
+    #[must_use]
+    pub fn make_build_command(&self) -> Option<Command> {
+        let manifest_arg = self.manifest_arg.as_ref()?;
+        let mut command = Command::new("cargo");
+        command
+            .arg(&self.toolchain_arg)
+            .arg("build")
+            .arg(manifest_arg)
+            .arg("--release");
+        if self.options.offline {
+            command.arg("--offline");
+        }
+        Some(command)
+    }
+
+    // A Cargo command using Clippy's toolchain (e.g. `cargo +nightly generate-lockfile`).
+    #[must_use]
+    pub fn make_cargo_command(&self, subcommand: &str) -> Command {
+        let mut command = Command::new("cargo");
+        command.arg(&self.toolchain_arg).arg(subcommand);
+        if self.options.offline {
+            command.arg("--offline");
+        }
+        command
+    }
+
     #[must_use]
     pub fn make_clippy_command(&self, bin: ClippyBin) -> Command {
+        let Some(manifest_arg) = &self.manifest_arg else {
+            return match bin {
+                ClippyBin::CargoClippy => {
+                    let mut command = Command::new("cargo");
+                    command.arg(&self.toolchain_arg).arg("clippy");
+                    command
+                }
+                ClippyBin::ClippyDriver => {
+                    let mut command = Command::new("rustup");
+                    command.arg("run").arg(&self.toolchain).arg("clippy-driver");
+                    command
+                }
+            };
+        };
+
         let mut command = Command::new("cargo");
         let cargo_run_args: &[&OsStr] = &[
             &self.toolchain_arg,
             "--quiet".as_ref(),
             "run".as_ref(),
-            &self.manifest_arg,
+            manifest_arg,
             "--release".as_ref(),
-            "--bin".as_ref(),
-            match bin {
-                ClippyBin::CargoClippy => "cargo-clippy",
-                ClippyBin::ClippyDriver => "clippy-driver",
-            }
-            .as_ref(),
-            "--".as_ref(), // end cargo run args
         ];
         command.args(cargo_run_args);
+        if self.options.offline {
+            command.arg("--offline");
+        }
+        command.arg("--bin").arg(bin.name()).arg("--"); // end cargo run args
+        command
+    }
+
+    // The absolute path to the `clippy-driver` binary, for running it directly rather than through
+    // `cargo run` (e.g. as `RUSTC_WORKSPACE_WRAPPER`).
+    pub fn clippy_driver_path(&self) -> Result<PathBuf> {
+        self.bin_path(ClippyBin::ClippyDriver)
+    }
+
+    // The absolute path to the `cargo-clippy` binary.
+    pub fn cargo_clippy_path(&self) -> Result<PathBuf> {
+        self.bin_path(ClippyBin::CargoClippy)
+    }
+
+    // The path of a binary built by `prepare_clippy` or, for an installed Clippy, the one in the
+    // toolchain. Fails if it doesn't exist.
+    pub fn bin_path(&self, bin: ClippyBin) -> Result<PathBuf> {
+        let path = if let Some(release_dir) = &self.release_dir {
+            let mut file_name = OsString::from(bin.name());
+            file_name.push(std::env::consts::EXE_SUFFIX);
+            let path = release_dir.join(file_name);
+            if !path.is_file() {
+                bail!(
+                    "`{}` doesn't exist - has Clippy been built?",
+                    path.display()
+                );
+            }
+            path
+        } else {
+            let output = Command::new("rustup")
+                .arg("which")
+                .arg("--toolchain")
+                .arg(&self.toolchain)
+                .arg(bin.name())
+                .output()
+                .context("Running rustup")?;
+            if !output.status.success() {
+                bail!(
+                    "Couldn't find `{}` for toolchain `{}`\nstderr: {}",
+                    bin.name(),
+                    self.toolchain,
+                    String::from_utf8_lossy(&output.stderr).trim_end()
+                );
+            }
+            let stdout =
+                String::from_utf8(output.stdout).context("Converting rustup output to str")?;
+            PathBuf::from(stdout.trim_end())
+        };
+        path.canonicalize()
+            .with_context(|| format!("Resolving `{}`", path.display()))
+    }
+
+    // A `cargo-clippy` command ready for Cargo args to be added.
+    #[must_use]
+    pub fn make_cargo_clippy_command(&self) -> Command {
+        let mut command = self.make_clippy_command(ClippyBin::CargoClippy);
+        if self.manifest_arg.is_some() {
+            command.arg("--");
+        }
+        if self.options.offline {
+            command.arg("--offline");
+        }
         command
     }
 }
+
+// The directory Cargo builds Clippy in, as reported by `cargo metadata`. It can be moved from
+// `target` by `CARGO_TARGET_DIR` or by `build.target-dir` in a Cargo config.
+fn target_dir(workspace: &ClippyWorkspace, clippy_source: &Path) -> Result<PathBuf> {
+    let output = workspace
+        .make_cargo_command("metadata")
+        .arg(workspace.manifest_arg.as_ref().expect("built from source"))
+        .args(["--no-deps", "--format-version", "1"])
+        .current_dir(clippy_source)
+        .output()
+        .context("Running cargo metadata")?;
+    if !output.status.success() {
+        bail!(
+            "Failed to find Clippy's target directory\nstderr: {}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    let metadata: CargoMetadata =
+        serde_json::from_slice(&output.stdout).context("Parsing cargo metadata output")?;
+    Ok(metadata.target_directory)
+}
+
+// Whether a diagnostic's code is the lint. Clippy reports its lints as `clippy::lint_name` and the
+// lint names are normally in the same form, but the lint may be missing the prefix. The lint must
+// already use underscores rather than dashes, as the names from `available_lints` do.
+// A code without the prefix never matches a Clippy lint as it's a rustc lint, which can have the
+// same name as a Clippy lint that was uplifted (e.g. `invalid_atomic_ordering`).
+#[must_use]
+pub fn lint_code_matches(code: &str, lint: &str) -> bool {
+    code == lint || code.strip_prefix("clippy::") == Some(lint)
+}
+
+const LIST_LINTS_ATTEMPTS: usize = 2;
+
+// Newer versions of Clippy say `loaded by this crate` rather than `provided by plugins loaded by
+// this crate`.
+fn is_lints_heading(line: &str) -> bool {
+    line.starts_with("Lint checks provided by plugins")
+        || line.starts_with("Lint checks loaded by this crate")
+}
+
+fn is_groups_heading(line: &str) -> bool {
+    line.starts_with("Lint groups provided by plugins")
+        || line.starts_with("Lint groups loaded by this crate")
+}
+
+// Errors if there's no list of Clippy's lints rather than returning no lints, as that would report
+// every lint as not found when the format of the help changes.
+fn parse_lint_help(help: &str) -> Result<Vec<LintInfo>> {
+    if !help.lines().any(is_lints_heading) {
+        bail!(
+            "Couldn't find Clippy's lints in the output of `clippy-driver -W help` - expected a \
+            line starting with `Lint checks provided by plugins` or `Lint checks loaded by this \
+            crate`\noutput: {}",
+            help.trim_end()
+        );
+    }
+
+    let mut lints: Vec<LintInfo> = help
+        .lines()
+        .skip_while(|l| !is_lints_heading(l))
+        .skip(1)
+        .take_while(|l| !is_groups_heading(l))
+        .filter_map(|l| {
+            let mut words = l.split_whitespace();
+            let name = words.next()?;
+            let default_level = LintLevel::parse(words.next()?)?;
+            Some(LintInfo {
+                name: name.replace('-', "_"),
+                group: None,
+                default_level,
+            })
+        })
+        .collect();
+    if lints.is_empty() {
+        bail!(
+            "Couldn't parse any of Clippy's lints in the output of `clippy-driver -W help`\n\
+            output: {}",
+            help.trim_end()
+        );
+    }
+
+    for line in help.lines().skip_while(|l| !is_groups_heading(l)).skip(1) {
+        let line = line.trim_start();
+        let (group, sub_lints) = match line.split_once(char::is_whitespace) {
+            Some((group, sub_lints)) if group.contains("::") => (group, sub_lints),
+            _ => continue,
+        };
+        let group = group.replace('-', "_");
+        if group == "clippy::all" {
+            continue;
+        }
+
+        for sub_lint in sub_lints.split(',') {
+            let sub_lint = sub_lint.trim().replace('-', "_");
+            if let Some(lint) = lints.iter_mut().find(|l| l.name == sub_lint) {
+                lint.group.get_or_insert_with(|| group.clone());
+            }
+        }
+    }
+
+    Ok(lints)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{
+        lint_code_matches, parse_lint_help, prepare_clippy, target_dir, ClippyBuildFailed,
+        ClippyBuildOptions, ClippyWorkspace, LintInfo, LintLevel,
+    };
+
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use indoc::indoc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn parse_driver_lint_help() {
+        let help = indoc! {"
+
+            Available lint options:
+                -W <foo>           Warn about <foo>
+
+            Lint checks provided by rustc:
+
+                                                          name  default  meaning
+                                                          ----  -------  -------
+                                      absolute-paths-not-starting-with-crate  allow    fully qualified paths that start with a module name
+
+            Lint checks provided by plugins loaded by this crate:
+
+                                                          name  default  meaning
+                                                          ----  -------  -------
+                          clippy::absurd-extreme-comparisons  deny     checks for comparisons where one side of the relation is...
+                                     clippy::approx-constant  deny     the approximate of a known float constant (in `std::fmt::...
+                                      clippy::needless-return  warn     using a return statement like `return expr;` where an ex...
+                                       clippy::dbg-macro  allow    `dbg!` macro is intended as a debugging tool
+
+            Lint groups provided by plugins loaded by this crate:
+
+                                name  sub-lints
+                                ----  ---------
+                         clippy::all  clippy::absurd-extreme-comparisons, clippy::approx-constant, clippy::needless-return
+                 clippy::correctness  clippy::absurd-extreme-comparisons, clippy::approx-constant
+                       clippy::style  clippy::needless-return
+                 clippy::restriction  clippy::dbg-macro
+
+        "};
+
+        let lint = |name: &str, group: &str, default_level| LintInfo {
+            name: name.into(),
+            group: Some(group.into()),
+            default_level,
+        };
+
+        assert_eq!(
+            parse_lint_help(help).unwrap(),
+            vec![
+                lint(
+                    "clippy::absurd_extreme_comparisons",
+                    "clippy::correctness",
+                    LintLevel::Deny
+                ),
+                lint(
+                    "clippy::approx_constant",
+                    "clippy::correctness",
+                    LintLevel::Deny
+                ),
+                lint("clippy::needless_return", "clippy::style", LintLevel::Warn),
+                lint("clippy::dbg_macro", "clippy::restriction", LintLevel::Allow),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_malformed_lint_help() {
+        // A renamed heading
+        let help = indoc! {"
+            Lints from this crate:
+
+                clippy::approx-constant  deny  the approximate of a known float constant
+        "};
+        let err = parse_lint_help(help).unwrap_err().to_string();
+        assert!(
+            err.starts_with(
+                "Couldn't find Clippy's lints in the output of `clippy-driver -W help`"
+            ),
+            "{err}"
+        );
+        assert!(err
+            .ends_with("clippy::approx-constant  deny  the approximate of a known float constant"));
+
+        // Lints without a default level
+        let help = indoc! {"
+            Lint checks loaded by this crate:
+
+                clippy::approx-constant  the approximate of a known float constant
+        "};
+        let err = parse_lint_help(help).unwrap_err().to_string();
+        assert!(
+            err.starts_with("Couldn't parse any of Clippy's lints"),
+            "{err}"
+        );
+
+        assert!(parse_lint_help("").is_err());
+    }
+
+    #[test]
+    fn match_lint_codes() {
+        assert!(lint_code_matches(
+            "clippy::approx_constant",
+            "clippy::approx_constant"
+        ));
+        assert!(lint_code_matches(
+            "clippy::approx_constant",
+            "approx_constant"
+        ));
+        assert!(lint_code_matches("unused_variables", "unused_variables"));
+
+        assert!(!lint_code_matches(
+            "clippy::approx_constant",
+            "clippy::needless_return"
+        ));
+        assert!(!lint_code_matches("clippy::approx_constant", "approx"));
+        assert!(!lint_code_matches(
+            "invalid_atomic_ordering",
+            "clippy::invalid_atomic_ordering"
+        ));
+        assert!(!lint_code_matches("E0425", "clippy::E0425"));
+    }
+
+    #[test]
+    fn built_bin_paths() {
+        let dir = tempdir().unwrap();
+        let release_dir = dir.path().join("target/release");
+        let workspace = ClippyWorkspace {
+            toolchain_arg: "+nightly".into(),
+            manifest_arg: Some("--manifest-path=Cargo.toml".into()),
+            release_dir: Some(release_dir.clone()),
+            toolchain: "nightly".into(),
+            options: ClippyBuildOptions::default(),
+        };
+        assert!(workspace.clippy_driver_path().is_err());
+
+        let driver = release_dir.join(format!("clippy-driver{}", std::env::consts::EXE_SUFFIX));
+        fs::create_dir_all(&release_dir).unwrap();
+        fs::write(&driver, "").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&driver, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let path = workspace.clippy_driver_path().unwrap();
+        assert!(path.is_absolute());
+        assert_eq!(path, driver.canonicalize().unwrap());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_ne!(fs::metadata(&path).unwrap().permissions().mode() & 0o111, 0);
+        }
+        assert!(workspace.cargo_clippy_path().is_err());
+    }
+
+    #[test]
+    fn configured_target_dir() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        fs::write(
+            source.join("Cargo.toml"),
+            "[package]\nname = \"clippy\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        fs::create_dir(source.join("src")).unwrap();
+        fs::write(source.join("src/lib.rs"), "").unwrap();
+        fs::create_dir(source.join(".cargo")).unwrap();
+        fs::write(
+            source.join(".cargo/config.toml"),
+            "[build]\ntarget-dir = \"out\"\n",
+        )
+        .unwrap();
+
+        let mut manifest_arg = std::ffi::OsString::from("--manifest-path=");
+        manifest_arg.push(source.join("Cargo.toml"));
+        let workspace = ClippyWorkspace {
+            toolchain_arg: "+stable".into(),
+            manifest_arg: Some(manifest_arg),
+            release_dir: None,
+            toolchain: "stable".into(),
+            options: ClippyBuildOptions::default(),
+        };
+        // `CARGO_TARGET_DIR` overrides the config.
+        let expected =
+            env::var_os("CARGO_TARGET_DIR").map_or_else(|| source.join("out"), PathBuf::from);
+        assert_eq!(target_dir(&workspace, &source).unwrap(), expected);
+    }
+
+    #[test]
+    fn keep_going_after_build_failure() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("rust-toolchain"),
+            "[toolchain]\nchannel = \"nightly-1999-01-01\"\n",
+        )
+        .unwrap();
+
+        let err = prepare_clippy(dir.path(), &ClippyBuildOptions::default(), || (), || ())
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<ClippyBuildFailed>().is_none());
+
+        let options = ClippyBuildOptions {
+            keep_going: true,
+            ..ClippyBuildOptions::default()
+        };
+        let err = prepare_clippy(dir.path(), &options, || (), || ())
+            .err()
+            .unwrap();
+        let failed = err.downcast_ref::<ClippyBuildFailed>().unwrap();
+        assert_eq!(failed.clippy_source, dir.path());
+    }
+}
@@ -0,0 +1,156 @@
+// Linting the crates of a git repository rather than a directory of downloaded crates.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use walkdir::WalkDir;
+
+// Whether the target names a git repository to clone: a URL (e.g. `https://...` or
+// `git@host:repo`) or a local bare repository. A plain local checkout isn't cloned as it may
+// already be a directory of crates - use a `file://` URL to clone one.
+#[must_use]
+pub fn is_git_target(target: &str) -> bool {
+    if target.contains("://") || target.starts_with("git@") {
+        return true;
+    }
+    let path = Path::new(target);
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+// The repository's name from its URL or path (e.g. `serde` for `https://host/serde-rs/serde.git`).
+fn repo_name(url: &str) -> &str {
+    let url = url.trim_end_matches('/');
+    let name = url.rsplit(['/', ':']).next().unwrap_or(url);
+    match name.strip_suffix(".git") {
+        Some(stem) if !stem.is_empty() => stem,
+        _ => name,
+    }
+}
+
+// A shallow clone of a git repository which is removed on drop unless kept.
+pub struct GitClone {
+    // The managed directory holding the clone
+    dir: PathBuf,
+    path: PathBuf,
+    keep: bool,
+}
+
+impl GitClone {
+    // Clones `url` at `git_ref` (a branch, tag or commit; the default branch if omitted) into a
+    // directory named after the repository inside the new directory `dir`. Only the one commit
+    // is fetched.
+    pub fn new(url: &str, git_ref: Option<&str>, dir: PathBuf) -> Result<Self> {
+        if dir.exists() {
+            bail!("Clone directory `{}` already exists", dir.display());
+        }
+        let path = dir.join(repo_name(url));
+        fs::create_dir_all(&path)
+            .with_context(|| format!("Creating clone directory `{}`", path.display()))?;
+        // Created before running git so that a failed clone is cleaned up too
+        let clone = GitClone {
+            dir,
+            path,
+            keep: false,
+        };
+
+        // Local paths are made into URLs as git ignores `--depth` for them.
+        let url = if url.contains("://") || url.starts_with("git@") {
+            url.to_owned()
+        } else {
+            let path = fs::canonicalize(url).with_context(|| format!("Resolving `{url}`"))?;
+            format!("file://{}", path.display())
+        };
+
+        // Fetching by ref rather than `git clone --branch` so that commits work as well as
+        // branches and tags.
+        clone.git(&["init", "--quiet"])?;
+        clone.git(&[
+            "fetch",
+            "--quiet",
+            "--depth",
+            "1",
+            &url,
+            git_ref.unwrap_or("HEAD"),
+        ])?;
+        clone.git(&["checkout", "--quiet", "FETCH_HEAD"])?;
+
+        Ok(clone)
+    }
+
+    fn git(&self, args: &[&str]) -> Result<()> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(&self.path)
+            .output()
+            .context("Running git")?;
+        if !output.status.success() {
+            bail!(
+                "`git {}` failed\nstderr: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+        }
+        Ok(())
+    }
+
+    // The managed directory, which holds the clone and can hold other files (e.g. a target dir)
+    // to be removed along with it.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Leaves the clone in place when dropped.
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+
+    // The sorted directories of the packages in the clone, skipping `target` dirs. Each is linted
+    // as a crate of the corpus.
+    pub fn package_paths(&self, manifest_name: &Path) -> Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        let walker = WalkDir::new(&self.path).into_iter().filter_entry(|entry| {
+            !(entry.file_type().is_dir()
+                && (entry.file_name() == ".git" || entry.file_name() == "target"))
+        });
+        for entry in walker {
+            let entry = entry.context("Walking the clone")?;
+            if !entry.file_type().is_dir() {
+                continue;
+            }
+            let manifest_path = entry.path().join(manifest_name);
+            let contents = match fs::read_to_string(&manifest_path) {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("Reading `{}`", manifest_path.display()))
+                }
+            };
+            let manifest: toml::Value = toml::from_str(&contents)
+                .with_context(|| format!("Parsing `{}`", manifest_path.display()))?;
+            // Virtual workspace manifests have no package to lint
+            if manifest.get("package").is_some() {
+                paths.push(entry.into_path());
+            }
+        }
+        paths.sort_unstable();
+        Ok(paths)
+    }
+}
+
+impl Drop for GitClone {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// Stopping a run early with Ctrl-C. The first Ctrl-C only requests a stop, which the run checks
+// between crates so that the results so far can still be reported. Child processes are run in
+// their own process group so that they aren't interrupted by it. A second Ctrl-C interrupts them,
+// and any started after it, so the run can clean up and exit without finishing the crate.
+
+use std::io;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use anyhow::{Context, Result};
+
+// The exit code when the run is stopped early (128 + SIGINT, as for a process killed by it).
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+static STOP_NOW_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// The ids of the running children, each the leader of its process group
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+// The stop requested by a Ctrl-C, passed to the handler's callback to tell the user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    AfterCurrent,
+    Now,
+}
+
+// `on_stop` is called from the handler's thread on each Ctrl-C.
+pub fn install_handler(on_stop: impl Fn(Stop) + Send + 'static) -> Result<()> {
+    ctrlc::set_handler(move || {
+        if !STOP_REQUESTED.swap(true, Ordering::SeqCst) {
+            on_stop(Stop::AfterCurrent);
+            return;
+        }
+        on_stop(Stop::Now);
+        // Set before locking so that a child spawned after the children are interrupted sees it.
+        STOP_NOW_REQUESTED.store(true, Ordering::SeqCst);
+        for &id in CHILDREN
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+        {
+            imp::interrupt_group(id);
+        }
+    })
+    .context("Installing the Ctrl-C handler")
+}
+
+#[must_use]
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::SeqCst)
+}
+
+// Whether a second Ctrl-C was pressed. The run should exit with `INTERRUPTED_EXIT_CODE` once it's
+// cleaned up.
+#[must_use]
+pub fn stop_now_requested() -> bool {
+    STOP_NOW_REQUESTED.load(Ordering::SeqCst)
+}
+
+// Removes the child from the interrupted children when dropped.
+pub struct ChildGuard(u32);
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        CHILDREN
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|&id| id != self.0);
+    }
+}
+
+// Spawns `command` in its own process group. It's interrupted by a second Ctrl-C until the guard
+// is dropped, or straight away if there's already been one.
+pub fn spawn(command: &mut Command) -> io::Result<(Child, ChildGuard)> {
+    imp::isolate(command);
+    let child = command.spawn()?;
+    let mut children = CHILDREN.lock().unwrap_or_else(PoisonError::into_inner);
+    children.push(child.id());
+    if STOP_NOW_REQUESTED.load(Ordering::SeqCst) {
+        imp::interrupt_group(child.id());
+    }
+    drop(children);
+    let guard = ChildGuard(child.id());
+    Ok((child, guard))
+}
+
+// `Command::status` for a command run with `spawn`.
+pub fn status(command: &mut Command) -> io::Result<ExitStatus> {
+    let (mut child, _guard) = spawn(command)?;
+    child.wait()
+}
+
+// `Command::output` for a command run with `spawn`.
+pub fn output(command: &mut Command) -> io::Result<Output> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let (child, _guard) = spawn(command)?;
+    child.wait_with_output()
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    pub fn isolate(command: &mut Command) {
+        command.process_group(0);
+    }
+
+    pub fn interrupt_group(id: u32) {
+        if let Ok(id) = libc::pid_t::try_from(id) {
+            // SAFETY: `kill` has no memory safety requirements.
+            unsafe {
+                libc::kill(-id, libc::SIGINT);
+            }
+        }
+    }
+}
+
+// Children share the console and are interrupted by the first Ctrl-C as well.
+#[cfg(not(unix))]
+mod imp {
+    use std::process::Command;
+
+    pub fn isolate(_command: &mut Command) {}
+
+    pub fn interrupt_group(_id: u32) {}
+}
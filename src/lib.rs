@@ -7,9 +7,11 @@
 #![allow(clippy::missing_errors_doc)]
 #![allow(clippy::missing_panics_doc)]
 
+use std::fmt::Write;
 use std::fs;
-use std::io;
+use std::io::{self, Write as _};
 use std::path::{Path, PathBuf};
+use std::process::Command;
 
 use anyhow::{bail, Context, Result};
 use filetime::{set_file_mtime, FileTime};
@@ -20,17 +22,31 @@ use walkdir::WalkDir;
 
 pub mod attr_cleaning;
 pub mod clippy_workspace;
+pub mod git_target;
+pub mod interrupt;
 pub mod markdown_formatting;
 pub mod progress_bar;
+pub mod resource_usage;
+pub mod wrapping;
 
-use attr_cleaning::{clean_source, CleanError};
+use attr_cleaning::{
+    clean_source_detailed_with_options, CleanError, CleanOptions, CleanedAttr, CleanedSource,
+};
 
-pub use progress_bar::ProgressBar;
+pub use progress_bar::{ProgressBar, ProgressMode, ProgressTemplate};
+
+// The manifest file name Cargo looks for in a crate's dir.
+pub const DEFAULT_MANIFEST_NAME: &str = "Cargo.toml";
+
+// The crate roots Cargo finds by convention, relative to the manifest's dir.
+pub const DEFAULT_CRATE_ROOTS: [&str; 2] = ["src/lib.rs", "src/main.rs"];
 
 pub enum EnsureEmptyDirOutcome {
     Created,
     Empty,
     NonEmpty,
+    // The dir's contents were removed.
+    Cleared,
 }
 
 pub fn ensure_empty_dir(path: &Path) -> Result<EnsureEmptyDirOutcome> {
@@ -51,6 +67,38 @@ pub fn ensure_empty_dir(path: &Path) -> Result<EnsureEmptyDirOutcome> {
     }
 }
 
+// Like `ensure_empty_dir` but removes the contents of a non-empty dir when `force` is set. Refuses to
+// clear the root, the home dir or a dir containing the current dir.
+pub fn ensure_empty_dir_or_clear(path: &Path, force: bool) -> Result<EnsureEmptyDirOutcome> {
+    let outcome = ensure_empty_dir(path)?;
+    if !force || !matches!(outcome, EnsureEmptyDirOutcome::NonEmpty) {
+        return Ok(outcome);
+    }
+
+    let path = fs::canonicalize(path).with_context(|| format!("Resolving {}", path.display()))?;
+    let home = std::env::var_os("HOME").and_then(|home| fs::canonicalize(home).ok());
+    let current_dir = std::env::current_dir().and_then(fs::canonicalize).ok();
+    if path.parent().is_none()
+        || home.as_deref() == Some(&path)
+        || current_dir.is_some_and(|dir| dir.starts_with(&path))
+    {
+        bail!("Refusing to clear {}", path.display());
+    }
+
+    for entry in fs::read_dir(&path).with_context(|| format!("Reading {}", path.display()))? {
+        let entry = entry.with_context(|| format!("Reading {}", path.display()))?;
+        let entry_path = entry.path();
+        // Symlinks are removed rather than followed so nothing outside of `path` is touched.
+        let result = if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(&entry_path)
+        } else {
+            fs::remove_file(&entry_path)
+        };
+        result.with_context(|| format!("Removing {}", entry_path.display()))?;
+    }
+    Ok(EnsureEmptyDirOutcome::Cleared)
+}
+
 pub struct FileCleanError {
     pub path: PathBuf,
     pub error: CleanError,
@@ -58,55 +106,144 @@ pub struct FileCleanError {
 
 // Remove all attrs from all source files that could affect linting.
 pub fn clean_attrs(path: &Path) -> Result<Vec<FileCleanError>> {
+    clean_attrs_with_options(path, &CleanOptions::default())
+}
+
+pub fn clean_attrs_with_options(
+    path: &Path,
+    options: &CleanOptions,
+) -> Result<Vec<FileCleanError>> {
+    clean_attrs_with_report(path, options).map(|(errors, _)| errors)
+}
+
+// The attributes cleaned from a file.
+pub struct CleanedFile {
+    pub path: PathBuf,
+    pub attrs: Vec<CleanedAttr>,
+}
+
+// Like `clean_attrs_with_options` but also returns the attributes cleaned from each file that had
+// any, sorted by path.
+pub fn clean_attrs_with_report(
+    path: &Path,
+    options: &CleanOptions,
+) -> Result<(Vec<FileCleanError>, Vec<CleanedFile>)> {
+    clean_attrs_in_place(path, options, true)
+}
+
+// Like `clean_attrs_with_report` but `path` is left untouched. It's copied to `destination`, which
+// must not exist, and the copy is cleaned. No `.orig` backups are written as `path` is the
+// original. Symlinks aren't copied, as with `copy_dir`.
+pub fn clean_attrs_into(
+    path: &Path,
+    destination: &Path,
+    options: &CleanOptions,
+) -> Result<(Vec<FileCleanError>, Vec<CleanedFile>)> {
+    if fs::symlink_metadata(destination).is_ok() {
+        bail!("{} already exists", destination.display());
+    }
     if path.is_file() {
-        clean_attrs_file(path).map(|result| {
-            result
-                .map(|err| FileCleanError {
-                    path: path.to_path_buf(),
-                    error: err,
-                })
-                .into_iter()
-                .collect()
-        })
+        fs::copy(path, destination)
+            .with_context(|| format!("Copying {}", destination.display()))?;
     } else if path.is_dir() {
-        clean_attrs_dir(path)
+        copy_dir(path, destination)?;
     } else {
         bail!("Path not file or dir");
     }
+    clean_attrs_in_place(destination, options, false)
 }
 
-// path must be for a dir
-fn clean_attrs_dir(path: &Path) -> Result<Vec<FileCleanError>> {
+fn clean_attrs_in_place(
+    path: &Path,
+    options: &CleanOptions,
+    backup: bool,
+) -> Result<(Vec<FileCleanError>, Vec<CleanedFile>)> {
     let mut errors = vec![];
-    for entry in WalkDir::new(path) {
+    let mut cleaned = vec![];
+    let mut record = |path: &Path, result: Result<Vec<CleanedAttr>, CleanError>| match result {
+        Ok(attrs) if attrs.is_empty() => {}
+        Ok(attrs) => cleaned.push(CleanedFile {
+            path: path.to_path_buf(),
+            attrs,
+        }),
+        Err(error) => errors.push(FileCleanError {
+            path: path.to_path_buf(),
+            error,
+        }),
+    };
+
+    if path.is_file() {
+        record(path, clean_attrs_file(path, options, backup)?);
+    } else if path.is_dir() {
+        // Symlinks are never followed so that cleaning can't modify files outside of `path`.
+        for entry in WalkDir::new(path).follow_links(false) {
+            let entry = entry.with_context(|| format!("Reading {}", path.display()))?;
+            let file_type = entry.file_type();
+            if file_type.is_file() && entry.path().extension().map_or(false, |e| e == "rs") {
+                if let Ok(result) = clean_attrs_file(entry.path(), options, backup) {
+                    record(entry.path(), result);
+                }
+            }
+        }
+    } else {
+        bail!("Path not file or dir");
+    }
+
+    cleaned.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok((errors, cleaned))
+}
+
+// Count the lines of all the `.rs` files in a crate. Files which can't be read are skipped.
+pub fn count_source_lines(path: &Path) -> Result<usize> {
+    let mut lines = 0;
+    for entry in WalkDir::new(path).follow_links(false) {
         let entry = entry.with_context(|| format!("Reading {}", path.display()))?;
-        let file_type = entry.file_type();
-        if file_type.is_file() && entry.path().extension().map_or(false, |e| e == "rs") {
-            if let Ok(Some(err)) = clean_attrs_file(entry.path()) {
-                errors.push(FileCleanError {
-                    path: entry.path().to_path_buf(),
-                    error: err,
-                });
+        if entry.file_type().is_file() && entry.path().extension().is_some_and(|e| e == "rs") {
+            if let Ok(source) = fs::read_to_string(entry.path()) {
+                lines += source.lines().count();
             }
         }
     }
-    Ok(errors)
+    Ok(lines)
 }
 
-// path must be for a file
-fn clean_attrs_file(path: &Path) -> Result<Option<CleanError>> {
+// path must be for a file. Returns the attributes cleaned. The original is kept as a `.orig` backup
+// if `backup` is set.
+fn clean_attrs_file(
+    path: &Path,
+    options: &CleanOptions,
+    backup: bool,
+) -> Result<Result<Vec<CleanedAttr>, CleanError>> {
     let source =
         fs::read_to_string(&path).with_context(|| format!("Reading file {}", path.display()))?;
-    match clean_source(&source) {
-        Ok(None) => Ok(None),
-        Ok(Some(cleaned)) => {
-            let backup = backup_path(path);
-            fs::copy(&path, &backup)
-                .with_context(|| format!("Copying {} to {}", path.display(), backup.display()))?;
-            fs::write(&path, cleaned).with_context(|| format!("Writing to {}", path.display()))?;
-            Ok(None)
+    match clean_source_detailed_with_options(&source, options) {
+        Ok(None) => Ok(Ok(vec![])),
+        Ok(Some(CleanedSource {
+            source: cleaned,
+            attrs,
+        })) => {
+            if backup {
+                let backup = backup_path(path);
+                fs::copy(&path, &backup).with_context(|| {
+                    format!("Copying {} to {}", path.display(), backup.display())
+                })?;
+                // Never overwrite the source unless the original can be recovered.
+                let backup_len = fs::metadata(&backup)
+                    .with_context(|| format!("Reading metadata of {}", backup.display()))?
+                    .len();
+                if backup_len != source.len() as u64 {
+                    bail!("Backup {} is incomplete", backup.display());
+                }
+            }
+
+            // Written to a temporary file and renamed so that the source is never half written.
+            let temp = temp_path(path);
+            fs::write(&temp, cleaned).with_context(|| format!("Writing to {}", temp.display()))?;
+            fs::rename(&temp, path)
+                .with_context(|| format!("Renaming {} to {}", temp.display(), path.display()))?;
+            Ok(Ok(attrs))
         }
-        Err(err) => Ok(Some(err)),
+        Err(err) => Ok(Err(err)),
     }
 }
 
@@ -116,25 +253,244 @@ fn backup_path(path: &Path) -> PathBuf {
     path.with_extension(ext)
 }
 
+fn temp_path(path: &Path) -> PathBuf {
+    let mut ext = path.extension().unwrap_or_else(|| "".as_ref()).to_owned();
+    ext.push(".cleaning");
+    path.with_extension(ext)
+}
+
+pub enum VerifyCleanOutcome {
+    // The cleaned crate compiles.
+    Compiles,
+    // Neither the cleaned nor the original crate compiles so cleaning isn't to blame.
+    AlreadyBroken,
+    // Only the original crate compiles. Contains the compiler errors for the cleaned crate.
+    BrokenByCleaning(String),
+}
+
+// Check that a crate cleaned by `clean_attrs` still compiles, ignoring lints.
+// If it doesn't, the `.orig` backups are restored in a copy of the crate under `target_dir` to
+// determine whether the build failure was caused by the cleaning.
+pub fn verify_clean(crate_path: &Path, target_dir: &Path) -> Result<VerifyCleanOutcome> {
+    let Some(errors) = cargo_check(crate_path, target_dir)? else {
+        return Ok(VerifyCleanOutcome::Compiles);
+    };
+
+    let name = crate_path.file_name().context("Crate path has no name")?;
+    let orig_path = target_dir.join("verify_clean_orig").join(name);
+    if orig_path.exists() {
+        fs::remove_dir_all(&orig_path)
+            .with_context(|| format!("Removing {}", orig_path.display()))?;
+    }
+    fs::create_dir_all(orig_path.parent().expect("Joined path has parent"))
+        .with_context(|| format!("Creating {}", orig_path.display()))?;
+    copy_dir(crate_path, &orig_path)?;
+    for entry in WalkDir::new(&orig_path).follow_links(false) {
+        let entry = entry.with_context(|| format!("Reading {}", orig_path.display()))?;
+        if entry.file_type().is_file() && entry.path().to_string_lossy().ends_with(".rs.orig") {
+            let restored = entry.path().with_extension("");
+            fs::rename(entry.path(), &restored)
+                .with_context(|| format!("Restoring {}", restored.display()))?;
+        }
+    }
+
+    let orig_result = cargo_check(&orig_path, target_dir);
+    fs::remove_dir_all(&orig_path).with_context(|| format!("Removing {}", orig_path.display()))?;
+
+    Ok(match orig_result? {
+        None => VerifyCleanOutcome::BrokenByCleaning(errors),
+        Some(_) => VerifyCleanOutcome::AlreadyBroken,
+    })
+}
+
+// Run `cargo check` on the crate, returning the errors if it fails.
+fn cargo_check(crate_path: &Path, target_dir: &Path) -> Result<Option<String>> {
+    let output = Command::new("cargo")
+        .args(["check", "--quiet", "--message-format=short", "--target-dir"])
+        .arg(target_dir)
+        .current_dir(crate_path)
+        .output()
+        .with_context(|| format!("Running cargo check in {}", crate_path.display()))?;
+    Ok(if output.status.success() {
+        None
+    } else {
+        Some(String::from_utf8_lossy(&output.stderr).into_owned())
+    })
+}
+
+// Copy the contents of the `source` dir into `target`.
+// Symlinks are skipped rather than copied or followed as they may point outside of `source`.
+// Returns the paths of the skipped symlinks.
+pub fn copy_dir(source: &Path, target: &Path) -> Result<Vec<PathBuf>> {
+    let mut skipped = vec![];
+    for entry in WalkDir::new(source).follow_links(false) {
+        let entry = entry.with_context(|| format!("Reading {}", source.display()))?;
+        let file_type = entry.file_type();
+
+        let entry_target = target.join(
+            entry
+                .path()
+                .strip_prefix(source)
+                .expect("Entries of source"),
+        );
+
+        if file_type.is_symlink() {
+            skipped.push(entry.into_path());
+        } else if file_type.is_dir() {
+            fs::create_dir(&entry_target)
+                .with_context(|| format!("Creating {}", &entry_target.display()))?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), &entry_target)
+                .with_context(|| format!("Copying {}", &entry_target.display()))?;
+        }
+    }
+
+    Ok(skipped)
+}
+
+// Remove the `target` dir and the `.bak`/`.orig` backups from a crate copied for fixing so that
+// only the sources remain. Symlinks are never followed so nothing outside `path` is removed.
+pub fn remove_fix_artifacts(path: &Path) -> Result<()> {
+    let target = path.join("target");
+    if fs::symlink_metadata(&target).is_ok_and(|m| m.is_dir()) {
+        fs::remove_dir_all(&target).with_context(|| format!("Removing {}", target.display()))?;
+    }
+
+    for entry in WalkDir::new(path).follow_links(false) {
+        let entry = entry.with_context(|| format!("Reading {}", path.display()))?;
+        if entry.file_type().is_file()
+            && entry
+                .path()
+                .extension()
+                .is_some_and(|e| e == "bak" || e == "orig")
+        {
+            fs::remove_file(entry.path())
+                .with_context(|| format!("Removing {}", entry.path().display()))?;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn clean_config(path: &Path) -> Result<()> {
-    let manifest_path = path.join("Cargo.toml");
-    clean_cargo_manifest(&manifest_path)?;
+    clean_config_with_manifest(path, Path::new(DEFAULT_MANIFEST_NAME))
+}
 
-    disable_clippy_config(path)?;
+// Clean a crate whose manifest is at `manifest_name` relative to `path`. Clippy configs are
+// disabled next to the manifest as well as in `path`.
+// Changes are only kept if all of them succeed.
+pub fn clean_config_with_manifest(path: &Path, manifest_name: &Path) -> Result<()> {
+    let mut guard = CrateMutationGuard::new();
+    let manifest_path = path.join(manifest_name);
+    clean_cargo_manifest(&mut guard, &manifest_path)?;
 
+    disable_clippy_config(&mut guard, path)?;
+    if let Some(manifest_dir) = manifest_path.parent().filter(|&dir| dir != path) {
+        disable_clippy_config(&mut guard, manifest_dir)?;
+    }
+
+    guard.keep();
     Ok(())
 }
 
+enum CrateMutation {
+    // A file which didn't exist before
+    Created(PathBuf),
+    // A file moved from the first path to the second
+    Renamed(PathBuf, PathBuf),
+}
+
+// Records the changes made to a crate so they can be undone. Unless `keep` is called the changes
+// are reverted in reverse order when the guard is dropped, including when unwinding from a panic.
+#[must_use]
+#[derive(Default)]
+pub struct CrateMutationGuard {
+    mutations: Vec<CrateMutation>,
+}
+
+impl CrateMutationGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Create a new file. Fails if the file already exists.
+    pub fn create_file(&mut self, path: &Path, contents: &str) -> Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .with_context(|| format!("Creating {}", path.display()))?;
+        self.mutations
+            .push(CrateMutation::Created(path.to_path_buf()));
+        file.write_all(contents.as_bytes())
+            .with_context(|| format!("Writing {}", path.display()))
+    }
+
+    pub fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        fs::rename(from, to).with_context(|| format!("Renaming {}", from.display()))?;
+        self.mutations
+            .push(CrateMutation::Renamed(from.to_path_buf(), to.to_path_buf()));
+        Ok(())
+    }
+
+    // Keep all changes made so far.
+    pub fn keep(mut self) {
+        self.mutations.clear();
+    }
+}
+
+impl Drop for CrateMutationGuard {
+    fn drop(&mut self) {
+        // Errors can't be reported from here. Restoring as much as possible is the best that can
+        // be done.
+        for mutation in self.mutations.drain(..).rev() {
+            let _ = match mutation {
+                CrateMutation::Created(path) => fs::remove_file(path),
+                CrateMutation::Renamed(from, to) => fs::rename(to, from),
+            };
+        }
+    }
+}
+
 pub fn touch_crate_roots(crate_path: &Path) -> Result<()> {
-    let manifest_path = crate_path.join("Cargo.toml");
+    touch_crate_roots_with_manifest(crate_path, Path::new(DEFAULT_MANIFEST_NAME))
+}
 
-    let contents = fs::read_to_string(manifest_path)
-        .with_context(|| format!("Failed to read Cargo.toml '{}'", crate_path.display()))?;
+// Touch the roots of a crate whose manifest is at `manifest_name` relative to `crate_path`. Target
+// paths are relative to the manifest's dir.
+pub fn touch_crate_roots_with_manifest(crate_path: &Path, manifest_name: &Path) -> Result<()> {
+    touch_crate_roots_with_extra_roots(crate_path, manifest_name, &[])
+}
+
+// Like `touch_crate_roots_with_manifest` but `extra_roots` (e.g. `lib.rs` for crates whose lib
+// isn't under `src`) are also touched if they exist, in addition to `DEFAULT_CRATE_ROOTS`.
+pub fn touch_crate_roots_with_extra_roots(
+    crate_path: &Path,
+    manifest_name: &Path,
+    extra_roots: &[PathBuf],
+) -> Result<()> {
+    let manifest_path = crate_path.join(manifest_name);
+    let crate_path = manifest_path.parent().unwrap_or(crate_path);
+
+    let contents = fs::read_to_string(&manifest_path)
+        .with_context(|| format!("Failed to read Cargo.toml '{}'", manifest_path.display()))?;
     let mut root: Value = contents
         .parse()
-        .with_context(|| format!("Failed to parse Cargo.toml '{}'", crate_path.display()))?;
+        .with_context(|| format!("Failed to parse Cargo.toml '{}'", manifest_path.display()))?;
+
+    // A build script is found by convention unless it's set or disabled with `build = false`. It's
+    // touched so that any code it generates is regenerated.
+    let mut build_script = Some(PathBuf::from("build.rs"));
 
     if let Value::Table(root_table) = &mut root {
+        if let Some(Value::Table(package)) = root_table.get("package") {
+            match package.get("build") {
+                Some(Value::String(path)) => build_script = Some(path.into()),
+                Some(Value::Boolean(false)) => build_script = None,
+                _ => {}
+            }
+        }
+
         if let Some(Value::Table(section)) = root_table.get("lib") {
             if let Some(Value::String(path)) = section.get("path") {
                 let root_path = crate_path.join(path);
@@ -151,53 +507,221 @@ pub fn touch_crate_roots(crate_path: &Path) -> Result<()> {
                     set_file_mtime(&root_path, FileTime::now()).with_context(|| {
                         format!("Failed to set mtime for '{}'", root_path.display())
                     })?;
+                } else if let Some(Value::String(name)) = section.get("name") {
+                    // Without a path the root is found by convention. The primary bin's
+                    // `src/main.rs` is touched below.
+                    let bin_dir = crate_path.join("src").join("bin");
+                    touch_if_exists(&bin_dir.join(format!("{name}.rs")))?;
+                    touch_if_exists(&bin_dir.join(name).join("main.rs"))?;
                 }
             }
         }
-    }
 
-    for default_root in ["src/lib.rs", "src/main.rs"] {
-        let path = crate_path.join(default_root);
-        match set_file_mtime(&path, FileTime::now()) {
-            Ok(()) => {}
-            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
-            Err(err) => {
-                return Err(err)
-                    .with_context(|| format!("Failed to set mtime for '{}'", path.display()))
+        // These are often excluded from the published crate so they may not exist.
+        for kind in ["test", "example", "bench"] {
+            if let Some(Value::Array(sections)) = root_table.get(kind) {
+                for section in sections {
+                    if let Some(Value::String(path)) = section.get("path") {
+                        touch_if_exists(&crate_path.join(path))?;
+                    }
+                }
             }
+        }
+    }
+
+    for default_root in DEFAULT_CRATE_ROOTS {
+        touch_if_exists(&crate_path.join(default_root))?;
+    }
+    for extra_root in extra_roots {
+        touch_if_exists(&crate_path.join(extra_root))?;
+    }
+    if let Some(build_script) = build_script {
+        touch_if_exists(&crate_path.join(build_script))?;
+    }
+
+    // Test, example and bench targets are also discovered from these dirs, either as single files
+    // or as a dir with a `main.rs`.
+    for target_dir in ["tests", "examples", "benches"] {
+        let entries = match fs::read_dir(crate_path.join(target_dir)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| format!("Failed to read '{target_dir}'")),
         };
+        for entry in entries {
+            let path = entry
+                .with_context(|| format!("Failed to read '{target_dir}'"))?
+                .path();
+            if path.is_dir() {
+                touch_if_exists(&path.join("main.rs"))?;
+            } else if path.extension().is_some_and(|e| e == "rs") {
+                touch_if_exists(&path)?;
+            }
+        }
     }
 
     Ok(())
 }
 
-// Replace path dependencies with crate versions.
-fn clean_cargo_manifest(path: &Path) -> Result<()> {
+fn touch_if_exists(path: &Path) -> Result<()> {
+    match set_file_mtime(path, FileTime::now()) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => {
+            Err(err).with_context(|| format!("Failed to set mtime for '{}'", path.display()))
+        }
+    }
+}
+
+const DEPENDENCY_TABLES: [&str; 3] = ["dependencies", "build-dependencies", "dev-dependencies"];
+
+fn read_manifest(path: &Path) -> Result<Value> {
     let contents = fs::read_to_string(path)
         .with_context(|| format!("Failed to read Cargo.toml '{}'", path.display()))?;
-    let mut root: Value = contents
+    contents
         .parse()
-        .with_context(|| format!("Failed to parse Cargo.toml '{}'", path.display()))?;
+        .with_context(|| format!("Failed to parse Cargo.toml '{}'", path.display()))
+}
+
+// Replace a manifest, keeping a backup of the original.
+fn replace_manifest(guard: &mut CrateMutationGuard, path: &Path, root: &Value) -> Result<()> {
+    let backup_path = path.with_extension("toml.bak");
+    guard
+        .rename(path, &backup_path)
+        .with_context(|| format!("Making Cargo.toml backup '{}'", &backup_path.display()))?;
+    guard
+        .create_file(path, &root.to_string())
+        .with_context(|| format!("Replace Cargo.toml contents '{}'", path.display()))
+}
+
+// Replace path dependencies with crate versions. Dependencies inherited from the workspace are
+// copied into the manifest and its members first as the workspace is removed.
+fn clean_cargo_manifest(guard: &mut CrateMutationGuard, path: &Path) -> Result<()> {
+    let mut root = read_manifest(path)?;
 
     let mut paths_removed = false;
     if let Value::Table(root_table) = &mut root {
-        paths_removed = remove_paths(root_table, "dependencies")
-            | remove_paths(root_table, "build-dependencies")
-            | remove_paths(root_table, "dev-dependencies")
-            | root_table.remove("workspace").is_some();
+        if let Some(Value::Table(workspace)) = root_table.get("workspace") {
+            let workspace = workspace.clone();
+            let manifest_dir = path.parent().unwrap_or_else(|| Path::new(""));
+            for member_path in workspace_member_manifests(manifest_dir, &workspace)? {
+                if member_path == path {
+                    continue;
+                }
+                let mut member = read_manifest(&member_path)?;
+                if let Value::Table(member_table) = &mut member {
+                    if inherit_workspace_dependencies(member_table, &workspace) {
+                        replace_manifest(guard, &member_path, &member)?;
+                    }
+                }
+            }
+            inherit_workspace_dependencies(root_table, &workspace);
+        }
+
+        paths_removed = DEPENDENCY_TABLES.iter().fold(false, |removed, name| {
+            remove_paths(root_table, name) | removed
+        }) | root_table.remove("workspace").is_some();
     }
 
     if paths_removed {
-        let backup_path = path.with_extension("toml.bak");
-        fs::copy(path, &backup_path)
-            .with_context(|| format!("Making Cargo.toml backup '{}'", &backup_path.display()))?;
-        fs::write(path, root.to_string())
-            .with_context(|| format!("Replace Cargo.toml contents '{}'", path.display()))?;
+        replace_manifest(guard, path, &root)?;
     }
 
     Ok(())
 }
 
+// The manifests of the workspace's members which exist. Members can be globs (e.g. `crates/*`).
+fn workspace_member_manifests(manifest_dir: &Path, workspace: &Table) -> Result<Vec<PathBuf>> {
+    let Some(Value::Array(members)) = workspace.get("members") else {
+        return Ok(vec![]);
+    };
+
+    let mut manifests = vec![];
+    for member in members.iter().filter_map(Value::as_str) {
+        let mut dirs = vec![manifest_dir.to_path_buf()];
+        for component in member.split('/').filter(|c| !c.is_empty() && *c != ".") {
+            if !component.contains(['*', '?']) {
+                for dir in &mut dirs {
+                    dir.push(component);
+                }
+                continue;
+            }
+            let mut matches = vec![];
+            for dir in &dirs {
+                let Ok(entries) = fs::read_dir(dir) else {
+                    continue;
+                };
+                for entry in entries {
+                    let entry = entry.with_context(|| format!("Reading {}", dir.display()))?;
+                    let name = entry.file_name();
+                    if entry.path().is_dir() && glob_match(component, &name.to_string_lossy()) {
+                        matches.push(entry.path());
+                    }
+                }
+            }
+            dirs = matches;
+        }
+        manifests.extend(
+            dirs.into_iter()
+                .map(|dir| dir.join(DEFAULT_MANIFEST_NAME))
+                .filter(|path| path.is_file()),
+        );
+    }
+    manifests.sort_unstable();
+    manifests.dedup();
+    Ok(manifests)
+}
+
+// Replace `dep = { workspace = true }` dependencies with the workspace's dependency. A path is
+// replaced with a `*` version. Returns whether any were replaced.
+fn inherit_workspace_dependencies(root_table: &mut Table, workspace: &Table) -> bool {
+    let Some(Value::Table(workspace_deps)) = workspace.get("dependencies") else {
+        return false;
+    };
+
+    let mut result = false;
+    for name in DEPENDENCY_TABLES {
+        let Some(Value::Table(dep_table)) = root_table.get_mut(name) else {
+            continue;
+        };
+        for (dep_name, dep) in dep_table.iter_mut() {
+            let Value::Table(member_dep) = dep else {
+                continue;
+            };
+            if member_dep.get("workspace").and_then(Value::as_bool) != Some(true) {
+                continue;
+            }
+            let mut inherited = match workspace_deps.get(dep_name) {
+                Some(Value::String(version)) => {
+                    let mut table = Table::new();
+                    table.insert("version".into(), Value::String(version.clone()));
+                    table
+                }
+                Some(Value::Table(table)) => table.clone(),
+                _ => continue,
+            };
+            if inherited.remove("path").is_some() {
+                inherited
+                    .entry("version")
+                    .or_insert_with(|| Value::String("*".into()));
+            }
+            // Features are added to the workspace's while other keys (e.g. `optional`) are set
+            for (key, value) in member_dep.iter().filter(|(key, _)| *key != "workspace") {
+                match (key.as_str(), inherited.get_mut(key), value) {
+                    ("features", Some(Value::Array(features)), Value::Array(added)) => {
+                        features.extend(added.iter().cloned());
+                    }
+                    _ => {
+                        inherited.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            *member_dep = inherited;
+            result = true;
+        }
+    }
+    result
+}
+
 fn remove_paths(root_table: &mut Table, name: &str) -> bool {
     let mut result = false;
     if let Some(Value::Table(dep_table)) = root_table.get_mut(name) {
@@ -216,14 +740,527 @@ fn remove_paths(root_table: &mut Table, name: &str) -> bool {
     result
 }
 
-fn disable_clippy_config(path: &Path) -> Result<()> {
+fn disable_clippy_config(guard: &mut CrateMutationGuard, path: &Path) -> Result<()> {
     for name in &[".clippy.toml", "clippy.toml"] {
         let config_path = path.join(name);
         if config_path.exists() {
-            fs::rename(&config_path, config_path.with_extension("toml.bak"))
-                .with_context(|| format!("Renaming {}", config_path.display()))?;
+            guard.rename(&config_path, &config_path.with_extension("toml.bak"))?;
         }
     }
 
     Ok(())
 }
+
+// Match `text` against a glob `pattern` where `*` matches any sequence of characters and `?`
+// matches a single character. There are no character classes or escapes.
+#[must_use]
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // The position of the last `*` and the text position it was tried at
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the `*` consume one more character
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Format a command so that it can be copied into a shell to reproduce a run.
+// Environment variables set or removed on the command are included.
+#[must_use]
+pub fn format_command(command: &Command) -> String {
+    let mut result = String::new();
+
+    if let Some(current_dir) = command.get_current_dir() {
+        write!(
+            result,
+            "cd {} && ",
+            &shell_escape::escape(current_dir.to_string_lossy()),
+        )
+        .expect("Write to string succeeds");
+    }
+
+    let (set_envs, removed_envs): (Vec<_>, Vec<_>) =
+        command.get_envs().partition(|(_, value)| value.is_some());
+
+    if !removed_envs.is_empty() {
+        result.push_str("env");
+        for (key, _) in removed_envs {
+            write!(
+                result,
+                " -u {}",
+                &shell_escape::escape(key.to_string_lossy())
+            )
+            .expect("Write to string succeeds");
+        }
+        result.push(' ');
+    }
+
+    for (key, value) in set_envs {
+        write!(
+            result,
+            "{}={} ",
+            &shell_escape::escape(key.to_string_lossy()),
+            &shell_escape::escape(value.expect("partitioned").to_string_lossy()),
+        )
+        .expect("Write to string succeeds");
+    }
+
+    result.push_str(&shell_escape::escape(
+        command.get_program().to_string_lossy(),
+    ));
+
+    for arg in command.get_args() {
+        write!(result, " {}", &shell_escape::escape(arg.to_string_lossy()))
+            .expect("Write to string succeeds");
+    }
+
+    result
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+
+    use expect_test::expect;
+    use tempfile::tempdir;
+
+    use super::{
+        clean_attrs, clean_config, copy_dir, ensure_empty_dir_or_clear, format_command, glob_match,
+        remove_fix_artifacts, touch_crate_roots, touch_crate_roots_with_extra_roots, verify_clean,
+        CrateMutationGuard, EnsureEmptyDirOutcome, VerifyCleanOutcome, DEFAULT_MANIFEST_NAME,
+    };
+    use filetime::{set_file_mtime, FileTime};
+
+    #[test]
+    fn ensure_empty_dir_cleared() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+        fs::create_dir_all(dir.path().join("b/c")).unwrap();
+
+        assert!(matches!(
+            ensure_empty_dir_or_clear(dir.path(), false).unwrap(),
+            EnsureEmptyDirOutcome::NonEmpty
+        ));
+        assert!(dir.path().join("a.txt").exists());
+        assert!(matches!(
+            ensure_empty_dir_or_clear(dir.path(), true).unwrap(),
+            EnsureEmptyDirOutcome::Cleared
+        ));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn clean_attrs_without_backup_leaves_source() {
+        let dir = tempdir().unwrap();
+        let source = "#![allow(clippy::all)]\nfn main() {}\n";
+        let path = dir.path().join("main.rs");
+        fs::write(&path, source).unwrap();
+        // The backup can't be written as a dir is in the way.
+        fs::create_dir(dir.path().join("main.rs.orig")).unwrap();
+
+        assert!(clean_attrs(&path).is_err());
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), source);
+        assert!(!dir.path().join("main.rs.cleaning").exists());
+    }
+
+    #[test]
+    fn clean_attrs_writes_backup() {
+        let dir = tempdir().unwrap();
+        let source = "#![allow(clippy::all)]\nfn main() {}\n";
+        let path = dir.path().join("main.rs");
+        fs::write(&path, source).unwrap();
+
+        assert!(clean_attrs(&path).unwrap().is_empty());
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("main.rs.orig")).unwrap(),
+            source
+        );
+        assert_ne!(fs::read_to_string(&path).unwrap(), source);
+        assert!(!dir.path().join("main.rs.cleaning").exists());
+    }
+
+    #[test]
+    fn mutation_guard_restores_on_panic() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("clippy.toml");
+        let backup_path = dir.path().join("clippy.toml.bak");
+        fs::write(&config_path, "msrv = \"1.0\"\n").unwrap();
+
+        let result = std::panic::catch_unwind(|| {
+            let mut guard = CrateMutationGuard::new();
+            guard.rename(&config_path, &backup_path).unwrap();
+            guard.create_file(&config_path, "injected").unwrap();
+            panic!("failed mid-operation");
+        });
+        assert!(result.is_err());
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            "msrv = \"1.0\"\n"
+        );
+        assert!(!backup_path.exists());
+
+        let mut guard = CrateMutationGuard::new();
+        guard.rename(&config_path, &backup_path).unwrap();
+        guard.keep();
+        assert!(!config_path.exists());
+        assert!(backup_path.exists());
+    }
+
+    #[test]
+    fn touch_build_script_and_extra_roots() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("tools")).unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            indoc::indoc! {r#"
+                [package]
+                name = "a"
+                version = "0.1.0"
+                build = "tools/gen.rs"
+            "#},
+        )
+        .unwrap();
+        let roots = ["lib.rs", "tools/gen.rs"];
+        let old = FileTime::from_unix_time(0, 0);
+        for root in roots.iter().chain(&["build.rs"]) {
+            let path = dir.path().join(root);
+            fs::write(&path, "").unwrap();
+            set_file_mtime(&path, old).unwrap();
+        }
+
+        touch_crate_roots_with_extra_roots(
+            dir.path(),
+            DEFAULT_MANIFEST_NAME.as_ref(),
+            &["lib.rs".into()],
+        )
+        .unwrap();
+
+        for root in roots {
+            let metadata = fs::metadata(dir.path().join(root)).unwrap();
+            assert_ne!(
+                FileTime::from_last_modification_time(&metadata),
+                old,
+                "{root}"
+            );
+        }
+        // Not the build script as another is set.
+        let metadata = fs::metadata(dir.path().join("build.rs")).unwrap();
+        assert_eq!(FileTime::from_last_modification_time(&metadata), old);
+    }
+
+    #[test]
+    fn touch_build_script() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            indoc::indoc! {r#"
+                [package]
+                name = "a"
+                version = "0.1.0"
+                build = "build.rs"
+            "#},
+        )
+        .unwrap();
+        let path = dir.path().join("build.rs");
+        let old = FileTime::from_unix_time(0, 0);
+        fs::write(&path, "").unwrap();
+        set_file_mtime(&path, old).unwrap();
+
+        touch_crate_roots(dir.path()).unwrap();
+
+        let metadata = fs::metadata(&path).unwrap();
+        assert_ne!(FileTime::from_last_modification_time(&metadata), old);
+    }
+
+    #[test]
+    fn touch_pathless_bins() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src/bin/bar")).unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            indoc::indoc! {r#"
+                [package]
+                name = "a"
+                version = "0.1.0"
+
+                [[bin]]
+                name = "a"
+
+                [[bin]]
+                name = "foo"
+
+                [[bin]]
+                name = "bar"
+            "#},
+        )
+        .unwrap();
+        let roots = ["src/main.rs", "src/bin/foo.rs", "src/bin/bar/main.rs"];
+        let old = FileTime::from_unix_time(0, 0);
+        for root in roots {
+            let path = dir.path().join(root);
+            fs::write(&path, "").unwrap();
+            set_file_mtime(&path, old).unwrap();
+        }
+
+        touch_crate_roots(dir.path()).unwrap();
+
+        for root in roots {
+            let metadata = fs::metadata(dir.path().join(root)).unwrap();
+            assert_ne!(
+                FileTime::from_last_modification_time(&metadata),
+                old,
+                "{root}"
+            );
+        }
+    }
+
+    #[test]
+    fn workspace_dependencies_inherited() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("crates/b")).unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            indoc::indoc! {r#"
+                [package]
+                name = "a"
+                version = "0.1.0"
+
+                [dependencies]
+                serde = { workspace = true }
+
+                [workspace]
+                members = ["crates/*"]
+
+                [workspace.dependencies]
+                serde = { version = "1.0", features = ["derive"] }
+                b = { path = "crates/b" }
+                log = "0.4"
+            "#},
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("crates/b/Cargo.toml"),
+            indoc::indoc! {r#"
+                [package]
+                name = "b"
+                version = "0.1.0"
+
+                [dependencies]
+                serde = { workspace = true, features = ["rc"], optional = true }
+                log.workspace = true
+            "#},
+        )
+        .unwrap();
+
+        clean_config(dir.path()).unwrap();
+
+        let root: toml::Value = fs::read_to_string(dir.path().join("Cargo.toml"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(root.get("workspace").is_none());
+        expect![[r#"
+            features = ["derive"]
+            version = "1.0"
+        "#]]
+        .assert_eq(&root["dependencies"]["serde"].to_string());
+
+        let member: toml::Value = fs::read_to_string(dir.path().join("crates/b/Cargo.toml"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        expect![[r#"
+            features = ["derive", "rc"]
+            optional = true
+            version = "1.0"
+        "#]]
+        .assert_eq(&member["dependencies"]["serde"].to_string());
+        expect![[r#"
+            version = "0.4"
+        "#]]
+        .assert_eq(&member["dependencies"]["log"].to_string());
+        assert!(dir.path().join("crates/b/Cargo.toml.bak").exists());
+    }
+
+    #[test]
+    fn remove_fix_artifacts_keeps_sources() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::create_dir_all(dir.path().join("target/debug")).unwrap();
+        fs::write(dir.path().join("target/debug/a"), "").unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "").unwrap();
+        fs::write(dir.path().join("Cargo.toml.bak"), "").unwrap();
+        fs::write(dir.path().join("src/main.rs"), "").unwrap();
+        fs::write(dir.path().join("src/main.rs.orig"), "").unwrap();
+
+        remove_fix_artifacts(dir.path()).unwrap();
+
+        let mut remaining: Vec<_> = walkdir::WalkDir::new(dir.path())
+            .into_iter()
+            .map(|e| {
+                e.unwrap()
+                    .path()
+                    .strip_prefix(dir.path())
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            ["", "Cargo.toml", "src", "src/main.rs"]
+                .iter()
+                .map(std::path::PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match("ser*", "serde-1.0.130"));
+        assert!(glob_match("*-1.0.*", "serde-1.0.130"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a?c", "abc"));
+        assert!(glob_match("*a*b", "xaxab"));
+        assert!(glob_match("serde-1.0.130", "serde-1.0.130"));
+        assert!(!glob_match("ser*", "rand-0.8.4"));
+        assert!(!glob_match("*-1.0.*", "serde-0.9.15"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(!glob_match("serde", "serde-1.0.130"));
+    }
+
+    fn write_crate(path: &std::path::Path, lib: &str) {
+        fs::create_dir_all(path.join("src")).unwrap();
+        fs::write(
+            path.join("Cargo.toml"),
+            "[package]\nname = \"tricky\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        fs::write(path.join("src/lib.rs"), lib).unwrap();
+    }
+
+    #[test]
+    fn verify_clean_tricky_file() {
+        let dir = tempdir().unwrap();
+        let crate_path = dir.path().join("tricky-0.1.0");
+        write_crate(
+            &crate_path,
+            indoc::indoc! {r#"
+                #![cfg_attr(feature = "x", deny(clippy::all))]
+                #![allow(clippy::approx_constant)]
+
+                pub struct S(#[allow(clippy::vec_box)] pub Vec<Box<u32>>);
+
+                pub fn f() -> u32 {
+                    #[allow(unused_mut)]
+                    let mut x = 1;
+                    x
+                }
+            "#},
+        );
+
+        clean_attrs(&crate_path).unwrap();
+        assert!(crate_path.join("src/lib.rs.orig").is_file());
+
+        let outcome = verify_clean(&crate_path, &dir.path().join("_target")).unwrap();
+        assert!(matches!(outcome, VerifyCleanOutcome::Compiles));
+    }
+
+    #[test]
+    fn verify_clean_detects_broken_cleaning() {
+        let dir = tempdir().unwrap();
+        let crate_path = dir.path().join("tricky-0.1.0");
+        write_crate(&crate_path, "pub fn f() {} /* cleaned */ }\n");
+        fs::write(crate_path.join("src/lib.rs.orig"), "pub fn f() {}\n").unwrap();
+
+        let outcome = verify_clean(&crate_path, &dir.path().join("_target")).unwrap();
+        assert!(matches!(outcome, VerifyCleanOutcome::BrokenByCleaning(_)));
+        assert!(!dir
+            .path()
+            .join("_target/verify_clean_orig")
+            .join("tricky-0.1.0")
+            .exists());
+
+        fs::write(crate_path.join("src/lib.rs.orig"), "pub fn f() {\n").unwrap();
+        let outcome = verify_clean(&crate_path, &dir.path().join("_target")).unwrap();
+        assert!(matches!(outcome, VerifyCleanOutcome::AlreadyBroken));
+    }
+
+    #[test]
+    fn format_command_with_envs() {
+        let mut command = Command::new("cargo");
+        command
+            .arg("clippy")
+            .arg("--message-format=json")
+            .current_dir("/path/with space")
+            .env("RUST_BACKTRACE", "1")
+            .env("RUSTFLAGS", "--cfg foo")
+            .env_remove("CARGO_TARGET_DIR");
+
+        let expected = expect![[
+            r"cd '/path/with space' && env -u CARGO_TARGET_DIR RUSTFLAGS='--cfg foo' RUST_BACKTRACE=1 cargo clippy --message-format=json"
+        ]];
+        expected.assert_eq(&format_command(&command));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copy_dir_skips_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let outside = tempdir().unwrap();
+        fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+
+        let source = tempdir().unwrap();
+        fs::create_dir(source.path().join("src")).unwrap();
+        fs::write(source.path().join("src/lib.rs"), "").unwrap();
+        symlink(outside.path(), source.path().join("src/escape")).unwrap();
+        symlink(
+            outside.path().join("secret.txt"),
+            source.path().join("secret.txt"),
+        )
+        .unwrap();
+
+        let target = tempdir().unwrap();
+        let dest = target.path().join("crate");
+        let mut skipped = copy_dir(source.path(), &dest).unwrap();
+        skipped.sort();
+
+        assert_eq!(
+            skipped,
+            [
+                source.path().join("secret.txt"),
+                source.path().join("src/escape")
+            ]
+        );
+        assert!(dest.join("src/lib.rs").is_file());
+        assert!(!dest.join("src/escape").exists());
+        assert!(!dest.join("secret.txt").exists());
+        assert_eq!(fs::read_dir(outside.path()).unwrap().count(), 1);
+    }
+}
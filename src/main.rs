@@ -4,97 +4,616 @@
 #![warn(clippy::unwrap_used)]
 
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env;
-use std::fmt::Write;
+use std::ffi::OsStr;
+use std::fmt::{self, Write};
 use std::fs;
-use std::io::stdout;
-use std::io::Read;
+use std::io::{self, stdout, Read, Write as _};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::{self, Command, Stdio};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{bail, Context, Result};
 use argh::FromArgs;
-use cargo_metadata::diagnostic::{Diagnostic, DiagnosticCode};
-use cargo_metadata::{CompilerMessage, Message};
+use cargo_metadata::diagnostic::{Diagnostic, DiagnosticCode, DiagnosticLevel, DiagnosticSpan};
+use cargo_metadata::{CompilerMessage, Message, Version};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
 
-use clippy_lint_tester::clippy_workspace::{prepare_clippy, ClippyBin, ClippyWorkspace};
-use clippy_lint_tester::markdown_formatting::print_table;
-use clippy_lint_tester::{ensure_empty_dir, touch_crate_roots, EnsureEmptyDirOutcome, ProgressBar};
+use clippy_lint_tester::clippy_workspace::{
+    installed_clippy, lint_code_matches, prepare_clippy, ClippyBuildFailed, ClippyBuildOptions,
+    ClippyWorkspace, LintLevel,
+};
+use clippy_lint_tester::git_target::{is_git_target, GitClone};
+use clippy_lint_tester::interrupt::{self, INTERRUPTED_EXIT_CODE};
+use clippy_lint_tester::markdown_formatting::{
+    print_multi_column_table, print_table, Alignment, Cell, Column, PerKloc, Percent, Seconds,
+};
+use clippy_lint_tester::progress_bar::Outcome;
+use clippy_lint_tester::resource_usage::wait_with_peak_rss;
+use clippy_lint_tester::wrapping::wrap_rendered;
+use clippy_lint_tester::{
+    copy_dir, count_source_lines, ensure_empty_dir_or_clear, format_command, glob_match,
+    remove_fix_artifacts, touch_crate_roots_with_extra_roots, EnsureEmptyDirOutcome, ProgressBar,
+    ProgressMode, ProgressTemplate, DEFAULT_MANIFEST_NAME,
+};
 
 const CARGO_TARGET_DIR: &str = "_target";
+// The Cargo target dir used with `--compare-workspaces`, inside the one for the first workspace.
+// Each Clippy build needs its own as switching between them rebuilds everything.
+const WORKSPACE_B_TARGET_DIR: &str = "_workspace_b";
 
 #[derive(FromArgs)]
+#[allow(clippy::struct_excessive_bools)]
 /// Test Clippy against downloaded crates
 struct Args {
     #[argh(positional)]
-    /// path to the Clippy source
-    source: PathBuf,
+    /// path to the Clippy source (can be omitted with `--use-installed`, or if `CLIPPY_SOURCE`
+    /// is set and no lints are given), path to the directory containing crates (or a git URL or bare
+    /// repository whose packages are linted) and the lints to test
+    paths_and_lints: Vec<String>,
 
-    #[argh(positional)]
-    /// path to the directory containing crates
-    target: PathBuf,
-
-    #[argh(positional)]
-    /// lints to test
-    lints: Vec<String>,
+    #[argh(option)]
+    /// use the Clippy installed with this rustup toolchain (e.g. `stable`) instead of building
+    /// it from source - the Clippy source is omitted from the positionals
+    use_installed: Option<String>,
 
     #[argh(option)]
     /// the directory to attempt fixes in - omit to skip fixing
     fix: Option<PathBuf>,
 
+    #[argh(switch)]
+    /// remove the contents of the fix directory if it isn't empty
+    force: bool,
+
+    #[argh(switch)]
+    /// keep the `target` dir and backup files in the fixed crates
+    keep_fix_artifacts: bool,
+
+    #[argh(switch)]
+    /// check the fixed crates with the lints enabled by default and report warnings the fix added
+    compare_fix: bool,
+
+    #[argh(switch)]
+    /// print Cargo's full output when a fix fails rather than just the errors it caused
+    verbose_fix: bool,
+
     #[argh(switch)]
     /// check for allows - useful for testing attribute cleaning
     check_allows: bool,
+
+    #[argh(option, default = "1")]
+    /// number of times to retry a crate whose build failed due to lock contention
+    build_retries: usize,
+
+    #[argh(option, default = "1")]
+    /// number of times to retry building Clippy after failing to fetch its dependencies
+    clippy_build_retries: usize,
+
+    #[argh(option)]
+    /// lints to compare against the positional lints - counts are reported side by side
+    lints_b: Vec<String>,
+
+    #[argh(option)]
+    /// another Clippy workspace to lint each crate with as well (B) - the differences in the
+    /// warning counts and build failures from the first workspace (A) are reported
+    compare_workspaces: Option<PathBuf>,
+
+    #[argh(option, default = "OutputFormat::Human")]
+    /// the output format: `human` (default), `jsonl` - one JSON object per crate as it
+    /// completes followed by a summary object, or `dashboard` - a JSON object of the totals
+    /// and example warnings of each lint for the Clippy lint dashboard. Diagnostics are written
+    /// to stderr for the JSON formats
+    format: OutputFormat,
+
+    #[argh(option)]
+    /// an extra argument for every Cargo command run on the crates (e.g. `--locked`)
+    cargo_arg: Vec<String>,
+
+    #[argh(option, default = "PathBuf::from(DEFAULT_MANIFEST_NAME)")]
+    /// the path of the manifest relative to each crate's dir (default: `Cargo.toml`)
+    manifest_name: PathBuf,
+
+    #[argh(option)]
+    /// another crate root to touch before linting each crate if it exists, relative to the
+    /// manifest's dir (e.g. `lib.rs`)
+    extra_crate_root: Vec<PathBuf>,
+
+    #[argh(option)]
+    /// an environment variable to set for every Cargo command run on the crates as `KEY=VALUE`
+    env: Vec<String>,
+
+    #[argh(option)]
+    /// flags to pass to rustc for every crate through `RUSTFLAGS` (e.g. `-Zcrate-attr=...`)
+    rustflags: Option<String>,
+
+    #[argh(option)]
+    /// a `--cfg` to set for every crate (e.g. `docsrs` or `feature="x"`) - added to `RUSTFLAGS`
+    cfg: Vec<String>,
+
+    #[argh(option, default = "100")]
+    /// the minimum number of milliseconds between progress bar redraws (default: 100)
+    progress_refresh_ms: u64,
+
+    #[argh(option, default = "ProgressMode::Auto")]
+    /// when to show the progress bar: `auto` (default) when stderr is a terminal, `always` or
+    /// `never`
+    progress: ProgressMode,
+
+    #[argh(option)]
+    /// the layout of the progress bar with the placeholders `{{n}}`, `{{total}}`, `{{name}}`,
+    /// `{{tally}}`, `{{bar}}`, `{{eta}}`, `{{rate}}` and `{{percent}}` (default:
+    /// `{{n}}/{{total}}: {{tally}}{{name}} [{{bar}}] {{eta}}`)
+    progress_template: Option<ProgressTemplate>,
+
+    #[argh(switch)]
+    /// count warnings in dependencies of the crates as well
+    count_deps: bool,
+
+    #[argh(switch)]
+    /// lint tests, examples and benches as well
+    all_targets: bool,
+
+    #[argh(switch)]
+    /// break down the warnings of each crate by the target they're in (e.g. `lib` or `bin foo`)
+    by_target: bool,
+
+    #[argh(switch)]
+    /// only lint the highest version of crates with the same package name (e.g. `serde-1.0.1`
+    /// but not `serde-1.0.0`)
+    dedup_versions: bool,
+
+    #[argh(option)]
+    /// only lint this many crates picked at random after the other filters are applied
+    sample: Option<usize>,
+
+    #[argh(option)]
+    /// the seed used to pick the crates for `--sample` (default: random) - the seed used is
+    /// reported so that a sample can be repeated
+    seed: Option<u64>,
+
+    #[argh(option)]
+    /// only lint crates whose directory name matches one of these globs (e.g. `ser*`)
+    include: Vec<String>,
+
+    #[argh(option)]
+    /// only lint crates with a file modified after this time, given as seconds since the Unix
+    /// epoch or as a file whose modification time is used
+    changed_since: Option<String>,
+
+    #[argh(option)]
+    /// a TOML or JSON file (by extension) mapping crate directory names to lists of tags (e.g.
+    /// `tokio-1.0.0 = ["async"]`) - warnings are totalled per tag in the summary
+    tags: Option<PathBuf>,
+
+    #[argh(option)]
+    /// only lint crates with one of these tags in the `--tags` file
+    tag: Vec<String>,
+
+    #[argh(option)]
+    /// word-wrap rendered diagnostics to the given number of columns
+    wrap: Option<usize>,
+
+    #[argh(switch)]
+    /// only count warnings - don't print them
+    count_only: bool,
+
+    #[argh(option)]
+    /// stop printing a crate's warnings after this many - they're still all counted
+    max_warnings_per_crate: Option<usize>,
+
+    #[argh(switch)]
+    /// don't count warnings whose spans are all in macro expansions - their number is reported
+    /// separately
+    exclude_macro_hits: bool,
+
+    #[argh(option)]
+    /// diagnostic codes reported by `--check-allows` (default: `E0453`)
+    allow_code: Vec<String>,
+
+    #[argh(option)]
+    /// write a shell script reproducing the runs of crates that warned or failed to build
+    script: Option<PathBuf>,
+
+    #[argh(option)]
+    /// write the summary to a file instead of stdout - gzip compressed if the path ends in `.gz`
+    output: Option<PathBuf>,
+
+    #[argh(switch)]
+    /// gzip compress the `--output` file regardless of its extension
+    compress: bool,
+
+    #[argh(option)]
+    /// write the warnings of each lint to its own file in this directory along with an index -
+    /// the summary is still written as usual
+    split_output: Option<PathBuf>,
+
+    #[argh(option)]
+    /// write the JSON message of each counted warning to `<crate>.json` in this directory, one
+    /// per line
+    json_diagnostics_dir: Option<PathBuf>,
+
+    #[argh(option)]
+    /// the branch, tag or commit to lint when the target is a git repository (default: the
+    /// default branch)
+    git_ref: Option<String>,
+
+    #[argh(switch)]
+    /// keep the clone of a git target instead of removing it once done
+    keep_clone: bool,
+
+    #[argh(switch)]
+    /// build every crate without lints before linting so that shared dependencies are already
+    /// built when the crates are linted
+    warmup: bool,
+
+    #[argh(switch)]
+    /// build each crate in its own target dir so that crates can't affect each other's builds
+    per_crate_target_dir: bool,
+
+    #[argh(switch)]
+    /// keep the target dirs made by `--per-crate-target-dir` instead of removing each one once
+    /// the crate is done
+    keep_target: bool,
+
+    #[argh(switch)]
+    /// count the target entries which aren't crates in the summary instead of listing each one
+    quiet_invalid: bool,
+
+    #[argh(switch)]
+    /// lint each crate a second time in another target dir and report the crates whose warning
+    /// count differs
+    check_determinism: bool,
+
+    #[argh(switch)]
+    /// set `CARGO_INCREMENTAL=0` when linting so runs sharing a target dir are more reproducible
+    /// at some cost in speed
+    no_incremental: bool,
+
+    #[argh(switch)]
+    /// report the crates with the highest peak memory usage
+    mem: bool,
+
+    #[argh(switch)]
+    /// add the number of lines of Rust source and the warnings per thousand lines to the warnings
+    /// table
+    density: bool,
+
+    #[argh(switch)]
+    /// pass `--locked` to Cargo so that crates whose lockfile is out of date fail rather than
+    /// using newer dependencies
+    locked: bool,
+
+    #[argh(switch)]
+    /// generate a lockfile for crates without one before linting them so that later runs use
+    /// the same dependency versions
+    generate_lockfile: bool,
+
+    #[argh(switch)]
+    /// run Cargo without accessing the network
+    offline: bool,
+
+    #[argh(option, default = "ContextLevel::Allow")]
+    /// the level of the clippy lints which aren't tested: `allow` (default) or `warn` to show
+    /// their warnings alongside the tested lints without counting them
+    context_level: ContextLevel,
+
+    #[argh(switch)]
+    /// tag each warning with its lint's group (e.g. `[pedantic]`)
+    show_groups: bool,
+
+    #[argh(switch)]
+    /// report the most common warning messages across all crates
+    common_messages: bool,
+
+    #[argh(switch)]
+    /// report the min, median and max size in lines and bytes of the warnings' primary spans
+    span_stats: bool,
+
+    #[argh(switch)]
+    /// start the summary with the run's totals, the mean, median and max warnings per crate and
+    /// the run's duration
+    stats: bool,
+
+    #[argh(switch)]
+    /// list the crates that would be linted and exit
+    list_crates: bool,
+
+    #[argh(switch)]
+    /// build Clippy, list its lints and exit - the positionals after the Clippy source are lint
+    /// groups to list the lints of (e.g. `pedantic`)
+    list_lints: bool,
+
+    #[argh(switch)]
+    /// allow lint groups which expand to more than 50 lints (e.g. `clippy::restriction`)
+    confirm_large_group: bool,
+
+    #[argh(option, long = "target")]
+    /// the target triple to lint for (e.g. `i686-unknown-linux-gnu`)
+    target_triple: Option<String>,
+
+    #[argh(option, default = "SortOrder::Name")]
+    /// the order of the warnings table: `name` (default) or `count`
+    sort: SortOrder,
+
+    #[argh(option)]
+    /// render the summary of a run saved with `--format jsonl` instead of linting
+    from_json: Option<PathBuf>,
 }
 
-fn crate_name(path: &Path) -> Cow<'_, str> {
-    path.file_name().expect("has file_name").to_string_lossy()
+// Diagnostics emitted when a lint attribute tries to override a lint forbidden on the command
+// line. `--check-allows` forbids the tested lints so each of these marks a suppression.
+// - E0453: `allow`, `warn` or `deny` of a forbidden lint.
+const DEFAULT_ALLOW_CODES: &[&str] = &["E0453"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Human,
+    JsonLines,
+    // A `DashboardRecord` once the run is done
+    Dashboard,
 }
 
-#[allow(clippy::too_many_lines)]
-fn main() -> Result<()> {
-    let Args {
-        source,
-        target,
-        lints: lint_args,
-        fix: fix_dir,
-        check_allows,
-    } = argh::from_env();
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(OutputFormat::Human),
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            "dashboard" => Ok(OutputFormat::Dashboard),
+            _ => Err(format!(
+                "unknown format `{s}` - expected `human`, `jsonl` or `dashboard`"
+            )),
+        }
+    }
+}
 
-    for name in &lint_args {
-        if name.is_empty()
-            || name
-                .strip_prefix("clippy::")
-                .unwrap_or(name)
-                .chars()
-                .any(|c| !c.is_ascii_alphanumeric() && c != '_' && c != '-')
+// The level of `clippy::all` when linting.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ContextLevel {
+    Allow,
+    // Shown as context for the tested lints but not counted
+    Warn,
+}
+
+impl ContextLevel {
+    fn flag(self) -> &'static str {
+        match self {
+            ContextLevel::Allow => "--allow",
+            ContextLevel::Warn => "--warn",
+        }
+    }
+}
+
+impl FromStr for ContextLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(ContextLevel::Allow),
+            "warn" => Ok(ContextLevel::Warn),
+            _ => Err(format!(
+                "unknown context level `{s}` - expected `allow` or `warn`"
+            )),
+        }
+    }
+}
+
+// Cargo arguments that are always set by the tool so can't be passed with `--cargo-arg`.
+const RESERVED_CARGO_ARGS: &[&str] = &[
+    "--target-dir",
+    "--message-format",
+    "--quiet",
+    "-q",
+    "--manifest-path",
+];
+
+// Settings that apply to every crate in a run.
+#[derive(Clone)]
+#[allow(clippy::struct_excessive_bools)]
+struct RunConfig {
+    build_retries: usize,
+    wrap: Option<usize>,
+    count_only: bool,
+    // The number of warnings printed for each crate. `None` if they're all printed.
+    max_warnings_per_crate: Option<usize>,
+    // Warnings whose spans are all in macro expansions are counted separately.
+    exclude_macro_hits: bool,
+    allow_codes: Vec<String>,
+    common_messages: bool,
+    span_stats: bool,
+    target_triple: Option<String>,
+    all_targets: bool,
+    keep_fix_artifacts: bool,
+    verbose_fix: bool,
+    count_deps: bool,
+    cargo_args: Vec<String>,
+    envs: Vec<(String, String)>,
+    // The manifest's path relative to each crate's dir
+    manifest_name: PathBuf,
+    // Touched along with the default crate roots if they exist
+    extra_crate_roots: Vec<PathBuf>,
+    locked: bool,
+    generate_lockfile: bool,
+    // Set as `RUSTFLAGS`, replacing any from the environment
+    rustflags: Option<String>,
+    // Set `CARGO_INCREMENTAL=0`
+    no_incremental: bool,
+    // Collect the text of each warning for `--split-output` and `--format dashboard`
+    collect_diagnostics: bool,
+    by_target: bool,
+    context_level: ContextLevel,
+    // Where the JSON messages of the counted warnings are written. `None` if they aren't.
+    json_diagnostics_dir: Option<PathBuf>,
+    // Lint -> its group without the `clippy::` prefix. `None` unless groups are shown.
+    lint_groups: Option<BTreeMap<String, String>>,
+    // The lints checked for new warnings after a fix. `None` unless `--compare-fix` is set.
+    side_effect_lints: Option<Vec<String>>,
+    // Added to the location of each printed warning, e.g. `B` for the second workspace of
+    // `--compare-workspaces`.
+    location_tag: Option<&'static str>,
+}
+
+impl RunConfig {
+    // The location printed before a warning, tagged with the lint's group if groups are shown.
+    fn warning_location(&self, crate_name: &str, lint: &str, span: &DiagnosticSpan) -> String {
+        let mut location = self.span_location(crate_name, span);
+        if let Some(group) = self
+            .lint_groups
+            .as_ref()
+            .and_then(|groups| groups.get(lint))
         {
-            bail!("Invalid lint name");
+            write!(location, " [{group}]").expect("Write to string succeeds");
         }
+        location
     }
 
-    if let Some(fix_dir) = &fix_dir {
-        match ensure_empty_dir(fix_dir)? {
-            EnsureEmptyDirOutcome::Created => println!("Fix directory created"),
-            EnsureEmptyDirOutcome::NonEmpty => bail!("Fix directory exists and not empty"),
-            EnsureEmptyDirOutcome::Empty => {}
+    // e.g. `---> foo/src/lib.rs:1:5`, followed by the `location_tag` if there is one.
+    fn span_location(&self, crate_name: &str, span: &DiagnosticSpan) -> String {
+        let mut location = format!(
+            "---> {}/{}:{}:{}",
+            crate_name, span.file_name, span.line_start, span.column_start
+        );
+        if let Some(tag) = self.location_tag {
+            write!(location, " ({tag})").expect("Write to string succeeds");
+        }
+        location
+    }
+
+    fn format_rendered<'a>(&self, rendered: &'a str) -> Cow<'a, str> {
+        match self.wrap {
+            Some(width) => wrap_rendered(rendered, width).into(),
+            None => rendered.into(),
         }
     }
 
-    if !target.exists() {
-        bail!("Target path `{}` does not exist", target.display())
+    // The config for linting a crate again only to count its warnings, with everything else that's
+    // collected from a run turned off.
+    fn for_recount(&self) -> RunConfig {
+        RunConfig {
+            count_only: true,
+            common_messages: false,
+            span_stats: false,
+            collect_diagnostics: false,
+            by_target: false,
+            json_diagnostics_dir: None,
+            ..self.clone()
+        }
     }
 
-    let clippy_workspace = prepare_clippy(&env::current_dir()?.join(source), || {
-        eprintln!("Compiling Clippy");
-    })?;
+    // Add the Cargo args and environment common to all `cargo-clippy` commands.
+    fn add_cargo_args(&self, command: &mut Command) {
+        command.envs(self.envs.iter().map(|(key, value)| (key, value)));
+        if let Some(rustflags) = &self.rustflags {
+            command.env("RUSTFLAGS", rustflags);
+        }
+        if self.no_incremental {
+            command.env("CARGO_INCREMENTAL", "0");
+        }
+        if let Some(target_triple) = &self.target_triple {
+            command.arg("--target").arg(target_triple);
+        }
+        if self.all_targets {
+            command.arg("--all-targets");
+        }
+        // Commands are run in the crate's dir so the relative path can be used as is.
+        if self.manifest_name != Path::new(DEFAULT_MANIFEST_NAME) {
+            command.arg("--manifest-path").arg(&self.manifest_name);
+        }
+        if self.locked {
+            command.arg("--locked");
+        }
+        command.args(&self.cargo_args);
+    }
+}
 
-    let lints = check_and_format_lint_names(&clippy_workspace, &lint_args)?;
+// The `RUSTFLAGS` for `--rustflags` and `--cfg`. `None` if neither is used.
+fn join_rustflags(rustflags: Option<String>, cfgs: &[String]) -> Option<String> {
+    if cfgs.is_empty() {
+        return rustflags;
+    }
+    // `RUSTFLAGS` is split on spaces so a cfg value can't contain any
+    let cfg_flags = cfgs.iter().map(|cfg| format!("--cfg {cfg}"));
+    Some(
+        rustflags
+            .into_iter()
+            .chain(cfg_flags)
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
 
-    eprintln!("Linting crates");
-    let mut paths = fs::read_dir(&target)
+// The file in the target dir recording the `RUSTFLAGS` of the last run.
+const RUSTFLAGS_FILE: &str = "clippy_lint_tester-rustflags";
+
+// Warn when `RUSTFLAGS` differ from the last run using the target dir as Cargo then rebuilds
+// everything in it. Only `--rustflags` and `--cfg` runs are recorded. Other runs use the ambient
+// `RUSTFLAGS` so they remove the record rather than leave a stale one.
+fn check_rustflags(cargo_target_dir: &Path, rustflags: Option<&str>) -> Result<()> {
+    let path = cargo_target_dir.join(RUSTFLAGS_FILE);
+    let Some(rustflags) = rustflags else {
+        return match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("Removing {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    };
+    if cargo_target_dir.exists() {
+        let last = match fs::read_to_string(&path) {
+            Ok(last) => last,
+            // The last run used the ambient `RUSTFLAGS`
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                env::var("RUSTFLAGS").unwrap_or_default()
+            }
+            Err(err) => return Err(err).with_context(|| format!("Reading {}", path.display())),
+        };
+        if last != rustflags {
+            eprintln!(
+                "warning: `RUSTFLAGS` changed from `{last}` to `{rustflags}` since the last run \
+                so all dependencies will be rebuilt"
+            );
+        }
+    }
+    fs::create_dir_all(cargo_target_dir)
+        .with_context(|| format!("Creating {}", cargo_target_dir.display()))?;
+    fs::write(&path, rustflags).with_context(|| format!("Writing {}", path.display()))
+}
+
+// Split a `--env` value into the variable's name and value.
+fn parse_env_arg(arg: &str) -> Result<(String, String)> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !key.contains('\0') && !value.contains('\0') => {
+            Ok((key.into(), value.into()))
+        }
+        _ => bail!("`--env {arg}` must be of the form `KEY=VALUE`"),
+    }
+}
+
+fn crate_name(path: &Path) -> Cow<'_, str> {
+    path.file_name().expect("has file_name").to_string_lossy()
+}
+
+// Remove a per-crate target dir. It won't exist if nothing was built.
+fn remove_target_dir(path: &Path) -> Result<()> {
+    match fs::remove_dir_all(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("Removing {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+// The sorted paths in the target dir that will be linted.
+fn list_crate_paths(target: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(target)
         .context("Failed to read target dir")?
         .map(|res| res.context("Failed to read entry").map(|e| e.path()))
         .filter(|res| {
@@ -105,336 +624,3262 @@ fn main() -> Result<()> {
         })
         .collect::<Result<Vec<PathBuf>, anyhow::Error>>()?;
     paths.sort_unstable();
+    Ok(paths)
+}
 
-    let total_crates = paths.len();
-    if total_crates == 0 {
-        return Ok(());
+// How the crates to lint are chosen from the target.
+struct CrateSelection<'a> {
+    // Globs the crate names must match. Empty to match all crates.
+    include: &'a [String],
+    // The time a file in the crate must be modified after.
+    changed_since: Option<SystemTime>,
+    // `None` unless a `--tags` file is given.
+    crate_tags: Option<&'a CrateTags>,
+    // Tags the crates must have one of. Empty to match all crates.
+    tag_filter: &'a [String],
+    dedup_versions: bool,
+    // The number of crates to pick at random and the seed used.
+    sample: Option<(usize, u64)>,
+}
+
+// The crate paths filtered by the selection, reporting what was filtered out. The crates of a git
+// target are the packages in its clone.
+fn select_crate_paths(
+    target: &Path,
+    clone: Option<&GitClone>,
+    manifest_name: &Path,
+    selection: &CrateSelection<'_>,
+) -> Result<Vec<PathBuf>> {
+    let mut paths = match clone {
+        Some(clone) => clone.package_paths(manifest_name)?,
+        None => list_crate_paths(target)?,
+    };
+    if let Some(crate_tags) = selection.crate_tags {
+        let untagged: Vec<_> = paths
+            .iter()
+            .map(|path| crate_name(path))
+            .filter(|name| !crate_tags.contains_key(name.as_ref()))
+            .collect();
+        if !untagged.is_empty() {
+            eprintln!(
+                "warning: {} crates have no tags: {}",
+                untagged.len(),
+                untagged.join(", ")
+            );
+        }
+        if !selection.tag_filter.is_empty() {
+            let total = paths.len();
+            paths.retain(|path| {
+                crate_tags
+                    .get(crate_name(path).as_ref())
+                    .is_some_and(|tags| tags.iter().any(|tag| selection.tag_filter.contains(tag)))
+            });
+            eprintln!("{} of {} crates match `--tag`", paths.len(), total);
+        }
+    }
+    let include = selection.include;
+    if !include.is_empty() {
+        let total = paths.len();
+        paths.retain(|path| {
+            let name = crate_name(path);
+            include.iter().any(|pattern| glob_match(pattern, &name))
+        });
+        eprintln!("{} of {} crates match `--include`", paths.len(), total);
+    }
+    if let Some(changed_since) = selection.changed_since {
+        let total = paths.len();
+        paths.retain(|path| newest_mtime(path).is_some_and(|mtime| mtime > changed_since));
+        eprintln!(
+            "{} of {} crates changed since `--changed-since`",
+            paths.len(),
+            total
+        );
     }
+    if selection.dedup_versions {
+        paths = dedup_crate_versions(&paths, manifest_name);
+    }
+    if let Some((count, seed)) = selection.sample {
+        let total = paths.len();
+        paths = sample_paths(paths, count, seed);
+        let names: Vec<_> = paths.iter().map(|path| crate_name(path)).collect();
+        eprintln!(
+            "Sampled {} of {} crates with `--seed {}`: {}",
+            paths.len(),
+            total,
+            seed,
+            names.join(", ")
+        );
+    }
+    Ok(paths)
+}
 
-    let cargo_target_dir = env::current_dir()?.join(target).join(CARGO_TARGET_DIR);
+// Crate dir name -> its tags
+type CrateTags = BTreeMap<String, Vec<String>>;
 
-    let mut build_failures = vec![];
-    let mut fix_failures = vec![];
+// Read the `--tags` file. Files ending in `.json` are JSON and any others are TOML.
+fn read_crate_tags(path: &Path) -> Result<CrateTags> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Reading tags file {}", path.display()))?;
+    let tags = if path.extension() == Some(OsStr::new("json")) {
+        serde_json::from_str(&contents).map_err(anyhow::Error::from)
+    } else {
+        toml::from_str(&contents).map_err(anyhow::Error::from)
+    };
+    tags.with_context(|| format!("Parsing tags file {}", path.display()))
+}
 
-    let mut warning_counts = BTreeMap::new();
-    let mut allow_counts: BTreeMap<Cow<'_, str>, _> = BTreeMap::new();
+// The time `--changed-since` refers to.
+fn changed_since_time(arg: &str) -> Result<SystemTime> {
+    if let Ok(secs) = arg.parse() {
+        return Ok(UNIX_EPOCH + Duration::from_secs(secs));
+    }
+    fs::metadata(arg)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| {
+            format!("`--changed-since {arg}` is neither a timestamp nor an existing file")
+        })
+}
 
-    {
-        let mut progress_bar = ProgressBar::new();
-        progress_bar.display_progress(total_crates, "Starting...");
+// The latest modification time of the files in a crate. A `target` dir in the crate is skipped as
+// building the crate outside of the tester changes it. `None` if the crate has no files.
+fn newest_mtime(path: &Path) -> Option<SystemTime> {
+    WalkDir::new(path)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() != 1 || entry.file_name() != "target")
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+        .max()
+}
 
-        for path in &paths {
-            let crate_name = crate_name(path);
+// Pick `count` of the paths at random, keeping their order. The same seed always picks the same
+// paths from the same list.
+fn sample_paths(mut paths: Vec<PathBuf>, count: usize, seed: u64) -> Vec<PathBuf> {
+    // SplitMix64 - good enough for sampling and needs no dependency
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+
+    // A partial Fisher-Yates shuffle of the indices
+    let mut indices: Vec<usize> = (0..paths.len()).collect();
+    let count = count.min(paths.len());
+    for i in 0..count {
+        let remaining = (indices.len() - i) as u64;
+        #[allow(clippy::cast_possible_truncation)]
+        let j = i + (next() % remaining) as usize;
+        indices.swap(i, j);
+    }
+    let mut picked = indices[..count].to_vec();
+    picked.sort_unstable();
+    picked
+        .into_iter()
+        .map(|i| std::mem::take(&mut paths[i]))
+        .collect()
+}
 
-            progress_bar.inc_progress(&crate_name);
-            if check_allows && !lints.is_empty() {
-                let count = check_for_allows(
-                    &mut progress_bar,
-                    &clippy_workspace,
-                    &cargo_target_dir,
-                    &lints,
-                    path,
-                    &crate_name,
-                )?;
-                if count > 0 {
-                    allow_counts.insert(crate_name.clone(), count);
+// The package name and version of a crate from its manifest, falling back to a `name-version`
+// dir name as made by `download_crates`. `None` if neither has a version.
+fn package_version(path: &Path, manifest_name: &Path) -> Option<(String, Version)> {
+    let manifest = fs::read_to_string(path.join(manifest_name))
+        .ok()
+        .and_then(|contents| toml::from_str::<toml::Value>(&contents).ok());
+    let package = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.get("package"));
+    let field = |key| package?.get(key)?.as_str();
+    if let (Some(name), Some(version)) = (field("name"), field("version")) {
+        if let Ok(version) = Version::parse(version) {
+            return Some((name.into(), version));
+        }
+    }
+
+    // Versions can contain `-` so each split is tried from the left
+    let dir_name = crate_name(path);
+    dir_name.match_indices('-').find_map(|(i, _)| {
+        let version = Version::parse(&dir_name[i + 1..]).ok()?;
+        Some((dir_name[..i].into(), version))
+    })
+}
+
+// Keep only the highest version of each package, reporting the crates skipped. Crates without a
+// version are all kept.
+fn dedup_crate_versions(paths: &[PathBuf], manifest_name: &Path) -> Vec<PathBuf> {
+    let versions: Vec<_> = paths
+        .iter()
+        .map(|path| package_version(path, manifest_name))
+        .collect();
+    // Package name -> index of the highest version
+    let mut newest: BTreeMap<&str, usize> = BTreeMap::new();
+    for (i, (name, version)) in versions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, v)| Some((i, v.as_ref()?)))
+    {
+        match newest.entry(name) {
+            Entry::Vacant(entry) => {
+                entry.insert(i);
+            }
+            Entry::Occupied(mut entry) => {
+                let (_, newest_version) = versions[*entry.get()].as_ref().expect("has version");
+                if version > newest_version {
+                    entry.insert(i);
                 }
             }
+        }
+    }
 
-            let result = run_lint(
-                &mut progress_bar,
-                &clippy_workspace,
-                &cargo_target_dir,
-                &lints[..],
-                path,
-                fix_dir.as_deref(),
-            )?;
-            match result {
-                LintResult::InvalidCrate => {
+    let mut kept = vec![];
+    for (i, (path, version)) in paths.iter().zip(&versions).enumerate() {
+        match version {
+            Some((name, _)) if newest[name.as_str()] != i => {
+                eprintln!(
+                    "Skipping `{}` - `{}` is a newer version",
+                    crate_name(path),
+                    crate_name(&paths[newest[name.as_str()]])
+                );
+            }
+            _ => kept.push(path.clone()),
+        }
+    }
+    kept
+}
+
+const CLIPPY_SOURCE_VAR: &str = "CLIPPY_SOURCE";
+
+enum ClippySource {
+    // A Clippy workspace to build
+    Path(PathBuf),
+    // The toolchain whose installed Clippy is used (`--use-installed`)
+    Installed(String),
+}
+
+impl fmt::Display for ClippySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClippySource::Path(path) => write!(f, "{}", path.display()),
+            ClippySource::Installed(toolchain) => write!(f, "installed with `{toolchain}`"),
+        }
+    }
+}
+
+fn load_clippy(source: &ClippySource, options: &ClippyBuildOptions) -> Result<ClippyWorkspace> {
+    match source {
+        ClippySource::Path(path) => prepare_clippy(
+            &env::current_dir()?.join(path),
+            options,
+            || eprintln!("Compiling Clippy"),
+            || eprintln!("Building Clippy failed due to a network error, retrying"),
+        ),
+        ClippySource::Installed(toolchain) => installed_clippy(toolchain, options),
+    }
+}
+
+// Builds the Clippy workspace for `--compare-workspaces`. It's skipped if it can't be built as the
+// results for the first workspace are still useful.
+fn load_compared_clippy(
+    path: &Path,
+    options: &ClippyBuildOptions,
+) -> Result<Option<ClippyWorkspace>> {
+    let options = ClippyBuildOptions {
+        keep_going: true,
+        ..options.clone()
+    };
+    match load_clippy(&ClippySource::Path(path.into()), &options) {
+        Ok(workspace) => Ok(Some(workspace)),
+        Err(err) => match err.downcast_ref::<ClippyBuildFailed>() {
+            Some(failed) => {
+                eprintln!("{failed}");
+                eprintln!("Not comparing workspaces");
+                Ok(None)
+            }
+            None => Err(err),
+        },
+    }
+}
+
+// Split the positional args into the Clippy source, the target and the lints.
+// argh only allows the last positional to be optional so the Clippy source can't be omitted
+// without this. As the lints make the number of args vary, `env_source` is only used when a single
+// arg (the target) is given.
+fn split_positional_args(
+    args: Vec<String>,
+    env_source: Option<PathBuf>,
+) -> Result<(PathBuf, PathBuf, Vec<String>)> {
+    let mut args = args.into_iter();
+    let source = take_clippy_source(&mut args, env_source, 2)?;
+    let target = args
+        .next()
+        .context("Missing the path to the directory containing crates")?;
+    Ok((source, PathBuf::from(target), args.collect()))
+}
+
+// Split the positional args of `--list-lints` into the Clippy source and the lint groups.
+// `env_source` is only used when no args are given.
+fn split_list_lints_args(
+    args: Vec<String>,
+    env_source: Option<PathBuf>,
+) -> Result<(PathBuf, Vec<String>)> {
+    let mut args = args.into_iter();
+    let source = take_clippy_source(&mut args, env_source, 1)?;
+    Ok((source, args.collect()))
+}
+
+// Take the Clippy source from the front of the positional args. `env_source` is used instead when
+// fewer than `required` args are given. Otherwise the first arg is taken even if it isn't a Clippy
+// workspace so the error is reported when the workspace is loaded.
+fn take_clippy_source(
+    args: &mut std::vec::IntoIter<String>,
+    env_source: Option<PathBuf>,
+    required: usize,
+) -> Result<PathBuf> {
+    match env_source {
+        Some(env_source) if args.len() < required => Ok(env_source),
+        _ => args.next().map(PathBuf::from).with_context(|| {
+            format!(
+                "Missing the path to the Clippy source. Pass it as the first argument or set \
+                `{CLIPPY_SOURCE_VAR}`"
+            )
+        }),
+    }
+}
+
+// Print a table of the lints Clippy provides, only including the lints in `groups` if any are
+// given.
+fn print_lint_catalog(clippy_workspace: &ClippyWorkspace, groups: &[String]) -> Result<()> {
+    let groups: BTreeSet<_> = groups
+        .iter()
+        .map(|group| {
+            let group = group.to_lowercase().replace('-', "_");
+            if group.starts_with("clippy::") {
+                group
+            } else {
+                format!("clippy::{group}")
+            }
+        })
+        .collect();
+
+    let lints = clippy_workspace.available_lints()?;
+    let missing: Vec<_> = groups
+        .iter()
+        .filter(|&group| !lints.iter().any(|lint| lint.group.as_ref() == Some(group)))
+        .map(|group| format!("`{group}`"))
+        .collect();
+    if !missing.is_empty() {
+        bail!("Lint groups not found: {}", missing.join(", "));
+    }
+
+    let columns = [
+        Column::new("Lint", Alignment::Left),
+        Column::new("Group", Alignment::Left),
+        Column::new("Default", Alignment::Left),
+    ];
+    let rows: Vec<_> = lints
+        .iter()
+        .filter(|lint| {
+            groups.is_empty()
+                || lint
+                    .group
+                    .as_ref()
+                    .is_some_and(|group| groups.contains(group))
+        })
+        .map(|lint| {
+            vec![
+                Cell::new(lint.name.as_str()),
+                Cell::new(lint.group.as_deref().unwrap_or("-")),
+                Cell::new(lint.default_level.as_str()),
+            ]
+        })
+        .collect();
+    print_multi_column_table(&columns, &rows, stdout().lock())?;
+    println!();
+    println!("Total: {}", rows.len());
+    Ok(())
+}
+
+#[allow(clippy::too_many_lines)]
+fn main() -> Result<()> {
+    let start = Instant::now();
+    let Args {
+        paths_and_lints,
+        use_installed,
+        fix: fix_dir,
+        force,
+        keep_fix_artifacts,
+        compare_fix,
+        verbose_fix,
+        check_allows,
+        build_retries,
+        clippy_build_retries,
+        lints_b: lint_b_args,
+        compare_workspaces,
+        format,
+        cargo_arg: cargo_args,
+        manifest_name,
+        extra_crate_root,
+        env: env_args,
+        rustflags,
+        cfg: cfgs,
+        progress_refresh_ms,
+        progress,
+        progress_template,
+        count_deps,
+        all_targets,
+        by_target,
+        include,
+        changed_since,
+        tags,
+        tag,
+        dedup_versions,
+        sample,
+        seed,
+        wrap,
+        count_only,
+        max_warnings_per_crate,
+        exclude_macro_hits,
+        allow_code: mut allow_codes,
+        script: script_path,
+        output: output_path,
+        compress,
+        split_output,
+        json_diagnostics_dir,
+        git_ref,
+        keep_clone,
+        warmup,
+        per_crate_target_dir,
+        keep_target,
+        quiet_invalid,
+        check_determinism,
+        no_incremental,
+        mem,
+        density,
+        locked,
+        generate_lockfile,
+        offline,
+        context_level,
+        show_groups,
+        common_messages,
+        span_stats,
+        stats,
+        list_crates,
+        list_lints,
+        confirm_large_group,
+        target_triple,
+        sort,
+        from_json,
+    } = argh::from_env();
+
+    if compress && output_path.is_none() {
+        bail!("`--compress` requires `--output`");
+    }
+    if compare_fix && fix_dir.is_none() {
+        bail!("`--compare-fix` requires `--fix`");
+    }
+    if keep_target && !per_crate_target_dir {
+        bail!("`--keep-target` requires `--per-crate-target-dir`");
+    }
+    if warmup && per_crate_target_dir {
+        bail!("`--warmup` can't be used with `--per-crate-target-dir` as nothing is shared");
+    }
+    if compare_workspaces.is_some() && !lint_b_args.is_empty() {
+        bail!("`--compare-workspaces` can't be used with `--lints-b`");
+    }
+
+    let build_options = ClippyBuildOptions {
+        offline,
+        build_retries: clippy_build_retries,
+        // A single workspace is useless if it can't be built.
+        keep_going: false,
+    };
+
+    if list_lints {
+        let (source, groups) = if let Some(toolchain) = use_installed {
+            (ClippySource::Installed(toolchain), paths_and_lints)
+        } else {
+            let (source, groups) = split_list_lints_args(
+                paths_and_lints,
+                env::var_os(CLIPPY_SOURCE_VAR).map(PathBuf::from),
+            )?;
+            (ClippySource::Path(source), groups)
+        };
+        let clippy_workspace = load_clippy(&source, &build_options)?;
+        return print_lint_catalog(&clippy_workspace, &groups);
+    }
+
+    if let Some(from_json) = &from_json {
+        let saved_run = read_saved_run(from_json)?;
+        let summary = saved_run.summary(sort);
+        return match &output_path {
+            Some(output_path) => write_summary_file(&summary, output_path, compress),
+            None => summary.write(stdout().lock()),
+        };
+    }
+
+    let (source, target, lint_args) = if let Some(toolchain) = use_installed {
+        let mut args = paths_and_lints.into_iter();
+        let target = args
+            .next()
+            .context("Missing the path to the directory containing crates")?;
+        (
+            ClippySource::Installed(toolchain),
+            PathBuf::from(target),
+            args.collect(),
+        )
+    } else {
+        let (source, target, lint_args) = split_positional_args(
+            paths_and_lints,
+            env::var_os(CLIPPY_SOURCE_VAR).map(PathBuf::from),
+        )?;
+        (ClippySource::Path(source), target, lint_args)
+    };
+
+    if allow_codes.is_empty() {
+        allow_codes = DEFAULT_ALLOW_CODES.iter().map(|&c| c.into()).collect();
+    }
+
+    let mut config = RunConfig {
+        build_retries,
+        wrap,
+        count_only,
+        max_warnings_per_crate,
+        exclude_macro_hits,
+        allow_codes,
+        common_messages,
+        span_stats,
+        target_triple,
+        all_targets,
+        keep_fix_artifacts,
+        verbose_fix,
+        count_deps,
+        cargo_args,
+        envs: env_args
+            .iter()
+            .map(|arg| parse_env_arg(arg))
+            .collect::<Result<_>>()?,
+        manifest_name,
+        extra_crate_roots: extra_crate_root,
+        locked,
+        generate_lockfile,
+        rustflags: join_rustflags(rustflags, &cfgs),
+        no_incremental,
+        collect_diagnostics: split_output.is_some() || format == OutputFormat::Dashboard,
+        by_target,
+        context_level,
+        json_diagnostics_dir,
+        lint_groups: None,
+        side_effect_lints: None,
+        location_tag: None,
+    };
+
+    if let Some(dir) = &config.json_diagnostics_dir {
+        fs::create_dir_all(dir).with_context(|| format!("Creating {}", dir.display()))?;
+    }
+
+    if config.rustflags.is_some() && config.envs.iter().any(|(key, _)| key == "RUSTFLAGS") {
+        bail!("`--env RUSTFLAGS=...` conflicts with `--rustflags` and `--cfg`");
+    }
+    if config.no_incremental
+        && config
+            .envs
+            .iter()
+            .any(|(key, _)| key == "CARGO_INCREMENTAL")
+    {
+        bail!("`--env CARGO_INCREMENTAL=...` conflicts with `--no-incremental`");
+    }
+
+    for arg in &config.cargo_args {
+        if arg == "--"
+            || RESERVED_CARGO_ARGS
+                .iter()
+                .any(|reserved| arg == reserved || arg.starts_with(&format!("{reserved}=")))
+        {
+            bail!("`--cargo-arg {arg}` conflicts with the arguments set by clippy_lint_tester");
+        }
+        if config.locked && arg == "--locked" {
+            bail!("`--cargo-arg --locked` is already passed by `--locked`");
+        }
+    }
+
+    if seed.is_some() && sample.is_none() {
+        bail!("`--seed` requires `--sample`");
+    }
+    if !tag.is_empty() && tags.is_none() {
+        bail!("`--tag` requires `--tags`");
+    }
+    // Read once before selecting the crates as the tags are needed again for the summary.
+    let crate_tags = tags.as_deref().map(read_crate_tags).transpose()?;
+    let selection = CrateSelection {
+        include: &include,
+        // Read before linting as touching the crate roots changes their modification time.
+        changed_since: changed_since
+            .as_deref()
+            .map(changed_since_time)
+            .transpose()?,
+        crate_tags: crate_tags.as_ref(),
+        tag_filter: &tag,
+        dedup_versions,
+        sample: sample.map(|count| {
+            let seed = seed.unwrap_or_else(|| {
+                // Only needs to differ between runs
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                now.as_secs() ^ (u64::from(now.subsec_nanos()) << 32) ^ u64::from(process::id())
+            });
+            (count, seed)
+        }),
+    };
+
+    if !lint_b_args.is_empty() && lint_args.is_empty() {
+        bail!("`--lints-b` requires lints to compare against");
+    }
+
+    for name in lint_args.iter().chain(&lint_b_args) {
+        if name.is_empty()
+            || name
+                .strip_prefix("clippy::")
+                .unwrap_or(name)
+                .chars()
+                .any(|c| !c.is_ascii_alphanumeric() && c != '_' && c != '-')
+        {
+            bail!("Invalid lint name");
+        }
+    }
+
+    // Dropping the clone removes it, so it's held until linting is done.
+    let mut clone = if let Some(url) = target.to_str().filter(|target| is_git_target(target)) {
+        eprintln!("Cloning `{url}`");
+        let dir = env::temp_dir().join(format!("clippy_lint_tester-{}", process::id()));
+        Some(GitClone::new(url, git_ref.as_deref(), dir)?)
+    } else {
+        if git_ref.is_some() {
+            bail!("`--git-ref` requires the target to be a git repository");
+        }
+        if keep_clone {
+            bail!("`--keep-clone` requires the target to be a git repository");
+        }
+        if !target.exists() {
+            bail!("Target path `{}` does not exist", target.display())
+        }
+        None
+    };
+    if let Some(clone) = &mut clone {
+        if keep_clone {
+            clone.keep();
+            eprintln!("Keeping the clone at `{}`", clone.path().display());
+        }
+    }
+    // The target dir goes next to the clone rather than in the repository. Otherwise the path is
+    // made absolute once so the crates and the target dir are always found from the same path.
+    let target = match &clone {
+        Some(clone) => clone.dir().to_owned(),
+        None => fs::canonicalize(&target)
+            .with_context(|| format!("Resolving target path `{}`", target.display()))?,
+    };
+
+    if list_crates {
+        let paths = select_crate_paths(&target, clone.as_ref(), &config.manifest_name, &selection)?;
+        for path in &paths {
+            println!("- {}", crate_name(path));
+        }
+        if !paths.is_empty() {
+            println!();
+        }
+        println!("Total: {}", paths.len());
+        return Ok(());
+    }
+
+    if let Some(fix_dir) = &fix_dir {
+        match ensure_empty_dir_or_clear(fix_dir, force)? {
+            EnsureEmptyDirOutcome::Created => println!("Fix directory created"),
+            EnsureEmptyDirOutcome::Cleared => println!("Fix directory cleared"),
+            EnsureEmptyDirOutcome::NonEmpty => {
+                bail!("Fix directory exists and not empty - use `--force` to clear it")
+            }
+            EnsureEmptyDirOutcome::Empty => {}
+        }
+    }
+
+    let clippy_workspace = load_clippy(&source, &build_options)?;
+    let clippy_workspace_b = match &compare_workspaces {
+        Some(path) => load_compared_clippy(path, &build_options)?,
+        None => None,
+    };
+
+    if !lint_args.is_empty() {
+        eprintln!("Checking lint names");
+    }
+    let lints = check_and_format_lint_names(&clippy_workspace, &lint_args, confirm_large_group)?;
+    let lints_b =
+        check_and_format_lint_names(&clippy_workspace, &lint_b_args, confirm_large_group)?;
+    if show_groups {
+        config.lint_groups = Some(
+            clippy_workspace
+                .available_lints()?
+                .into_iter()
+                .filter_map(|lint| {
+                    let group = lint.group?;
+                    let group = group.strip_prefix("clippy::").unwrap_or(&group).into();
+                    Some((lint.name, group))
+                })
+                .collect(),
+        );
+    }
+    if compare_fix {
+        // The tested lints are expected to change
+        config.side_effect_lints = Some(
+            clippy_workspace
+                .available_lints()?
+                .into_iter()
+                .filter(|lint| {
+                    lint.default_level != LintLevel::Allow && !lints.contains(&lint.name)
+                })
+                .map(|lint| lint.name)
+                .collect(),
+        );
+    }
+
+    eprintln!("Linting crates");
+    let paths = select_crate_paths(&target, clone.as_ref(), &config.manifest_name, &selection)?;
+
+    let total_crates = paths.len();
+    if total_crates == 0 {
+        return Ok(());
+    }
+
+    let cargo_target_dir = target.join(CARGO_TARGET_DIR);
+    if !per_crate_target_dir {
+        check_rustflags(&cargo_target_dir, config.rustflags.as_deref())?;
+    }
+
+    // Ctrl-C stops the run after the current crate so the results so far are still reported.
+    interrupt::install_handler(|stop| match stop {
+        interrupt::Stop::AfterCurrent => {
+            eprintln!("\nStopping after the current crate - press Ctrl-C again to stop now");
+        }
+        interrupt::Stop::Now => eprintln!("\nStopping now"),
+    })?;
+
+    if warmup {
+        warm_up(
+            &clippy_workspace,
+            &cargo_target_dir,
+            &paths,
+            &config,
+            progress,
+        )?;
+    }
+
+    let mut summary = Summary {
+        allow_counts: check_allows.then(BTreeMap::new),
+        fix_failures: fix_dir.as_ref().map(|_| vec![]),
+        fix_side_effects: compare_fix.then(Vec::new),
+        peak_rss: mem.then(Vec::new),
+        line_counts: density.then(BTreeMap::new),
+        invalid_crates: quiet_invalid.then_some(0),
+        message_counts: common_messages.then(BTreeMap::new),
+        span_sizes: span_stats.then(Vec::new),
+        macro_hits: exclude_macro_hits.then(BTreeMap::new),
+        target_counts: by_target.then(BTreeMap::new),
+        tag_totals: crate_tags.as_ref().map(|_| BTreeMap::new()),
+        nondeterministic: check_determinism.then(Vec::new),
+        workspace_comparison: clippy_workspace_b
+            .as_ref()
+            .zip(compare_workspaces.as_ref())
+            .map(|(_, path_b)| {
+                WorkspaceComparison::new(source.to_string(), path_b.display().to_string())
+            }),
+        ..Summary::new(&lints, &lints_b, sort)
+    };
+    // Only the diagnostics of the tested lints are saved.
+    let config_b = RunConfig {
+        json_diagnostics_dir: None,
+        ..config.clone()
+    };
+    // The warnings of both workspaces are printed so the second's are told apart.
+    let config_workspace_b = RunConfig {
+        location_tag: Some("B"),
+        ..config_b.clone()
+    };
+    let mut script_commands = vec![];
+    // Lint -> the text of each warning
+    let mut split_diagnostics: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut dashboard = (format == OutputFormat::Dashboard)
+        .then(|| DashboardRecord::new(clippy_workspace.version().ok(), &lints));
+
+    {
+        let mut progress_bar = ProgressBar::new(progress);
+        progress_bar.set_refresh_interval(Duration::from_millis(progress_refresh_ms));
+        if let Some(template) = progress_template {
+            progress_bar.set_template(template);
+        }
+        if format != OutputFormat::Human {
+            // Keep stdout for the JSON.
+            progress_bar.print_to_stderr();
+        }
+        progress_bar.display_progress(total_crates, "Starting...");
+
+        // A per-crate target dir is removed at the start of the next iteration as the loop body
+        // can `continue`.
+        let mut finished_target_dir: Option<PathBuf> = None;
+        for (linted, path) in paths.iter().enumerate() {
+            if interrupt::stop_requested() {
+                summary.stopped = Some((linted, total_crates));
+                break;
+            }
+            if let Some(dir) = finished_target_dir.take() {
+                remove_target_dir(&dir)?;
+            }
+            let crate_name = crate_name(path);
+            let cargo_target_dir = if per_crate_target_dir {
+                let dir = cargo_target_dir.join(&*crate_name);
+                if !keep_target {
+                    finished_target_dir = Some(dir.clone());
+                }
+                Cow::Owned(dir)
+            } else {
+                Cow::Borrowed(cargo_target_dir.as_path())
+            };
+
+            progress_bar.inc_progress(&crate_name);
+            if let Some(allow_counts) = summary.allow_counts.as_mut().filter(|_| !lints.is_empty())
+            {
+                let count = check_for_allows(
+                    &mut progress_bar,
+                    &clippy_workspace,
+                    &cargo_target_dir,
+                    &lints,
+                    path,
+                    &crate_name,
+                    &config,
+                )?;
+                if count > 0 {
+                    allow_counts.insert(crate_name.clone(), count);
+                }
+            }
+
+            let result = run_lint(
+                &mut progress_bar,
+                &clippy_workspace,
+                &cargo_target_dir,
+                &lints[..],
+                path,
+                fix_dir.as_deref(),
+                &config,
+            )?;
+            // The crate's commands were interrupted so the result is meaningless.
+            if interrupt::stop_now_requested() {
+                break;
+            }
+            let failed_or_warned = match &result {
+                LintResult::BuildFailed { .. } => true,
+                LintResult::Success { stats, .. } => stats.warning_count > 0,
+                LintResult::InvalidCrate
+                | LintResult::MissingTarget
+                | LintResult::LockfileFailed
+                | LintResult::NothingCompiled => false,
+            };
+            if script_path.is_some() && failed_or_warned {
+                script_commands.push(make_lint_command(
+                    &clippy_workspace,
+                    &cargo_target_dir,
+                    path,
+                    &lints,
+                    &config,
+                ));
+            }
+            if format == OutputFormat::JsonLines {
+                println!(
+                    "{}",
+                    serde_json::to_string(&JsonLine::Crate(CrateRecord::new(
+                        &crate_name,
+                        &result
+                    )))?
+                );
+            }
+            progress_bar.record_outcome(match &result {
+                LintResult::Success { stats, .. } if stats.warning_count == 0 => Outcome::Succeeded,
+                LintResult::Success { .. } => Outcome::Warned,
+                LintResult::InvalidCrate
+                | LintResult::BuildFailed { .. }
+                | LintResult::MissingTarget
+                | LintResult::LockfileFailed
+                | LintResult::NothingCompiled => Outcome::Failed,
+            });
+            if let (Some(clippy_workspace_b), Some(comparison)) =
+                (&clippy_workspace_b, &mut summary.workspace_comparison)
+            {
+                // Linted even if the crate failed with the first workspace to find the crates
+                // fixed by the second.
+                let result_b = run_lint(
+                    &mut progress_bar,
+                    clippy_workspace_b,
+                    &cargo_target_dir.join(WORKSPACE_B_TARGET_DIR),
+                    &lints[..],
+                    path,
+                    None,
+                    &config_workspace_b,
+                )?;
+                if let LintResult::Success { stats, .. } = &result {
+                    add_counts(&mut comparison.lint_counts_a, &stats.lint_counts);
+                }
+                match result_b {
+                    LintResult::Success { stats, .. } => {
+                        add_counts(&mut comparison.lint_counts_b, &stats.lint_counts);
+                        if stats.warning_count > 0 {
+                            summary
+                                .warning_counts_b
+                                .insert(crate_name.clone(), stats.warning_count);
+                        }
+                    }
+                    LintResult::BuildFailed { .. } => {
+                        summary.build_failures_b.push(crate_name.clone());
+                    }
+                    LintResult::InvalidCrate
+                    | LintResult::MissingTarget
+                    | LintResult::LockfileFailed
+                    | LintResult::NothingCompiled => {}
+                }
+            }
+            match result {
+                LintResult::InvalidCrate => {
+                    if let Some(invalid_crates) = &mut summary.invalid_crates {
+                        *invalid_crates += 1;
+                    } else {
+                        progress_bar.println(
+                            &crate_name,
+                            &format_args!("{} - not a crate", path.display()),
+                        );
+                    }
+                }
+                LintResult::BuildFailed { ice } => {
+                    if ice {
+                        summary.ice_count += 1;
+                    }
+                    summary.build_failures.push(crate_name);
+                }
+                LintResult::MissingTarget => {
+                    summary.missing_target.push(crate_name);
+                }
+                LintResult::LockfileFailed => {
+                    summary.lockfile_failures.push(crate_name);
+                }
+                LintResult::NothingCompiled => {
+                    summary.nothing_compiled.push(crate_name);
+                }
+                LintResult::Success {
+                    stats,
+                    fix_errors,
+                    fix_side_effects,
+                    all_targets_failed,
+                } => {
+                    let CrateStats {
+                        warning_count,
+                        lint_counts,
+                        peak_rss,
+                        messages,
+                        span_sizes,
+                        macro_hits,
+                        diagnostics,
+                        target_counts,
+                        timing,
+                    } = *stats;
+                    summary.linted_count += 1;
+                    summary.timing.dependencies += timing.dependencies;
+                    summary.timing.lint += timing.lint;
+                    if let (Some(tag_totals), Some(tags)) = (
+                        &mut summary.tag_totals,
+                        crate_tags
+                            .as_ref()
+                            .and_then(|crate_tags| crate_tags.get(crate_name.as_ref())),
+                    ) {
+                        for tag in tags {
+                            tag_totals
+                                .entry(tag.clone())
+                                .or_default()
+                                .add_crate(warning_count, &lint_counts);
+                        }
+                    }
+                    if let Some(dashboard) = &mut dashboard {
+                        dashboard.add_crate(&lint_counts, &diagnostics);
+                    }
+                    if split_output.is_some() {
+                        for (lint, text) in diagnostics {
+                            split_diagnostics.entry(lint).or_default().push(text);
+                        }
+                    }
+                    if all_targets_failed {
+                        summary.all_targets_failures.push(crate_name.clone());
+                    }
+                    if let Some(peak_rss_list) = &mut summary.peak_rss {
+                        peak_rss_list.push((crate_name.clone(), peak_rss));
+                    }
+                    if let Some(message_counts) = &mut summary.message_counts {
+                        for message in messages {
+                            *message_counts.entry(message).or_insert(0) += 1;
+                        }
+                    }
+                    if let Some(summary_span_sizes) = &mut summary.span_sizes {
+                        summary_span_sizes.extend(span_sizes);
+                    }
+                    if let Some(summary_macro_hits) = &mut summary.macro_hits {
+                        if macro_hits > 0 {
+                            summary_macro_hits.insert(crate_name.clone(), macro_hits);
+                        }
+                    }
+                    if let Some(nondeterministic) = &mut summary.nondeterministic {
+                        if let Some(rerun_count) = relint_warning_count(
+                            &mut progress_bar,
+                            &clippy_workspace,
+                            &cargo_target_dir,
+                            &lints,
+                            path,
+                            &config,
+                        )?
+                        .filter(|&count| count != warning_count)
+                        {
+                            progress_bar.println(
+                                &crate_name,
+                                format_args!(
+                                    "{crate_name} - warning count differs between runs: \
+                                    {warning_count} then {rerun_count}"
+                                ),
+                            );
+                            nondeterministic.push((crate_name.clone(), warning_count, rerun_count));
+                        }
+                    }
+
+                    if !lints_b.is_empty() {
+                        match run_lint(
+                            &mut progress_bar,
+                            &clippy_workspace,
+                            &cargo_target_dir,
+                            &lints_b[..],
+                            path,
+                            None,
+                            &config_b,
+                        )? {
+                            LintResult::Success { stats, .. } => {
+                                if stats.warning_count > 0 {
+                                    summary
+                                        .warning_counts_b
+                                        .insert(crate_name.clone(), stats.warning_count);
+                                }
+                            }
+                            // The crate built with the first lints so their results are still
+                            // recorded.
+                            LintResult::BuildFailed { .. } => {
+                                summary.build_failures_b.push(crate_name.clone());
+                            }
+                            LintResult::InvalidCrate
+                            | LintResult::MissingTarget
+                            | LintResult::LockfileFailed
+                            | LintResult::NothingCompiled => {}
+                        }
+                    }
+
+                    if warning_count > 0 {
+                        if let Some(line_counts) = &mut summary.line_counts {
+                            match count_source_lines(path) {
+                                Ok(line_count) => {
+                                    line_counts.insert(crate_name.clone(), line_count);
+                                }
+                                Err(err) => progress_bar.println(
+                                    &crate_name,
+                                    format_args!("{crate_name} - failed to count lines: {err:#}"),
+                                ),
+                            }
+                        }
+                        if let (Some(fix_failures), Some(fix_errors)) =
+                            (&mut summary.fix_failures, fix_errors)
+                        {
+                            fix_failures.push((crate_name.clone(), fix_errors));
+                        }
+                        if let Some(summary_side_effects) = &mut summary.fix_side_effects {
+                            if !fix_side_effects.is_empty() {
+                                summary_side_effects.push((crate_name.clone(), fix_side_effects));
+                            }
+                        }
+                        if let Some(summary_target_counts) = &mut summary.target_counts {
+                            summary_target_counts.insert(crate_name.clone(), target_counts);
+                        }
+                        summary.warning_counts.insert(crate_name, warning_count);
+                    }
+                }
+            }
+        }
+        if let Some(dir) = finished_target_dir {
+            remove_target_dir(&dir)?;
+        }
+    }
+
+    if interrupt::stop_now_requested() {
+        // `process::exit` doesn't run destructors.
+        drop(clone);
+        process::exit(INTERRUPTED_EXIT_CODE);
+    }
+
+    if stats {
+        summary.stats = Some(RunStats::new(&summary, total_crates, start.elapsed()));
+    }
+    if let Some(script_path) = &script_path {
+        write_script(script_path, &clippy_workspace, &script_commands)?;
+    }
+    if let Some(split_output) = &split_output {
+        write_split_output(
+            split_output,
+            &lints,
+            &split_diagnostics,
+            config.lint_groups.as_ref(),
+        )?;
+    }
+
+    match &output_path {
+        Some(output_path) => write_summary_file(&summary, output_path, compress)?,
+        None if format != OutputFormat::Human => {}
+        None => summary.write(stdout().lock())?,
+    }
+    if let Some(dashboard) = &dashboard {
+        println!("{}", serde_json::to_string(dashboard)?);
+    }
+    if format == OutputFormat::JsonLines {
+        println!(
+            "{}",
+            serde_json::to_string(&summary.to_record(total_crates, &config))?
+        );
+    }
+
+    if summary.stopped.is_some() {
+        // `process::exit` doesn't run destructors.
+        drop(clone);
+        process::exit(INTERRUPTED_EXIT_CODE);
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum CrateStatus {
+    InvalidCrate,
+    BuildFailed,
+    MissingTarget,
+    LockfileFailed,
+    NothingCompiled,
+    Success,
+}
+
+// A `--format jsonl` line for a crate. The fields are in alphabetical order to match the order
+// of the other JSON objects.
+#[derive(Serialize, Deserialize)]
+struct CrateRecord {
+    #[serde(rename = "crate")]
+    crate_name: String,
+    // Lint name -> warning count. `None` if the crate wasn't linted.
+    lints: Option<BTreeMap<String, usize>>,
+    status: CrateStatus,
+    warning_count: usize,
+}
+
+impl CrateRecord {
+    fn new(crate_name: &str, result: &LintResult) -> Self {
+        let status = match result {
+            LintResult::InvalidCrate => CrateStatus::InvalidCrate,
+            LintResult::BuildFailed { .. } => CrateStatus::BuildFailed,
+            LintResult::MissingTarget => CrateStatus::MissingTarget,
+            LintResult::LockfileFailed => CrateStatus::LockfileFailed,
+            LintResult::NothingCompiled => CrateStatus::NothingCompiled,
+            LintResult::Success { .. } => CrateStatus::Success,
+        };
+        let (warning_count, lints) = match result {
+            LintResult::Success { stats, .. } => {
+                (stats.warning_count, Some(stats.lint_counts.clone()))
+            }
+            _ => (0, None),
+        };
+        CrateRecord {
+            crate_name: crate_name.into(),
+            lints,
+            status,
+            warning_count,
+        }
+    }
+}
+
+// The version of `DashboardRecord`. Bumped when a field is removed or changes meaning so the
+// dashboard can tell the formats apart. Adding a field doesn't need a bump.
+const DASHBOARD_SCHEMA_VERSION: u32 = 1;
+
+// The number of example warnings in the dashboard for each lint.
+const DASHBOARD_EXAMPLES: usize = 3;
+
+// The `--format dashboard` output. Unlike `--format jsonl` it's by lint rather than by crate. The
+// fields are in alphabetical order to match the other JSON objects.
+#[derive(Serialize, Deserialize)]
+struct DashboardRecord {
+    // The output of `clippy-driver --version`. `None` if it couldn't be run.
+    clippy_version: Option<String>,
+    // The number of crates linted
+    crates: usize,
+    // Every tested lint, including those without warnings, by name
+    lints: Vec<DashboardLint>,
+    schema_version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DashboardLint {
+    // The number of crates with warnings
+    crates: usize,
+    // The first `DASHBOARD_EXAMPLES` warnings, each as its location followed by the rendered
+    // diagnostic
+    examples: Vec<String>,
+    name: String,
+    // The total over every crate
+    warnings: usize,
+}
+
+impl DashboardRecord {
+    fn new(clippy_version: Option<String>, lints: &[String]) -> Self {
+        let mut lints: Vec<_> = lints
+            .iter()
+            .map(|name| DashboardLint {
+                crates: 0,
+                examples: vec![],
+                name: name.clone(),
+                warnings: 0,
+            })
+            .collect();
+        lints.sort_by(|a, b| a.name.cmp(&b.name));
+        DashboardRecord {
+            clippy_version,
+            crates: 0,
+            lints,
+            schema_version: DASHBOARD_SCHEMA_VERSION,
+        }
+    }
+
+    // Add the results of a linted crate.
+    fn add_crate(
+        &mut self,
+        lint_counts: &BTreeMap<String, usize>,
+        diagnostics: &[(String, String)],
+    ) {
+        self.crates += 1;
+        for lint in &mut self.lints {
+            let count: usize = lint_counts
+                .iter()
+                .filter(|(code, _)| lint_code_matches(code, &lint.name))
+                .map(|(_, count)| count)
+                .sum();
+            if count > 0 {
+                lint.crates += 1;
+                lint.warnings += count;
+            }
+            let room = DASHBOARD_EXAMPLES.saturating_sub(lint.examples.len());
+            lint.examples.extend(
+                diagnostics
+                    .iter()
+                    .filter(|(code, _)| lint_code_matches(code, &lint.name))
+                    .take(room)
+                    .map(|(_, text)| text.clone()),
+            );
+        }
+    }
+}
+
+// The totals of a run for `--stats`. The fields are in alphabetical order to match the order of
+// the other JSON objects.
+#[derive(Serialize, Deserialize, Clone)]
+struct RunStats {
+    build_failures: usize,
+    crates: usize,
+    // The part of linting the crates spent building their dependencies. Missing from older runs.
+    #[serde(default)]
+    dependency_build_secs: f64,
+    duration_secs: f64,
+    ices: usize,
+    linted: usize,
+    // The rest of the time linting the crates. Missing from older runs.
+    #[serde(default)]
+    lint_secs: f64,
+    // The crate with the most warnings. `None` if no crate has warnings.
+    max_warnings: Option<CrateWarnings>,
+    // Per linted crate, including those without warnings
+    mean_warnings: f64,
+    median_warnings: f64,
+    warnings: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CrateWarnings {
+    #[serde(rename = "crate")]
+    crate_name: String,
+    count: usize,
+}
+
+impl RunStats {
+    fn new(summary: &Summary<'_>, total_crates: usize, duration: Duration) -> Self {
+        // Only crates with warnings are in the counts.
+        let mut counts = vec![
+            0;
+            summary
+                .linted_count
+                .saturating_sub(summary.warning_counts.len())
+        ];
+        counts.extend(summary.warning_counts.values().copied());
+        counts.sort_unstable();
+        let warnings = counts.iter().sum::<usize>();
+
+        // The first by name on a tie
+        let max_warnings = summary
+            .warning_counts
+            .iter()
+            .fold(
+                None,
+                |max: Option<(&Cow<'_, str>, usize)>, (name, &count)| match max {
+                    Some((_, max_count)) if max_count >= count => max,
+                    _ => Some((name, count)),
+                },
+            )
+            .map(|(name, count)| CrateWarnings {
+                crate_name: name.to_string(),
+                count,
+            });
+
+        #[allow(clippy::cast_precision_loss)]
+        let mean_warnings = if counts.is_empty() {
+            0.0
+        } else {
+            warnings as f64 / counts.len() as f64
+        };
+
+        RunStats {
+            build_failures: summary.build_failures.len(),
+            crates: total_crates,
+            dependency_build_secs: summary.timing.dependencies.as_secs_f64(),
+            duration_secs: duration.as_secs_f64(),
+            ices: summary.ice_count,
+            linted: summary.linted_count,
+            lint_secs: summary.timing.lint.as_secs_f64(),
+            max_warnings,
+            mean_warnings,
+            median_warnings: median(&counts),
+            warnings,
+        }
+    }
+
+    fn write(&self, mut output: impl io::Write) -> Result<()> {
+        writeln!(output, "- Crates: {}", self.crates)?;
+        writeln!(output, "- Linted: {}", self.linted)?;
+        writeln!(output, "- Build failures: {}", self.build_failures)?;
+        writeln!(output, "- ICEs: {}", self.ices)?;
+        writeln!(output, "- Warnings: {}", self.warnings)?;
+        writeln!(
+            output,
+            "- Mean warnings per crate: {:.1}",
+            self.mean_warnings
+        )?;
+        writeln!(
+            output,
+            "- Median warnings per crate: {:.1}",
+            self.median_warnings
+        )?;
+        match &self.max_warnings {
+            Some(max) => writeln!(
+                output,
+                "- Most warnings: {} ({})",
+                max.count, max.crate_name
+            )?,
+            None => writeln!(output, "- Most warnings: 0")?,
+        }
+        writeln!(
+            output,
+            "- Duration: {}",
+            Seconds(Duration::from_secs_f64(self.duration_secs))
+        )?;
+        writeln!(
+            output,
+            "- Building dependencies: {}",
+            Seconds(Duration::from_secs_f64(self.dependency_build_secs))
+        )?;
+        writeln!(
+            output,
+            "- Linting: {}",
+            Seconds(Duration::from_secs_f64(self.lint_secs))
+        )?;
+        Ok(())
+    }
+}
+
+// The final `--format jsonl` line.
+#[derive(Serialize, Deserialize)]
+struct SummaryRecord {
+    build_failures: Vec<String>,
+    crates: usize,
+    lints: Vec<String>,
+    missing_target: Vec<String>,
+    // Missing from runs saved before it was added, which may have been incremental.
+    #[serde(default)]
+    no_incremental: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rustflags: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stats: Option<RunStats>,
+    warning_count: usize,
+    warning_counts: BTreeMap<String, usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum JsonLine {
+    Summary { summary: SummaryRecord },
+    Crate(CrateRecord),
+}
+
+// The records of a run saved with `--format jsonl`.
+struct SavedRun {
+    lints: Vec<String>,
+    crates: Vec<CrateRecord>,
+}
+
+fn read_saved_run(path: &Path) -> Result<SavedRun> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Reading saved run {}", path.display()))?;
+
+    let mut lints = None;
+    let mut crates = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line)
+            .with_context(|| format!("Parsing line {} of {}", i + 1, path.display()))?
+        {
+            JsonLine::Summary { summary } => lints = Some(summary.lints),
+            JsonLine::Crate(record) => crates.push(record),
+        }
+    }
+
+    // An interrupted run has no summary so the lints are taken from the crates.
+    let lints = lints.unwrap_or_else(|| {
+        crates
+            .iter()
+            .filter_map(|c| c.lints.as_ref())
+            .flat_map(BTreeMap::keys)
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    });
+
+    Ok(SavedRun { lints, crates })
+}
+
+impl SavedRun {
+    fn summary(&self, sort: SortOrder) -> Summary<'_> {
+        let mut summary = Summary::new(&self.lints, &[], sort);
+        for record in &self.crates {
+            let name = Cow::Borrowed(record.crate_name.as_str());
+            match record.status {
+                CrateStatus::InvalidCrate => {}
+                CrateStatus::BuildFailed => summary.build_failures.push(name),
+                CrateStatus::MissingTarget => summary.missing_target.push(name),
+                CrateStatus::LockfileFailed => summary.lockfile_failures.push(name),
+                CrateStatus::NothingCompiled => summary.nothing_compiled.push(name),
+                CrateStatus::Success => {
+                    if record.warning_count > 0 {
+                        summary.warning_counts.insert(name, record.warning_count);
+                    }
+                }
+            }
+        }
+        summary
+    }
+}
+
+struct Summary<'a> {
+    lints: &'a [String],
+    lints_b: &'a [String],
+    build_failures: Vec<Cow<'a, str>>,
+    warning_counts: BTreeMap<Cow<'a, str>, usize>,
+    warning_counts_b: BTreeMap<Cow<'a, str>, usize>,
+    // Crates that failed to build with `--lints-b` or the `--compare-workspaces` workspace. With
+    // `--lints-b` only the crates that built with the first lints are linted again.
+    build_failures_b: Vec<Cow<'a, str>>,
+    // `None` if allows weren't checked.
+    allow_counts: Option<BTreeMap<Cow<'a, str>, usize>>,
+    // Crates that couldn't be built as the standard library for `--target` isn't installed.
+    missing_target: Vec<Cow<'a, str>>,
+    // Crates whose lockfile couldn't be generated or was out of date with `--locked`.
+    lockfile_failures: Vec<Cow<'a, str>>,
+    // Crates where only the default targets could be built with `--all-targets`.
+    all_targets_failures: Vec<Cow<'a, str>>,
+    // Crates which built without compiling anything.
+    nothing_compiled: Vec<Cow<'a, str>>,
+    // `None` if fixes weren't attempted.
+    fix_failures: Option<Vec<(Cow<'a, str>, Vec<String>)>>,
+    // The lints each fix added warnings for with the number added. `None` unless fixes were
+    // compared.
+    fix_side_effects: Option<Vec<(Cow<'a, str>, FixSideEffects)>>,
+    // Peak memory in bytes of each crate built. `None` if memory isn't reported.
+    peak_rss: Option<Vec<(Cow<'a, str>, Option<u64>)>>,
+    // Lines of Rust source in each crate with warnings. `None` if density isn't reported.
+    line_counts: Option<BTreeMap<Cow<'a, str>, usize>>,
+    // The number of target entries which aren't crates. `None` if each is listed instead.
+    invalid_crates: Option<usize>,
+    // Normalized warning message -> count. `None` if common messages aren't reported.
+    message_counts: Option<BTreeMap<String, usize>>,
+    // The primary span of each warning. `None` unless span stats are reported.
+    span_sizes: Option<Vec<SpanSize>>,
+    // Crate -> warnings only in macro expansions. `None` unless they're excluded from the counts.
+    macro_hits: Option<BTreeMap<Cow<'a, str>, usize>>,
+    // Crate -> target -> warning count. `None` if warnings aren't broken down by target.
+    target_counts: Option<BTreeMap<Cow<'a, str>, BTreeMap<String, usize>>>,
+    // Tag -> totals over the linted crates with the tag. `None` unless crates are tagged.
+    tag_totals: Option<BTreeMap<String, TagTotals>>,
+    // The warning counts of crates that differed when linted twice. `None` unless determinism is
+    // checked.
+    nondeterministic: Option<Vec<(Cow<'a, str>, usize, usize)>>,
+    // The number of crates linted and the total. `None` unless the run was stopped early.
+    stopped: Option<(usize, usize)>,
+    // Crates which built and were linted.
+    linted_count: usize,
+    // Build failures where the compiler panicked.
+    ice_count: usize,
+    // The total over the crates linted.
+    timing: CrateTiming,
+    // Set once the run is done. `None` unless stats are reported.
+    stats: Option<RunStats>,
+    // The order of the rows of the warnings table.
+    sort: SortOrder,
+    // `None` unless workspaces are compared. The warning counts and build failures of the second
+    // workspace are in `warning_counts_b` and `build_failures_b`.
+    workspace_comparison: Option<WorkspaceComparison>,
+}
+
+// The results for each workspace of `--compare-workspaces` other than the warning counts and
+// build failures.
+struct WorkspaceComparison {
+    // How each Clippy workspace was given
+    source_a: String,
+    source_b: String,
+    // Lint -> warning count over every crate
+    lint_counts_a: BTreeMap<String, usize>,
+    lint_counts_b: BTreeMap<String, usize>,
+}
+
+impl WorkspaceComparison {
+    fn new(source_a: String, source_b: String) -> Self {
+        WorkspaceComparison {
+            source_a,
+            source_b,
+            lint_counts_a: BTreeMap::new(),
+            lint_counts_b: BTreeMap::new(),
+        }
+    }
+}
+
+// The totals of the crates with a tag.
+#[derive(Default)]
+struct TagTotals {
+    linted: usize,
+    // Crates with warnings
+    warned: usize,
+    warnings: usize,
+    // Lint -> warning count
+    lint_counts: BTreeMap<String, usize>,
+}
+
+impl TagTotals {
+    fn add_crate(&mut self, warning_count: usize, lint_counts: &BTreeMap<String, usize>) {
+        self.linted += 1;
+        if warning_count > 0 {
+            self.warned += 1;
+        }
+        self.warnings += warning_count;
+        add_counts(&mut self.lint_counts, lint_counts);
+    }
+}
+
+fn add_counts(totals: &mut BTreeMap<String, usize>, counts: &BTreeMap<String, usize>) {
+    for (name, count) in counts {
+        *totals.entry(name.clone()).or_insert(0) += count;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SortOrder {
+    Name,
+    // Most warnings first
+    Count,
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortOrder::Name),
+            "count" => Ok(SortOrder::Count),
+            _ => Err(format!(
+                "unknown sort order `{s}` - expected `name` or `count`"
+            )),
+        }
+    }
+}
+
+// The number of crates listed in the memory section of the summary.
+const TOP_MEMORY_CRATES: usize = 10;
+
+// The number of messages listed in the common messages section of the summary.
+const TOP_MESSAGES: usize = 10;
+
+impl<'a> Summary<'a> {
+    fn new(lints: &'a [String], lints_b: &'a [String], sort: SortOrder) -> Self {
+        Summary {
+            lints,
+            lints_b,
+            build_failures: vec![],
+            missing_target: vec![],
+            lockfile_failures: vec![],
+            all_targets_failures: vec![],
+            nothing_compiled: vec![],
+            warning_counts: BTreeMap::new(),
+            warning_counts_b: BTreeMap::new(),
+            build_failures_b: vec![],
+            allow_counts: None,
+            fix_failures: None,
+            fix_side_effects: None,
+            peak_rss: None,
+            line_counts: None,
+            invalid_crates: None,
+            message_counts: None,
+            span_sizes: None,
+            macro_hits: None,
+            target_counts: None,
+            tag_totals: None,
+            nondeterministic: None,
+            stopped: None,
+            linted_count: 0,
+            ice_count: 0,
+            timing: CrateTiming::default(),
+            stats: None,
+            sort,
+            workspace_comparison: None,
+        }
+    }
+
+    // The final `--format jsonl` line.
+    fn to_record(&self, total_crates: usize, config: &RunConfig) -> JsonLine {
+        let to_strings = |names: &[Cow<'_, str>]| names.iter().map(ToString::to_string).collect();
+        JsonLine::Summary {
+            summary: SummaryRecord {
+                build_failures: to_strings(&self.build_failures),
+                crates: total_crates,
+                lints: self.lints.to_vec(),
+                missing_target: to_strings(&self.missing_target),
+                no_incremental: config.no_incremental,
+                rustflags: config.rustflags.clone(),
+                stats: self.stats.clone(),
+                warning_count: self.warning_counts.values().sum::<usize>(),
+                warning_counts: self
+                    .warning_counts
+                    .iter()
+                    .map(|(name, &count)| (name.to_string(), count))
+                    .collect(),
+            },
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn write(&self, mut output: impl io::Write) -> Result<()> {
+        writeln!(output)?;
+        writeln!(output, "# Summary")?;
+
+        if let Some((linted, total)) = self.stopped {
+            writeln!(output)?;
+            writeln!(output, "Stopped early - {linted} of {total} crates linted")?;
+        }
+        if self.lints.is_empty() {
+            writeln!(output)?;
+            writeln!(
+                output,
+                "Build check - no lints requested, so only build failures are reported"
+            )?;
+        }
+
+        if let Some(stats) = &self.stats {
+            writeln!(output)?;
+            writeln!(output, "## Stats")?;
+            writeln!(output)?;
+            stats.write(&mut output)?;
+        }
+
+        if !self.build_failures.is_empty() || self.lints.is_empty() {
+            writeln!(output)?;
+            writeln!(output, "## Build failures")?;
+            writeln!(output)?;
+            writeln!(output, "Total: {}", self.build_failures.len())?;
+            if !self.build_failures.is_empty() {
+                writeln!(output)?;
+                for crate_name in &self.build_failures {
+                    writeln!(output, "- {}", crate_name)?;
+                }
+            }
+        }
+
+        if let Some(invalid_crates @ 1..) = self.invalid_crates {
+            writeln!(output)?;
+            writeln!(output, "## Not crates")?;
+            writeln!(output)?;
+            writeln!(output, "Total: {invalid_crates}")?;
+        }
+
+        if !self.missing_target.is_empty() {
+            writeln!(output)?;
+            writeln!(output, "## Target not installed")?;
+            writeln!(output)?;
+            writeln!(output, "Total: {}", self.missing_target.len())?;
+            writeln!(output)?;
+            for crate_name in &self.missing_target {
+                writeln!(output, "- {crate_name}")?;
+            }
+        }
+
+        if !self.lockfile_failures.is_empty() {
+            writeln!(output)?;
+            writeln!(output, "## Lockfile failures")?;
+            writeln!(output)?;
+            writeln!(output, "Total: {}", self.lockfile_failures.len())?;
+            writeln!(output)?;
+            for crate_name in &self.lockfile_failures {
+                writeln!(output, "- {crate_name}")?;
+            }
+        }
+
+        if !self.nothing_compiled.is_empty() {
+            writeln!(output)?;
+            writeln!(output, "## Nothing compiled")?;
+            writeln!(output)?;
+            writeln!(
+                output,
+                "Total: {} (no targets were built - not counted as clean)",
+                self.nothing_compiled.len()
+            )?;
+            writeln!(output)?;
+            for crate_name in &self.nothing_compiled {
+                writeln!(output, "- {crate_name}")?;
+            }
+        }
+
+        if !self.all_targets_failures.is_empty() {
+            writeln!(output)?;
+            writeln!(output, "## Default targets only")?;
+            writeln!(output)?;
+            writeln!(
+                output,
+                "Total: {} (failed to build with `--all-targets`)",
+                self.all_targets_failures.len()
+            )?;
+            writeln!(output)?;
+            for crate_name in &self.all_targets_failures {
+                writeln!(output, "- {crate_name}")?;
+            }
+        }
+
+        if !self.lints_b.is_empty()
+            || (self.workspace_comparison.is_some() && !self.lints.is_empty())
+        {
+            writeln!(output)?;
+            writeln!(output, "## Warnings")?;
+            writeln!(output)?;
+            if let Some(comparison) = &self.workspace_comparison {
+                writeln!(output, "- A: {}", comparison.source_a)?;
+                writeln!(output, "- B: {}", comparison.source_b)?;
+            } else {
+                writeln!(output, "- A: {}", self.lints.join(", "))?;
+                writeln!(output, "- B: {}", self.lints_b.join(", "))?;
+            }
+            writeln!(output)?;
+            writeln!(
+                output,
+                "Total: A {}, B {}",
+                self.warning_counts.values().sum::<usize>(),
+                self.warning_counts_b.values().sum::<usize>()
+            )?;
+            if !self.warning_counts.is_empty() || !self.warning_counts_b.is_empty() {
+                writeln!(output)?;
+                write_diff_table(
+                    "Crate",
+                    &self.warning_counts,
+                    &self.warning_counts_b,
+                    &mut output,
+                )?;
+            }
+        } else if !self.lints.is_empty() {
+            writeln!(output)?;
+            writeln!(output, "## Warnings")?;
+            writeln!(output)?;
+            writeln!(
+                output,
+                "Total: {}",
+                self.warning_counts.values().sum::<usize>()
+            )?;
+            if !self.warning_counts.is_empty() {
+                writeln!(output)?;
+                let mut rows: Vec<_> = self.warning_counts.iter().collect();
+                if self.sort == SortOrder::Count {
+                    // Stable so that ties stay sorted by name
+                    rows.sort_by(|(_, a), (_, b)| b.cmp(a));
+                }
+                match &self.line_counts {
+                    Some(line_counts) => write_density_table(&rows, line_counts, &mut output)?,
+                    None => print_table(["Crate", "Count"], rows, &mut output)?,
+                }
+            }
+        }
+
+        if let Some(comparison) = &self.workspace_comparison {
+            self.write_workspace_comparison(comparison, &mut output)?;
+        }
+        if !self.lints_b.is_empty() || self.workspace_comparison.is_some() {
+            self.write_build_changes(&mut output)?;
+        }
+
+        if let Some(macro_hits) = &self.macro_hits {
+            writeln!(output)?;
+            writeln!(output, "## Macro-only warnings")?;
+            writeln!(output)?;
+            writeln!(
+                output,
+                "Total: {} (not counted as their spans are all in macro expansions)",
+                macro_hits.values().sum::<usize>()
+            )?;
+            if !macro_hits.is_empty() {
+                writeln!(output)?;
+                print_table(["Crate", "Count"], macro_hits, &mut output)?;
+            }
+        }
+
+        if let Some(target_counts) = &self.target_counts {
+            writeln!(output)?;
+            writeln!(output, "## Warnings by target")?;
+            if !target_counts.is_empty() {
+                writeln!(output)?;
+                write_target_counts_table(target_counts, &mut output)?;
+            }
+        }
+
+        if let Some(tag_totals) = &self.tag_totals {
+            writeln!(output)?;
+            writeln!(output, "## Warnings by tag")?;
+            if !tag_totals.is_empty() {
+                writeln!(output)?;
+                write_tag_totals_table(tag_totals, &mut output)?;
+                // The breakdown would repeat the totals with a single lint.
+                if self.lints.len() > 1 {
+                    writeln!(output)?;
+                    write_tag_lint_counts_table(tag_totals, &mut output)?;
+                }
+            }
+        }
+
+        if let Some(nondeterministic) = &self.nondeterministic {
+            writeln!(output)?;
+            writeln!(output, "## Nondeterministic warnings")?;
+            writeln!(output)?;
+            writeln!(
+                output,
+                "Total: {} (warning counts differ when linted twice)",
+                nondeterministic.len()
+            )?;
+            if !nondeterministic.is_empty() {
+                writeln!(output)?;
+                for (crate_name, first, second) in nondeterministic {
+                    writeln!(output, "- {crate_name} - {first} then {second}")?;
+                }
+            }
+        }
+
+        if let Some(allow_counts) = &self.allow_counts {
+            writeln!(output)?;
+            writeln!(output, "## Allows")?;
+            writeln!(output)?;
+            writeln!(output, "Total: {}", allow_counts.values().sum::<usize>())?;
+            if !allow_counts.is_empty() {
+                writeln!(output)?;
+                print_table(["Crate", "Count"], allow_counts, &mut output)?;
+            }
+        }
+
+        if let Some(fix_failures) = &self.fix_failures {
+            writeln!(output)?;
+            writeln!(output, "## Fix failures")?;
+            writeln!(output)?;
+            writeln!(output, "Total: {}", fix_failures.len())?;
+
+            if !fix_failures.is_empty() {
+                writeln!(output)?;
+                for (crate_name, errors) in fix_failures {
+                    if errors.is_empty() {
+                        writeln!(output, "- {crate_name}")?;
+                    } else {
+                        writeln!(output, "- {crate_name} - {}", errors.join(", "))?;
+                    }
+                }
+            }
+        }
+
+        if let Some(fix_side_effects) = &self.fix_side_effects {
+            writeln!(output)?;
+            writeln!(output, "## Fix side-effects")?;
+            writeln!(output)?;
+            writeln!(output, "Total: {}", fix_side_effects.len())?;
+
+            if !fix_side_effects.is_empty() {
+                writeln!(output)?;
+                for (crate_name, side_effects) in fix_side_effects {
+                    writeln!(
+                        output,
+                        "- {crate_name} - {}",
+                        format_side_effects(side_effects)
+                    )?;
+                }
+            }
+        }
+
+        if let Some(peak_rss) = &self.peak_rss {
+            writeln!(output)?;
+            writeln!(output, "## Peak memory")?;
+            writeln!(output)?;
+
+            let mut measured: Vec<_> = peak_rss
+                .iter()
+                .filter_map(|(crate_name, rss)| Some((crate_name, (*rss)?)))
+                .collect();
+            if measured.is_empty() && !peak_rss.is_empty() {
+                writeln!(output, "Unavailable on this platform")?;
+            } else if !measured.is_empty() {
+                measured.sort_by(|(_, a), (_, b)| b.cmp(a));
+                let rows: Vec<_> = measured
+                    .into_iter()
+                    .take(TOP_MEMORY_CRATES)
+                    .map(|(crate_name, rss)| {
+                        let mib = usize::try_from(rss / (1024 * 1024)).unwrap_or(usize::MAX);
+                        (crate_name, mib)
+                    })
+                    .collect();
+                print_table(
+                    ["Crate", "Peak RSS (MiB)"],
+                    rows.iter().map(|(crate_name, mib)| (crate_name, mib)),
+                    &mut output,
+                )?;
+            }
+        }
+
+        if let Some(message_counts) = &self.message_counts {
+            writeln!(output)?;
+            writeln!(output, "## Common messages")?;
+            writeln!(output)?;
+            writeln!(output, "Distinct: {}", message_counts.len())?;
+            if !message_counts.is_empty() {
+                let mut rows: Vec<_> = message_counts
+                    .iter()
+                    .map(|(message, count)| (message.as_str(), *count))
+                    .collect();
+                // Most common first. Ties are kept in alphabetical order.
+                rows.sort_by(|(_, a), (_, b)| b.cmp(a));
+                rows.truncate(TOP_MESSAGES);
+                writeln!(output)?;
+                write_common_messages_table(&rows, message_counts.values().sum(), &mut output)?;
+            }
+        }
+
+        if let Some(span_sizes) = &self.span_sizes {
+            writeln!(output)?;
+            writeln!(output, "## Span sizes")?;
+            writeln!(output)?;
+            writeln!(
+                output,
+                "Total: {} (the primary span of each warning)",
+                span_sizes.len()
+            )?;
+            if !span_sizes.is_empty() {
+                writeln!(output)?;
+                write_span_sizes_table(span_sizes, &mut output)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // The sections for `--compare-workspaces` after the warnings table.
+    fn write_workspace_comparison(
+        &self,
+        comparison: &WorkspaceComparison,
+        mut output: impl io::Write,
+    ) -> Result<()> {
+        if !self.lints.is_empty() {
+            writeln!(output)?;
+            writeln!(output, "## Warnings by lint")?;
+            if !comparison.lint_counts_a.is_empty() || !comparison.lint_counts_b.is_empty() {
+                writeln!(output)?;
+                write_diff_table(
+                    "Lint",
+                    &comparison.lint_counts_a,
+                    &comparison.lint_counts_b,
+                    &mut output,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // The crates whose build failed with only one of A and B. With `--lints-b` the crates that
+    // failed with A aren't built with B so none are newly passing.
+    fn write_build_changes(&self, mut output: impl io::Write) -> Result<()> {
+        let newly_failing: Vec<_> = self
+            .build_failures_b
+            .iter()
+            .filter(|name| !self.build_failures.contains(name))
+            .collect();
+        let newly_passing: Vec<_> = self
+            .build_failures
+            .iter()
+            .filter(|name| {
+                self.workspace_comparison.is_some() && !self.build_failures_b.contains(name)
+            })
+            .collect();
+        if newly_failing.is_empty() && newly_passing.is_empty() {
+            return Ok(());
+        }
+        writeln!(output)?;
+        writeln!(output, "## Build changes")?;
+        for (heading, crate_names) in [
+            ("Newly failing with B", newly_failing),
+            ("Newly passing with B", newly_passing),
+        ] {
+            if !crate_names.is_empty() {
+                writeln!(output)?;
+                writeln!(output, "{heading}: {}", crate_names.len())?;
+                writeln!(output)?;
+                for crate_name in crate_names {
+                    writeln!(output, "- {crate_name}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// A table of the counts in `a` and `b` and the change from `a` to `b`, with a row for each name in
+// either.
+fn write_diff_table<K: AsRef<str> + Ord>(
+    name_header: &str,
+    a: &BTreeMap<K, usize>,
+    b: &BTreeMap<K, usize>,
+    output: impl io::Write,
+) -> Result<()> {
+    let columns = [
+        Column::new(name_header, Alignment::Left),
+        Column::new("A", Alignment::Right),
+        Column::new("B", Alignment::Right),
+        Column::new("Delta", Alignment::Right),
+    ];
+
+    let names: BTreeSet<_> = a.keys().chain(b.keys()).collect();
+    let rows: Vec<_> = names
+        .into_iter()
+        .map(|name| {
+            let a = a.get(name).copied().unwrap_or(0);
+            let b = b.get(name).copied().unwrap_or(0);
+            let delta = match b.cmp(&a) {
+                Ordering::Greater => format!("+{}", b - a),
+                Ordering::Less => format!("-{}", a - b),
+                Ordering::Equal => "0".into(),
+            };
+            vec![
+                Cell::new(name.as_ref()),
+                Cell::new(a),
+                Cell::new(b),
+                Cell::new(delta.as_str()),
+            ]
+        })
+        .collect();
+
+    print_multi_column_table(&columns, &rows, output)
+}
+
+// The common messages with their share of all the warnings.
+fn write_common_messages_table(
+    rows: &[(&str, usize)],
+    total: usize,
+    output: impl io::Write,
+) -> Result<()> {
+    let columns = [
+        Column::new("Message", Alignment::Left),
+        Column::new("Count", Alignment::Right),
+        Column::new("Share", Alignment::Right),
+    ];
+
+    let rows: Vec<_> = rows
+        .iter()
+        .map(|&(message, count)| {
+            vec![
+                Cell::new(message),
+                Cell::new(count),
+                Cell::new(Percent {
+                    part: count,
+                    whole: total,
+                }),
+            ]
+        })
+        .collect();
+
+    print_multi_column_table(&columns, &rows, output)
+}
+
+// The median of sorted values, averaging the middle two of an even number of values. `0` if there
+// are none.
+#[allow(clippy::cast_precision_loss)]
+fn median(sorted: &[usize]) -> f64 {
+    match sorted.len() {
+        0 => 0.0,
+        len if len % 2 == 0 => (sorted[len / 2 - 1] + sorted[len / 2]) as f64 / 2.0,
+        len => sorted[len / 2] as f64,
+    }
+}
+
+// The min, median and max of the span sizes.
+fn write_span_sizes_table(span_sizes: &[SpanSize], output: impl io::Write) -> Result<()> {
+    let columns = [
+        Column::new("Size", Alignment::Left),
+        Column::new("Min", Alignment::Right),
+        Column::new("Median", Alignment::Right),
+        Column::new("Max", Alignment::Right),
+    ];
+
+    let row = |name: &'static str, size: fn(&SpanSize) -> usize| {
+        let mut sizes: Vec<_> = span_sizes.iter().map(size).collect();
+        sizes.sort_unstable();
+        vec![
+            Cell::new(name),
+            Cell::new(sizes[0]),
+            Cell::new(median(&sizes).to_string().as_str()),
+            Cell::new(sizes[sizes.len() - 1]),
+        ]
+    };
+    let rows = [
+        row("Lines", |size| size.lines),
+        row("Bytes", |size| size.bytes),
+    ];
+
+    print_multi_column_table(&columns, &rows, output)
+}
+
+// A row for each target of each crate with its warnings.
+fn write_target_counts_table(
+    target_counts: &BTreeMap<Cow<'_, str>, BTreeMap<String, usize>>,
+    output: impl io::Write,
+) -> Result<()> {
+    let columns = [
+        Column::new("Crate", Alignment::Left),
+        Column::new("Target", Alignment::Left),
+        Column::new("Count", Alignment::Right),
+    ];
+
+    let rows: Vec<_> = target_counts
+        .iter()
+        .flat_map(|(crate_name, counts)| {
+            counts.iter().map(move |(target, &count)| {
+                vec![
+                    Cell::new(crate_name.as_ref()),
+                    Cell::new(target.as_str()),
+                    Cell::new(count),
+                ]
+            })
+        })
+        .collect();
+
+    print_multi_column_table(&columns, &rows, output)
+}
+
+fn write_tag_totals_table(
+    tag_totals: &BTreeMap<String, TagTotals>,
+    output: impl io::Write,
+) -> Result<()> {
+    let columns = [
+        Column::new("Tag", Alignment::Left),
+        Column::new("Linted", Alignment::Right),
+        Column::new("Warned", Alignment::Right),
+        Column::new("Warnings", Alignment::Right),
+    ];
+
+    let rows: Vec<_> = tag_totals
+        .iter()
+        .map(|(tag, totals)| {
+            vec![
+                Cell::new(tag.as_str()),
+                Cell::new(totals.linted),
+                Cell::new(totals.warned),
+                Cell::new(totals.warnings),
+            ]
+        })
+        .collect();
+
+    print_multi_column_table(&columns, &rows, output)
+}
+
+fn write_tag_lint_counts_table(
+    tag_totals: &BTreeMap<String, TagTotals>,
+    output: impl io::Write,
+) -> Result<()> {
+    let columns = [
+        Column::new("Tag", Alignment::Left),
+        Column::new("Lint", Alignment::Left),
+        Column::new("Count", Alignment::Right),
+    ];
+
+    let rows: Vec<_> = tag_totals
+        .iter()
+        .flat_map(|(tag, totals)| {
+            totals.lint_counts.iter().map(move |(lint, &count)| {
+                vec![
+                    Cell::new(tag.as_str()),
+                    Cell::new(lint.as_str()),
+                    Cell::new(count),
+                ]
+            })
+        })
+        .collect();
+
+    print_multi_column_table(&columns, &rows, output)
+}
+
+// The warnings table with the lines of source and warnings per thousand lines of each crate.
+// Crates whose lines couldn't be counted are shown with `-`.
+fn write_density_table(
+    rows: &[(&Cow<'_, str>, &usize)],
+    line_counts: &BTreeMap<Cow<'_, str>, usize>,
+    output: impl io::Write,
+) -> Result<()> {
+    let columns = [
+        Column::new("Crate", Alignment::Left),
+        Column::new("Count", Alignment::Right),
+        Column::new("Lines", Alignment::Right),
+        Column::new("Per kloc", Alignment::Right),
+    ];
+
+    let rows: Vec<_> = rows
+        .iter()
+        .map(|&(crate_name, &count)| {
+            let lines = line_counts.get(crate_name).copied();
+            vec![
+                Cell::new(crate_name.as_ref()),
+                Cell::new(count),
+                lines.map_or_else(|| Cell::new("-"), Cell::new),
+                Cell::new(PerKloc {
+                    count,
+                    lines: lines.unwrap_or(0),
+                }),
+            ]
+        })
+        .collect();
+
+    print_multi_column_table(&columns, &rows, output)
+}
+
+fn write_summary_file(summary: &Summary<'_>, path: &Path, compress: bool) -> Result<()> {
+    let file = fs::File::create(path)
+        .with_context(|| format!("Creating output file {}", path.display()))?;
+    let mut writer = io::BufWriter::new(file);
+
+    if compress || path.extension().is_some_and(|e| e == "gz") {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        summary.write(&mut encoder)?;
+        // `finish` is required to write the gzip trailer.
+        writer = encoder.finish().context("Compressing output")?;
+    } else {
+        summary.write(&mut writer)?;
+    }
+
+    writer
+        .flush()
+        .with_context(|| format!("Writing output file {}", path.display()))
+}
+
+// The file of a lint's warnings for `--split-output` (e.g. `approx_constant.txt`).
+fn split_output_file_name(lint: &str) -> String {
+    format!(
+        "{}.txt",
+        lint.strip_prefix("clippy::")
+            .unwrap_or(lint)
+            .replace("::", "__")
+    )
+}
+
+// Write each lint's warnings to its own file in `dir` with an index of the files. Each file is
+// written to a temporary file first so that an existing file is only replaced once complete.
+fn write_split_output(
+    dir: &Path,
+    lints: &[String],
+    diagnostics: &BTreeMap<String, Vec<String>>,
+    lint_groups: Option<&BTreeMap<String, String>>,
+) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Creating {}", dir.display()))?;
+
+    let write_file = |name: &str, contents: &[u8]| -> Result<()> {
+        let path = dir.join(name);
+        let temp_path = dir.join(format!(".{name}.tmp"));
+        fs::write(&temp_path, contents)
+            .with_context(|| format!("Writing {}", temp_path.display()))?;
+        fs::rename(&temp_path, &path).with_context(|| format!("Writing {}", path.display()))
+    };
+
+    let mut rows = vec![];
+    for lint in lints {
+        let file_name = split_output_file_name(lint);
+        let texts = diagnostics.get(lint).map_or(&[][..], Vec::as_slice);
+        let mut contents = texts.join("\n\n");
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+        write_file(&file_name, contents.as_bytes())?;
+        let mut row = vec![Cell::new(lint.as_str())];
+        if let Some(lint_groups) = lint_groups {
+            row.push(Cell::new(lint_groups.get(lint).map_or("-", String::as_str)));
+        }
+        row.extend([Cell::new(texts.len()), Cell::new(file_name.as_str())]);
+        rows.push(row);
+    }
+
+    let mut index = vec![];
+    writeln!(index, "# Warnings by lint")?;
+    writeln!(index)?;
+    writeln!(
+        index,
+        "Total: {}",
+        diagnostics.values().map(Vec::len).sum::<usize>()
+    )?;
+    if !rows.is_empty() {
+        writeln!(index)?;
+        let mut columns = vec![Column::new("Lint", Alignment::Left)];
+        if lint_groups.is_some() {
+            columns.push(Column::new("Group", Alignment::Left));
+        }
+        columns.extend([
+            Column::new("Count", Alignment::Right),
+            Column::new("File", Alignment::Left),
+        ]);
+        print_multi_column_table(&columns, &rows, &mut index)?;
+    }
+    write_file("index.md", &index)
+}
+
+fn write_script(
+    path: &Path,
+    clippy_workspace: &ClippyWorkspace,
+    commands: &[Command],
+) -> Result<()> {
+    let mut script = String::from("#!/bin/sh\n");
+    script.push_str("# Generated by clippy_lint_tester\n");
+    script
+        .push_str("# `set -e` isn't used so that every crate is run even if some fail to build.\n");
+    if let Some(build_command) = clippy_workspace.make_build_command() {
+        script.push('\n');
+        script.push_str("# Build Clippy\n");
+        script.push_str(&format_command(&build_command));
+        script.push_str(" || exit 1\n");
+    }
+    for command in commands {
+        script.push('\n');
+        writeln!(script, "({})", format_command(command)).expect("Write to string succeeds");
+    }
+
+    fs::write(path, script).with_context(|| format!("Writing script {}", path.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Making script {} executable", path.display()))?;
+    }
+
+    Ok(())
+}
+
+enum LintResult {
+    InvalidCrate,
+    BuildFailed {
+        // The compiler panicked.
+        ice: bool,
+    },
+    // The standard library for the target isn't installed.
+    MissingTarget,
+    // The lockfile couldn't be generated or was out of date with `--locked`.
+    LockfileFailed,
+    // The build succeeded without compiling anything. e.g. a virtual workspace or a crate whose
+    // targets are all disabled by default.
+    NothingCompiled,
+    Success {
+        // Boxed as it's much larger than the other variants.
+        stats: Box<CrateStats>,
+        // The compile errors caused by fixing the crate (e.g. `E0502 in src/lib.rs`). `None` if the
+        // fix succeeded or wasn't attempted.
+        fix_errors: Option<Vec<String>>,
+        // The lints the fix added warnings for with the number added. Only checked for
+        // `--compare-fix`.
+        fix_side_effects: FixSideEffects,
+        // Only the default targets could be built with `--all-targets`.
+        all_targets_failed: bool,
+    },
+}
+
+// How long linting a crate took, split at when its last dependency was built. Approximate as
+// Cargo builds the crate's own build script and other targets alongside the dependencies.
+#[derive(Clone, Copy, Default)]
+struct CrateTiming {
+    dependencies: Duration,
+    lint: Duration,
+}
+
+// The warnings of a crate that linted successfully and what was measured while linting it.
+struct CrateStats {
+    warning_count: usize,
+    // Lint name -> warning count
+    lint_counts: BTreeMap<String, usize>,
+    // In bytes. `None` if unavailable.
+    peak_rss: Option<u64>,
+    // Normalized warning messages. Only collected for `--common-messages`.
+    messages: Vec<String>,
+    // Only collected for `--span-stats`.
+    span_sizes: Vec<SpanSize>,
+    // Warnings only in macro expansions. Only counted for `--exclude-macro-hits`.
+    macro_hits: usize,
+    // The lint and text of each warning. Only collected for `--split-output` and
+    // `--format dashboard`.
+    diagnostics: Vec<(String, String)>,
+    // Target (e.g. `bin foo`) -> warning count. Only collected for `--by-target`.
+    target_counts: BTreeMap<String, usize>,
+    timing: CrateTiming,
+}
+
+// Groups expanding to more lints than this require `--confirm-large-group`.
+const LARGE_GROUP_SIZE: usize = 50;
+
+// Lint groups (e.g. `clippy::style`) are expanded to the lints they contain.
+fn check_and_format_lint_names(
+    clippy_workspace: &ClippyWorkspace,
+    lint_args: &[String],
+    confirm_large_group: bool,
+) -> Result<Vec<String>> {
+    if lint_args.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Map formatted_name -> arg
+    let mut formatted_names = BTreeMap::new();
+
+    for lint_arg in lint_args {
+        let mut formatted_name = lint_arg.to_lowercase();
+        if !formatted_name.starts_with("clippy::") {
+            formatted_name.insert_str(0, "clippy::");
+        }
+        formatted_name = formatted_name.replace('-', "_");
+
+        // Only the first form is kept so that a missing lint is reported as it was first given.
+        match formatted_names.entry(formatted_name) {
+            Entry::Vacant(entry) => {
+                entry.insert(lint_arg);
+            }
+            Entry::Occupied(entry) => {
+                eprintln!(
+                    "warning: `{}` is the same lint as `{}` - it will only be tested once",
+                    lint_arg,
+                    entry.get()
+                );
+            }
+        }
+    }
+
+    let mut lints = Vec::with_capacity(lint_args.len());
+    // Group name -> number of lints in the group
+    let mut group_sizes = BTreeMap::new();
+    for lint in clippy_workspace.available_lints()? {
+        let group = lint
+            .group
+            .filter(|group| formatted_names.contains_key(group));
+        if let Some(group) = group {
+            *group_sizes.entry(group).or_insert(0) += 1;
+            lints.push(lint.name);
+        } else if formatted_names.contains_key(&lint.name) {
+            lints.push(lint.name);
+        }
+    }
+    for name in lints.iter().chain(group_sizes.keys()) {
+        formatted_names.remove(name);
+    }
+
+    let mut missing_args = formatted_names.values();
+    if let Some(first) = missing_args.next() {
+        let mut error_message = format!("Lints not found: `{}`", first);
+        for arg in missing_args {
+            write!(error_message, ", `{}`", arg).expect("Write to string succeeds");
+        }
+        bail!(error_message);
+    }
+
+    if !group_sizes.is_empty() {
+        for (group, &size) in &group_sizes {
+            eprintln!("`{group}` expanded to {size} lints");
+            if size > LARGE_GROUP_SIZE && !confirm_large_group {
+                bail!(
+                    "`{group}` expands to more than {LARGE_GROUP_SIZE} lints - \
+                    pass `--confirm-large-group` to test all of them"
+                );
+            }
+        }
+        eprintln!("Testing {} lints", lints.len());
+    }
+
+    Ok(lints)
+}
+
+fn make_lint_command(
+    clippy_workspace: &ClippyWorkspace,
+    cargo_target_dir: &Path,
+    path: &Path,
+    lints: &[impl AsRef<str>],
+    config: &RunConfig,
+) -> Command {
+    let mut command = clippy_workspace.make_cargo_clippy_command();
+    config.add_cargo_args(&mut command);
+    command
+        .arg("--quiet")
+        .arg("--message-format=json")
+        .arg("--target-dir")
+        .arg(cargo_target_dir)
+        .arg("--")
+        .arg("--cap-lints")
+        .arg("warn")
+        .arg(config.context_level.flag())
+        .arg("clippy::all")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .current_dir(path);
+    for name in lints {
+        command.arg("--warn").arg(name.as_ref());
+    }
+    command
+}
+
+// Build every crate without any lints so that the dependencies shared between crates are already
+// in the target dir when they're linted. Failures are ignored as they're reported when linting.
+fn warm_up(
+    clippy_workspace: &ClippyWorkspace,
+    cargo_target_dir: &Path,
+    paths: &[PathBuf],
+    config: &RunConfig,
+    progress: ProgressMode,
+) -> Result<()> {
+    let start = Instant::now();
+    let mut progress_bar = ProgressBar::new(progress);
+    progress_bar.display_progress(paths.len(), "Warming up...");
+    for path in paths {
+        if interrupt::stop_requested() {
+            break;
+        }
+        progress_bar.inc_progress(&crate_name(path));
+        if !path.join(&config.manifest_name).exists() {
+            continue;
+        }
+        let no_lints: &[&str] = &[];
+        interrupt::status(
+            make_lint_command(clippy_workspace, cargo_target_dir, path, no_lints, config)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null()),
+        )
+        .context("Running warmup build")?;
+    }
+    drop(progress_bar);
+    eprintln!(
+        "Warmed up {} crates in {}",
+        paths.len(),
+        Seconds(start.elapsed())
+    );
+    Ok(())
+}
+
+#[allow(clippy::too_many_lines)]
+fn run_lint(
+    progress_bar: &mut ProgressBar,
+    clippy_workspace: &ClippyWorkspace,
+    cargo_target_dir: &Path,
+    lints: &[impl AsRef<str>],
+    path: &Path,
+    fix_dir: Option<&Path>,
+    config: &RunConfig,
+) -> Result<LintResult> {
+    let crate_name = crate_name(path);
+
+    if !path.is_dir() || !path.join(&config.manifest_name).exists() {
+        return Ok(LintResult::InvalidCrate);
+    }
+
+    // Touch the crate roots to force recompilation.
+    // Cargo can't detect changes to Clippy's source.
+    touch_crate_roots_with_extra_roots(path, &config.manifest_name, &config.extra_crate_roots)
+        .context("Touching crate roots")?;
+
+    if config.generate_lockfile && !generate_lockfile(progress_bar, clippy_workspace, path, config)?
+    {
+        return Ok(LintResult::LockfileFailed);
+    }
+
+    let mut cargo_clippy =
+        make_lint_command(clippy_workspace, cargo_target_dir, path, lints, config);
+
+    let mut retries_remaining = config.build_retries;
+    let stats = loop {
+        match run_lint_command(progress_bar, &mut cargo_clippy, lints, &crate_name, config)? {
+            LintCommandOutcome::Success {
+                stats: CrateStats {
+                    warning_count: 0, ..
+                },
+                compiled: false,
+            } => {
+                progress_bar.println(
+                    &crate_name,
+                    format_args!("{} - nothing compiled", &crate_name),
+                );
+                return Ok(LintResult::NothingCompiled);
+            }
+            LintCommandOutcome::Success { stats, .. } => break stats,
+            LintCommandOutcome::Failed { errors } => {
+                if retries_remaining > 0 && is_spurious_build_failure(&errors) {
+                    retries_remaining -= 1;
+                    progress_bar.println(
+                        &crate_name,
+                        format_args!(
+                            "{} - build failed due to lock contention, retrying",
+                            &crate_name
+                        ),
+                    );
+                    continue;
+                }
+
+                progress_bar.println(&crate_name, "");
+
+                if is_missing_target_failure(&errors) {
+                    progress_bar.println(
+                        &crate_name,
+                        format_args!("{} - build failed (target not installed)", &crate_name),
+                    );
+                    return Ok(LintResult::MissingTarget);
+                }
+
+                if is_locked_failure(&errors) {
+                    progress_bar.println(
+                        &crate_name,
+                        format_args!("{} - build failed (lockfile out of date)", &crate_name),
+                    );
+                    return Ok(LintResult::LockfileFailed);
+                }
+
+                let ice = errors.contains("internal compiler error: unexpected panic\n\nnote: the compiler unexpectedly panicked. this is a bug.");
+
+                progress_bar.println(
+                    &crate_name,
+                    &format_args!(
+                        "{} - build failed{}",
+                        &crate_name,
+                        if ice { " (ICE)" } else { "" }
+                    ),
+                );
+                progress_bar.println(
+                    &crate_name,
+                    &format_args!("Command used: `{}`", format_command(&cargo_clippy)),
+                );
+
+                if config.all_targets {
                     progress_bar.println(
                         &crate_name,
-                        &format_args!("{} - not a crate", path.display()),
+                        format_args!("{} - retrying without `--all-targets`", &crate_name),
+                    );
+                    return run_lint_default_targets(
+                        progress_bar,
+                        clippy_workspace,
+                        cargo_target_dir,
+                        lints,
+                        path,
+                        fix_dir,
+                        config,
                     );
                 }
-                LintResult::BuildFailed => {
-                    build_failures.push(crate_name);
-                }
-                LintResult::Success {
-                    warning_count,
-                    fix_failed,
-                } => {
-                    if warning_count > 0 {
-                        if fix_failed {
-                            fix_failures.push(crate_name.clone());
-                        }
-                        warning_counts.insert(crate_name, warning_count);
-                    }
-                }
-            }
-        }
-    }
 
-    println!();
-    println!("# Summary");
-
-    if !build_failures.is_empty() || lints.is_empty() {
-        println!();
-        println!("## Build failures");
-        println!();
-        println!("Total: {}", build_failures.len());
-        if !build_failures.is_empty() {
-            println!();
-            for crate_name in &build_failures {
-                println!("- {}", crate_name);
+                return Ok(LintResult::BuildFailed { ice });
             }
         }
-    }
+    };
+
+    let (fix_errors, fix_side_effects) = match fix_dir {
+        Some(fix_dir) if stats.warning_count > 0 && !lints.is_empty() => fix_crate(
+            progress_bar,
+            clippy_workspace,
+            cargo_target_dir,
+            lints,
+            path,
+            fix_dir,
+            config,
+        )?,
+        _ => (None, vec![]),
+    };
 
-    if !lints.is_empty() {
-        println!();
-        println!("## Warnings");
-        println!();
-        println!("Total: {}", warning_counts.values().sum::<usize>());
-        if !warning_counts.is_empty() {
-            println!();
-            print_table(["Crate", "Count"], &warning_counts, stdout())?;
-        }
-    }
+    Ok(LintResult::Success {
+        stats: Box::new(stats),
+        fix_errors,
+        fix_side_effects,
+        all_targets_failed: false,
+    })
+}
 
-    if check_allows {
-        println!();
-        println!("## Allows");
-        println!();
-        println!("Total: {}", allow_counts.values().sum::<usize>());
-        if !allow_counts.is_empty() {
-            println!();
-            print_table(["Crate", "Count"], &allow_counts, stdout())?;
-        }
+// Generate a lockfile for a crate without one next to its manifest. Returns whether the crate
+// has a lockfile.
+fn generate_lockfile(
+    progress_bar: &mut ProgressBar,
+    clippy_workspace: &ClippyWorkspace,
+    path: &Path,
+    config: &RunConfig,
+) -> Result<bool> {
+    let manifest_path = path.join(&config.manifest_name);
+    let manifest_dir = manifest_path.parent().expect("manifest in a dir");
+    if manifest_dir.join("Cargo.lock").exists() {
+        return Ok(true);
     }
 
-    if fix_dir.is_some() {
-        println!();
-        println!("## Fix failures");
-        println!();
-        println!("Total: {}", fix_failures.len());
-
-        if !fix_failures.is_empty() {
-            println!();
-            for crate_name in &fix_failures {
-                println!("- {}", crate_name);
-            }
-        }
+    let crate_name = crate_name(path);
+    let mut command = clippy_workspace.make_cargo_command("generate-lockfile");
+    command
+        .current_dir(path)
+        .envs(config.envs.iter().map(|(key, value)| (key, value)));
+    if config.manifest_name != Path::new(DEFAULT_MANIFEST_NAME) {
+        command.arg("--manifest-path").arg(&config.manifest_name);
     }
-
-    Ok(())
+    let output = interrupt::output(&mut command).context("Generating lockfile")?;
+    if !output.status.success() {
+        progress_bar.println(
+            &crate_name,
+            format_args!("{} - failed to generate the lockfile", &crate_name),
+        );
+        progress_bar.println(
+            &crate_name,
+            format_args!("Command used: `{}`", format_command(&command)),
+        );
+    }
+    Ok(output.status.success())
 }
 
-enum LintResult {
-    InvalidCrate,
-    BuildFailed,
-    Success {
-        warning_count: usize,
-        fix_failed: bool,
-    },
-}
+// Lints with the number of warnings a fix added for each.
+type FixSideEffects = Vec<(String, usize)>;
 
-fn check_and_format_lint_names(
+// Fix a copy of the crate in `fix_dir`. Returns the errors caused by the fix if it failed and the
+// warnings it added for other lints with `--compare-fix`.
+fn fix_crate(
+    progress_bar: &mut ProgressBar,
     clippy_workspace: &ClippyWorkspace,
-    lint_args: &[String],
-) -> Result<Vec<String>> {
-    if lint_args.is_empty() {
-        return Ok(vec![]);
+    cargo_target_dir: &Path,
+    lints: &[impl AsRef<str>],
+    path: &Path,
+    fix_dir: &Path,
+    config: &RunConfig,
+) -> Result<(Option<Vec<String>>, FixSideEffects)> {
+    let crate_name = crate_name(path);
+    let fix_dir = fix_destination(fix_dir, path.file_name().expect("Path not '..'"));
+    if fix_dir.file_name() != path.file_name() {
+        progress_bar.println(
+            &crate_name,
+            format_args!(
+                "{} - fixed in `{}` as another crate has the same name",
+                &crate_name,
+                fix_dir.display()
+            ),
+        );
     }
-
-    eprintln!("Checking lint names");
-
-    // Map formatted_name -> arg
-    let mut formatted_names = BTreeMap::new();
-
-    for lint_arg in lint_args {
-        let mut formatted_name = lint_arg.to_lowercase();
-        if !formatted_name.starts_with("clippy::") {
-            formatted_name.insert_str(0, "clippy::");
-        }
-        formatted_name = formatted_name.replace('_', "-");
-
-        formatted_names.insert(formatted_name, lint_arg);
+    for symlink in copy_dir(path, &fix_dir)? {
+        progress_bar.println(
+            &crate_name,
+            format_args!("{} - symlink not copied", symlink.display()),
+        );
     }
+    let fix_errors = run_fix(
+        progress_bar,
+        clippy_workspace,
+        cargo_target_dir,
+        lints,
+        &fix_dir,
+        &crate_name,
+        config,
+    )?;
+    let side_effects = match &config.side_effect_lints {
+        Some(side_effect_lints) if fix_errors.is_none() => fix_side_effects(
+            progress_bar,
+            clippy_workspace,
+            cargo_target_dir,
+            side_effect_lints,
+            path,
+            &fix_dir,
+            config,
+        )?,
+        _ => vec![],
+    };
+    if fix_errors.is_none() && !config.keep_fix_artifacts {
+        remove_fix_artifacts(&fix_dir)?;
+    }
+    Ok((fix_errors, side_effects))
+}
 
-    let mut clippy_driver = clippy_workspace.make_clippy_command(ClippyBin::ClippyDriver);
-    let output = clippy_driver
-        .arg("-W")
-        .arg("help")
-        .output()
-        .context("Running Clippy driver help")?;
-
-    if !output.status.success() {
-        bail!("Command to check lint names failed");
+// Where a crate named `name` is copied to be fixed. Crates in different dirs can share a name (e.g.
+// the packages of a git target) so a counter is appended if a previous crate took the name.
+fn fix_destination(fix_dir: &Path, name: &OsStr) -> PathBuf {
+    let mut destination = fix_dir.join(name);
+    let mut counter = 2;
+    while fs::symlink_metadata(&destination).is_ok() {
+        let mut numbered = name.to_owned();
+        numbered.push(format!("-{counter}"));
+        destination = fix_dir.join(numbered);
+        counter += 1;
     }
+    destination
+}
 
-    let mut lints = Vec::with_capacity(lint_args.len());
-    let stdout = std::str::from_utf8(&output.stdout).context("Converting Cargo output to str")?;
-    for help_lint in stdout
-        .lines()
-        .skip_while(|l| !l.starts_with("Lint checks provided by plugins"))
-        .skip(1)
-        .take_while(|l| !l.starts_with("Lint groups provided by plugins"))
-        .filter_map(|l| l.split_whitespace().next())
-    {
-        if formatted_names.remove(help_lint).is_some() {
-            lints.push(help_lint.replace('-', "_"));
-        }
+// The target dir of the second lint of `--check-determinism`, inside the first one.
+const DETERMINISM_TARGET_DIR: &str = "determinism";
 
-        if formatted_names.is_empty() {
-            break;
+// Lint the crate again in a separate target dir. `None` if the build failed this time.
+fn relint_warning_count(
+    progress_bar: &mut ProgressBar,
+    clippy_workspace: &ClippyWorkspace,
+    cargo_target_dir: &Path,
+    lints: &[String],
+    path: &Path,
+    config: &RunConfig,
+) -> Result<Option<usize>> {
+    let crate_name = crate_name(path);
+    let config = config.for_recount();
+
+    touch_crate_roots_with_extra_roots(path, &config.manifest_name, &config.extra_crate_roots)
+        .context("Touching crate roots")?;
+    let mut command = make_lint_command(
+        clippy_workspace,
+        &cargo_target_dir.join(DETERMINISM_TARGET_DIR),
+        path,
+        lints,
+        &config,
+    );
+    match run_lint_command(progress_bar, &mut command, lints, &crate_name, &config)? {
+        LintCommandOutcome::Success { stats, .. } => Ok(Some(stats.warning_count)),
+        LintCommandOutcome::Failed { .. } => {
+            progress_bar.println(
+                &crate_name,
+                format_args!("{crate_name} - build failed while checking determinism"),
+            );
+            Ok(None)
         }
     }
+}
 
-    let mut missing_args = formatted_names.values();
-    if let Some(first) = missing_args.next() {
-        let mut error_message = format!("Lints not found: `{}`", first);
-        for arg in missing_args {
-            write!(error_message, ", `{}`", arg).expect("Write to string succeeds");
+// Lint both the crate and its fixed copy with `lints` and return the lints with more warnings in
+// the fixed copy with the number added. Nothing is reported if either fails to build.
+fn fix_side_effects(
+    progress_bar: &mut ProgressBar,
+    clippy_workspace: &ClippyWorkspace,
+    cargo_target_dir: &Path,
+    lints: &[String],
+    path: &Path,
+    fixed_path: &Path,
+    config: &RunConfig,
+) -> Result<FixSideEffects> {
+    let crate_name = crate_name(path);
+    let config = config.for_recount();
+
+    let mut lint_counts = vec![];
+    for path in [path, fixed_path] {
+        touch_crate_roots_with_extra_roots(path, &config.manifest_name, &config.extra_crate_roots)
+            .context("Touching crate roots")?;
+        let mut command =
+            make_lint_command(clippy_workspace, cargo_target_dir, path, lints, &config);
+        match run_lint_command(progress_bar, &mut command, lints, &crate_name, &config)? {
+            LintCommandOutcome::Success { stats, .. } => lint_counts.push(stats.lint_counts),
+            LintCommandOutcome::Failed { .. } => {
+                progress_bar.println(
+                    &crate_name,
+                    format_args!(
+                        "{crate_name} - build failed while checking the fix's side-effects"
+                    ),
+                );
+                return Ok(vec![]);
+            }
         }
-        bail!(error_message);
     }
 
-    Ok(lints)
+    let (before, after) = (&lint_counts[0], &lint_counts[1]);
+    let side_effects: Vec<_> = after
+        .iter()
+        .filter_map(|(lint, &count)| {
+            let added = count.saturating_sub(before.get(lint).copied().unwrap_or(0));
+            (added > 0).then(|| (lint.clone(), added))
+        })
+        .collect();
+    if !side_effects.is_empty() {
+        progress_bar.println(
+            &crate_name,
+            format_args!(
+                "{crate_name} - fix added warnings: {}",
+                format_side_effects(&side_effects)
+            ),
+        );
+    }
+    Ok(side_effects)
 }
 
-fn make_lint_command(
-    clippy_workspace: &ClippyWorkspace,
-    cargo_target_dir: &Path,
-    path: &Path,
-) -> Command {
-    let mut command = clippy_workspace.make_clippy_command(ClippyBin::CargoClippy);
-    command
-        .arg("--")
-        .arg("--quiet")
-        .arg("--message-format=json")
-        .arg("--target-dir")
-        .arg(cargo_target_dir)
-        .arg("--")
-        .arg("--cap-lints")
-        .arg("warn")
-        .arg("--allow")
-        .arg("clippy::all")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .current_dir(path);
-    command
+// e.g. `clippy::needless_return +1, clippy::redundant_clone +2`
+fn format_side_effects(side_effects: &[(String, usize)]) -> String {
+    side_effects
+        .iter()
+        .map(|(lint, added)| format!("{lint} +{added}"))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
-fn run_lint(
+// Lint a crate that failed to build with `--all-targets` using only the default targets.
+fn run_lint_default_targets(
     progress_bar: &mut ProgressBar,
     clippy_workspace: &ClippyWorkspace,
     cargo_target_dir: &Path,
     lints: &[impl AsRef<str>],
     path: &Path,
     fix_dir: Option<&Path>,
+    config: &RunConfig,
 ) -> Result<LintResult> {
-    let crate_name = crate_name(path);
-
-    if !path.is_dir() || !path.join("Cargo.toml").exists() {
-        return Ok(LintResult::InvalidCrate);
+    let config = RunConfig {
+        all_targets: false,
+        ..config.clone()
+    };
+    let mut result = run_lint(
+        progress_bar,
+        clippy_workspace,
+        cargo_target_dir,
+        lints,
+        path,
+        fix_dir,
+        &config,
+    )?;
+    if let LintResult::Success {
+        all_targets_failed, ..
+    } = &mut result
+    {
+        *all_targets_failed = true;
     }
+    Ok(result)
+}
 
-    // Touch the crate roots to force recompilation.
-    // Cargo can't detect changes to Clippy's source.
-    touch_crate_roots(path).context("Touching crate roots")?;
+enum LintCommandOutcome {
+    Success {
+        stats: CrateStats,
+        // Whether Cargo reported any compiled artifacts.
+        compiled: bool,
+    },
+    Failed {
+        errors: String,
+    },
+}
 
-    let mut cargo_clippy = make_lint_command(clippy_workspace, cargo_target_dir, path);
-    for name in lints {
-        cargo_clippy.arg("--warn").arg(name.as_ref());
+// The span a diagnostic is reported at. The first span if none are marked as primary.
+fn primary_span(spans: &[DiagnosticSpan]) -> Option<&DiagnosticSpan> {
+    spans
+        .iter()
+        .find(|span| span.is_primary)
+        .or_else(|| spans.first())
+}
+
+// The extent of a warning's primary span.
+#[derive(Clone, Copy)]
+struct SpanSize {
+    lines: usize,
+    bytes: usize,
+}
+
+impl SpanSize {
+    fn new(span: &DiagnosticSpan) -> Self {
+        SpanSize {
+            lines: span.line_end.saturating_sub(span.line_start) + 1,
+            bytes: span.byte_end.saturating_sub(span.byte_start) as usize,
+        }
     }
+}
 
-    let mut child = cargo_clippy.spawn().expect("command succeeds");
+// Whether the primary span of a diagnostic is in the crate being tested. Files of the crate are
+// relative to it while dependencies are in the Cargo registry, `_target` or elsewhere outside.
+fn is_in_crate(spans: &[DiagnosticSpan]) -> bool {
+    primary_span(spans).is_none_or(|span| {
+        let path = Path::new(&span.file_name);
+        path.is_relative()
+            && !path
+                .components()
+                .any(|c| c == std::path::Component::ParentDir)
+    })
+}
 
+#[allow(clippy::too_many_lines)]
+fn run_lint_command(
+    progress_bar: &mut ProgressBar,
+    cargo_clippy: &mut Command,
+    lints: &[impl AsRef<str>],
+    crate_name: &str,
+    config: &RunConfig,
+) -> Result<LintCommandOutcome> {
+    // Dependencies are the artifacts from outside the crate's dir, which Cargo is run in.
+    let crate_dir =
+        env::current_dir()?.join(cargo_clippy.get_current_dir().unwrap_or(Path::new("")));
+    let start = Instant::now();
+    let (mut child, _guard) = interrupt::spawn(cargo_clippy).expect("command succeeds");
+
+    // When the last dependency finished building
+    let mut dependencies_built = Duration::ZERO;
     let mut warning_count = 0;
+    let mut lint_counts = BTreeMap::new();
+    let mut messages = vec![];
+    let mut span_sizes = vec![];
+    let mut macro_hits = 0;
+    let mut diagnostics = vec![];
+    let mut target_counts = BTreeMap::new();
+    // The target each warning was first reported for. Cargo reports a warning again for each
+    // target which includes the file (e.g. a module shared by a lib and a bin).
+    let mut seen_warnings = HashMap::new();
+    let mut compiled = false;
+    // The JSON of each counted warning. Only collected for `--json-diagnostics-dir`.
+    let mut json_messages = String::new();
 
     let reader = std::io::BufReader::new(child.stdout.take().expect("stdout piped"));
     for message in cargo_metadata::Message::parse_stream(reader) {
-        if let Message::CompilerMessage(CompilerMessage {
+        let message = message.context("parsing Cargo messages")?;
+        // Serialized before it's taken apart below.
+        let json = config
+            .json_diagnostics_dir
+            .is_some()
+            .then(|| serde_json::to_string(&message))
+            .transpose()?;
+        if let Message::CompilerArtifact(artifact) = &message {
+            compiled = true;
+            let src_path = Path::new(artifact.target.src_path.as_str());
+            if src_path.is_absolute() && !src_path.starts_with(&crate_dir) {
+                dependencies_built = start.elapsed();
+            }
+        } else if let Message::CompilerMessage(CompilerMessage {
             message:
                 Diagnostic {
+                    message,
                     code: Some(DiagnosticCode { code, .. }),
+                    level,
                     spans,
                     rendered: Some(rendered),
                     ..
                 },
+            target,
             ..
-        }) = message.context("parsing Cargo messages")?
+        }) = message
         {
-            if lints.iter().any(|name| code == name.as_ref()) {
+            if lints
+                .iter()
+                .any(|name| lint_code_matches(&code, name.as_ref()))
+                && (config.count_deps || is_in_crate(&spans))
+            {
+                let target = target_label(&target);
+                if let Some(span) = spans.first() {
+                    let key = (
+                        code.clone(),
+                        span.file_name.clone(),
+                        span.line_start,
+                        span.column_start,
+                        message.clone(),
+                    );
+                    if *seen_warnings.entry(key).or_insert_with(|| target.clone()) != target {
+                        continue;
+                    }
+                }
+                if config.exclude_macro_hits
+                    && !spans.is_empty()
+                    && spans.iter().all(|span| span.expansion.is_some())
+                {
+                    macro_hits += 1;
+                    continue;
+                }
+                if config.by_target {
+                    *target_counts.entry(target).or_insert(0) += 1;
+                }
+                if let Some(json) = json {
+                    json_messages.push_str(&json);
+                    json_messages.push('\n');
+                }
                 warning_count += 1;
-                let span = &spans[0];
-                progress_bar.println(&crate_name, "");
-                progress_bar.println(
-                    &crate_name,
-                    &format_args!(
-                        "---> {}/{}:{}:{}",
-                        &crate_name, span.file_name, span.line_start, span.column_start
+                *lint_counts.entry(code.clone()).or_insert(0) += 1;
+                if config.common_messages {
+                    messages.push(normalize_message(&message));
+                }
+                if config.span_stats {
+                    span_sizes.extend(primary_span(&spans).map(SpanSize::new));
+                }
+                if config.count_only && !config.collect_diagnostics {
+                    continue;
+                }
+                let location = config.warning_location(crate_name, &code, &spans[0]);
+                let rendered = config.format_rendered(rendered.trim_end());
+                if config.collect_diagnostics {
+                    diagnostics.push((code.clone(), format!("{location}\n{rendered}")));
+                }
+                if !config.count_only
+                    && config
+                        .max_warnings_per_crate
+                        .is_none_or(|max| warning_count <= max)
+                {
+                    progress_bar.println(crate_name, "");
+                    progress_bar.println(crate_name, &location);
+                    progress_bar.println(crate_name, rendered);
+                }
+            } else if config.context_level == ContextLevel::Warn
+                && !config.count_only
+                && level == DiagnosticLevel::Warning
+                && code.starts_with("clippy::")
+                && is_in_crate(&spans)
+            {
+                progress_bar.println(crate_name, "");
+                match primary_span(&spans) {
+                    Some(span) => progress_bar.println(
+                        crate_name,
+                        format_args!("{} (context)", config.span_location(crate_name, span)),
                     ),
-                );
-                progress_bar.println(&crate_name, &rendered.trim_end());
+                    None => progress_bar.println(crate_name, "(context)"),
+                }
+                progress_bar.println(crate_name, config.format_rendered(rendered.trim_end()));
             }
         }
     }
 
-    let status = child.wait().context("Waiting for Cargo command")?;
+    if let Some(max) = config.max_warnings_per_crate {
+        if !config.count_only && warning_count > max {
+            progress_bar.println(crate_name, "");
+            progress_bar.println(
+                crate_name,
+                format_args!("... and {} more warnings", warning_count - max),
+            );
+        }
+    }
 
-    if !status.success() {
-        progress_bar.println(&crate_name, "");
+    let (status, peak_rss) = wait_with_peak_rss(&mut child).context("Waiting for Cargo command")?;
+    let timing = CrateTiming {
+        dependencies: dependencies_built,
+        lint: start.elapsed().saturating_sub(dependencies_built),
+    };
 
-        let mut errors = String::new();
-        child
-            .stderr
-            .take()
-            .expect("stderr piped")
-            .read_to_string(&mut errors)
-            .context("Reading stderr")?;
+    if let Some(dir) = &config.json_diagnostics_dir {
+        let path = dir.join(format!("{crate_name}.json"));
+        fs::write(&path, json_messages).with_context(|| format!("Writing {}", path.display()))?;
+    }
 
-        let ice = errors.contains("internal compiler error: unexpected panic\n\nnote: the compiler unexpectedly panicked. this is a bug.");
+    if status.success() {
+        return Ok(LintCommandOutcome::Success {
+            stats: CrateStats {
+                warning_count,
+                lint_counts,
+                peak_rss,
+                messages,
+                span_sizes,
+                macro_hits,
+                diagnostics,
+                target_counts,
+                timing,
+            },
+            compiled,
+        });
+    }
 
-        progress_bar.println(
-            &crate_name,
-            &format_args!(
-                "{} - build failed{}",
-                &crate_name,
-                if ice { " (ICE)" } else { "" }
-            ),
-        );
-        progress_bar.println(
-            &crate_name,
-            &format_args!("Command used: `{}`", format_command(&cargo_clippy)),
-        );
+    let mut errors = String::new();
+    child
+        .stderr
+        .take()
+        .expect("stderr piped")
+        .read_to_string(&mut errors)
+        .context("Reading stderr")?;
+
+    Ok(LintCommandOutcome::Failed { errors })
+}
 
-        return Ok(LintResult::BuildFailed);
+// A short name for a target of a crate (e.g. `lib` or `bin foo`). Library targets are only named by
+// their kind as a crate has at most one.
+fn target_label(target: &cargo_metadata::Target) -> String {
+    let kind = target.kind.first().map_or("unknown", String::as_str);
+    match kind {
+        "lib" | "rlib" | "dylib" | "cdylib" | "staticlib" | "proc-macro" => "lib".into(),
+        "custom-build" => "build script".into(),
+        _ => format!("{kind} {}", target.name),
     }
+}
 
-    let mut fix_failed = false;
-    if warning_count > 0 && !lints.is_empty() {
-        if let Some(fix_dir) = fix_dir {
-            let fix_dir = fix_dir.join(path.file_name().expect("Path not '..'"));
-            copy_dir(progress_bar, path, &fix_dir)?;
-            let fix_success = run_fix(
-                progress_bar,
-                clippy_workspace,
-                cargo_target_dir,
-                lints,
-                &fix_dir,
-                &crate_name,
-            )?;
-            if !fix_success {
-                fix_failed = true;
+// Replace code quoted in backticks so that messages differing only by the code they quote are
+// grouped together.
+// e.g. "this `if` has identical blocks: `a == b`" -> "this `if` has identical blocks: `_`"
+// Quotes of keywords and other single words are kept as they're usually part of the message.
+fn normalize_message(message: &str) -> String {
+    let mut result = String::with_capacity(message.len());
+    let mut parts = message.split('`');
+    result.push_str(parts.next().unwrap_or_default());
+    while let Some(quoted) = parts.next() {
+        result.push('`');
+        if let Some(unquoted) = parts.next() {
+            if quoted.chars().all(|c| c.is_ascii_alphabetic() || c == '_') {
+                result.push_str(quoted);
+            } else {
+                result.push('_');
             }
+            result.push('`');
+            result.push_str(unquoted);
+        } else {
+            // Unmatched backtick
+            result.push_str(quoted);
         }
     }
+    result
+}
 
-    Ok(LintResult::Success {
-        warning_count,
-        fix_failed,
-    })
+// Cargo errors caused by contention on the shared target dir or package cache rather than
+// by the crate itself. These are worth retrying. Cargo's status lines about waiting on a lock
+// (e.g. `Blocking waiting for file lock`) aren't errors so they aren't included.
+const SPURIOUS_BUILD_ERRORS: &[&str] = &[
+    "failed to acquire package cache lock",
+    "could not acquire package cache lock",
+    "failed to lock file",
+    "Text file busy",
+];
+
+fn is_missing_target_failure(errors: &str) -> bool {
+    errors.contains("target may not be installed")
+}
+
+fn is_locked_failure(errors: &str) -> bool {
+    errors.contains("needs to be updated but --locked was passed")
+}
+
+fn is_spurious_build_failure(errors: &str) -> bool {
+    // Genuine compile errors are never retried, even if lock noise is also present. The compiler's
+    // errors are JSON on stdout but Cargo reports the failed compile on stderr.
+    !errors.contains("error[E")
+        && !errors.contains("error: could not compile")
+        && SPURIOUS_BUILD_ERRORS.iter().any(|e| errors.contains(e))
 }
 
 fn check_for_allows(
@@ -444,10 +3889,11 @@ fn check_for_allows(
     lints: &[impl AsRef<str>],
     path: &Path,
     crate_name: &str,
+    config: &RunConfig,
 ) -> Result<usize> {
-    let mut command = clippy_workspace.make_clippy_command(ClippyBin::CargoClippy);
+    let mut command = clippy_workspace.make_cargo_clippy_command();
+    config.add_cargo_args(&mut command);
     command
-        .arg("--")
         .arg("--target-dir")
         .arg(cargo_target_dir)
         .arg("--quiet")
@@ -465,7 +3911,7 @@ fn check_for_allows(
         .stderr(Stdio::piped())
         .current_dir(path);
 
-    let mut child = command.spawn().expect("command succeeds");
+    let (mut child, _guard) = interrupt::spawn(&mut command).expect("command succeeds");
     let reader = std::io::BufReader::new(child.stdout.take().expect("stdout piped"));
 
     let mut count = 0;
@@ -481,18 +3927,20 @@ fn check_for_allows(
             ..
         }) = message.context("parsing Cargo messages")?
         {
-            if code == "E0453" && spans.iter().all(|s| s.expansion.is_none()) {
+            if config.allow_codes.contains(&code) && spans.iter().all(|s| s.expansion.is_none()) {
                 count += 1;
-                let span = &spans[0];
-                progress_bar.println(
-                    crate_name,
-                    &format_args!(
-                        "---> {}/{}:{}:{}",
-                        &crate_name, span.file_name, span.line_start, span.column_start
-                    ),
-                );
+                // e.g. `unknown_lints` for a lint forbidden on the command line has no span.
+                if let Some(span) = primary_span(&spans) {
+                    progress_bar.println(
+                        crate_name,
+                        &format_args!(
+                            "---> {}/{}:{}:{}",
+                            &crate_name, span.file_name, span.line_start, span.column_start
+                        ),
+                    );
+                }
                 progress_bar.println(crate_name, "Allow found");
-                progress_bar.println(crate_name, &rendered);
+                progress_bar.println(crate_name, config.format_rendered(&rendered));
             }
         }
     }
@@ -500,7 +3948,7 @@ fn check_for_allows(
     Ok(count)
 }
 
-// Returns `true` if successful and `false` otherwise.
+// Returns `None` if successful and the errors from building the fixed code otherwise.
 fn run_fix(
     progress_bar: &mut ProgressBar,
     clippy_workspace: &ClippyWorkspace,
@@ -508,13 +3956,15 @@ fn run_fix(
     lints: &[impl AsRef<str>],
     path: &Path,
     crate_name: &str,
-) -> Result<bool> {
-    let mut fix_command = clippy_workspace.make_clippy_command(ClippyBin::CargoClippy);
+    config: &RunConfig,
+) -> Result<Option<Vec<String>>> {
+    let mut fix_command = clippy_workspace.make_cargo_clippy_command();
+    config.add_cargo_args(&mut fix_command);
 
     fix_command
-        .arg("--")
         .arg("--target-dir")
         .arg(cargo_target_dir)
+        .arg("--message-format=json")
         .arg("--fix")
         .arg("--broken-code")
         .arg("--allow-dirty")
@@ -530,69 +3980,59 @@ fn run_fix(
         fix_command.arg("--warn").arg(name.as_ref());
     }
 
-    let fix_output = fix_command
-        .current_dir(path)
-        .output()
-        .context("Executing fix command")?;
-
-    let success = fix_output.status.success();
+    let fix_output =
+        interrupt::output(fix_command.current_dir(path)).context("Executing fix command")?;
 
-    if success {
+    if fix_output.status.success() {
         progress_bar.println(crate_name, &format_args!("{} - fix succeeded", &crate_name));
-    } else {
-        progress_bar.println(crate_name, &format_args!("{} - fix failed", &crate_name));
-        let error =
-            std::str::from_utf8(&fix_output.stderr).context("Converting Cargo output to str")?;
-        progress_bar.println(crate_name, error);
+        return Ok(None);
     }
 
-    Ok(success)
-}
-
-fn copy_dir(_progress_bar: &mut ProgressBar, clippy_source: &Path, target: &Path) -> Result<()> {
-    for entry in WalkDir::new(clippy_source) {
-        let entry = entry.with_context(|| format!("Reading {}", clippy_source.display()))?;
-        let file_type = entry.file_type();
-
-        let entry_target = target.join(
-            entry
-                .path()
-                .strip_prefix(clippy_source)
-                .expect("Entries of source"),
-        );
-
-        if file_type.is_dir() {
-            fs::create_dir(&entry_target)
-                .with_context(|| format!("Creating {}", &entry_target.display()))?;
-        } else if file_type.is_file() {
-            fs::copy(entry.path(), &entry_target)
-                .with_context(|| format!("Copying {}", &entry_target.display()))?;
+    // The errors from building the fixed code, in the order first seen
+    let mut errors = vec![];
+    for message in Message::parse_stream(&fix_output.stdout[..]) {
+        if let Message::CompilerMessage(CompilerMessage {
+            message:
+                Diagnostic {
+                    message,
+                    code,
+                    level: DiagnosticLevel::Error,
+                    spans,
+                    ..
+                },
+            ..
+        }) = message.context("parsing Cargo messages")?
+        {
+            let error = match (code, primary_span(&spans)) {
+                (Some(DiagnosticCode { code, .. }), Some(span)) => {
+                    format!("{code} in {}", span.file_name)
+                }
+                (Some(DiagnosticCode { code, .. }), None) => code,
+                // e.g. from `compile_error!`
+                (None, Some(span)) => format!("{message} in {}", span.file_name),
+                // Only summarizes the other errors (e.g. `aborting due to 2 previous errors`).
+                (None, None) => continue,
+            };
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
         }
     }
 
-    Ok(())
-}
-
-fn format_command(command: &Command) -> String {
-    let mut result = String::new();
-
-    if let Some(current_dir) = command.get_current_dir() {
-        write!(
-            result,
-            "cd {} && ",
-            &shell_escape::escape(current_dir.to_string_lossy()),
-        )
-        .expect("Write to string succeeds");
+    if errors.is_empty() {
+        progress_bar.println(crate_name, &format_args!("{} - fix failed", &crate_name));
+    } else {
+        progress_bar.println(
+            crate_name,
+            format_args!("{} - fix failed with {}", &crate_name, errors.join(", ")),
+        );
     }
-
-    result.push_str(&shell_escape::escape(
-        command.get_program().to_string_lossy(),
-    ));
-
-    for arg in command.get_args() {
-        write!(result, " {}", &shell_escape::escape(arg.to_string_lossy()))
-            .expect("Write to string succeeds");
+    // The errors are enough to see what went wrong unless none could be found
+    if config.verbose_fix || errors.is_empty() {
+        let error =
+            std::str::from_utf8(&fix_output.stderr).context("Converting Cargo output to str")?;
+        progress_bar.println(crate_name, error);
     }
 
-    result
+    Ok(Some(errors))
 }
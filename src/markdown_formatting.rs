@@ -1,12 +1,15 @@
 use std::borrow::Cow;
-use std::fmt::Display;
+use std::fmt::{self, Display};
 use std::io::Write;
 use std::iter::IntoIterator;
 use std::ops::Deref;
+use std::time::Duration;
 
 use anyhow::Result;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
+#[derive(Clone, Copy)]
 pub enum Alignment {
     Left,
     Center,
@@ -23,7 +26,8 @@ pub trait TableDisplay: Display {
 
 impl TableDisplay for &str {
     fn display_width(&self) -> usize {
-        self.graphemes(true).count()
+        // Per grapheme so that e.g. an emoji sequence isn't counted as several emoji.
+        self.graphemes(true).map(UnicodeWidthStr::width).sum()
     }
 }
 
@@ -50,6 +54,76 @@ impl TableDisplay for usize {
     }
 }
 
+// A duration displayed for humans, e.g. `1.2s` or `3m04s`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Seconds(pub Duration);
+
+impl Display for Seconds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        if secs < 60 {
+            // Truncated rather than rounded so that 59.99s isn't shown as 60.0s
+            write!(f, "{}.{}s", secs, self.0.subsec_millis() / 100)
+        } else {
+            write!(f, "{}m{:02}s", secs / 60, secs % 60)
+        }
+    }
+}
+
+// A count per thousand lines to two decimal places, e.g. `2.50`. Shown as `-` without any lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PerKloc {
+    pub count: usize,
+    pub lines: usize,
+}
+
+impl Display for PerKloc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.lines == 0 {
+            return write!(f, "-");
+        }
+        // Integer maths rounded to the nearest hundredth
+        let hundredths = (self.count * 100_000 + self.lines / 2) / self.lines;
+        write!(f, "{}.{:02}", hundredths / 100, hundredths % 100)
+    }
+}
+
+// A part of a whole as a percentage to one decimal place, e.g. `42.3%`. Shown as `-` for an empty
+// whole.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Percent {
+    pub part: usize,
+    pub whole: usize,
+}
+
+impl Display for Percent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.whole == 0 {
+            return write!(f, "-");
+        }
+        // Integer maths rounded to the nearest tenth
+        let tenths = (self.part * 1000 + self.whole / 2) / self.whole;
+        write!(f, "{}.{}%", tenths / 10, tenths % 10)
+    }
+}
+
+// Right aligned numeric types whose text is always ASCII.
+macro_rules! impl_ascii_table_display {
+    ($($ty:ty),*) => {$(
+        impl TableDisplay for $ty {
+            fn display_width(&self) -> usize {
+                self.to_string().len()
+            }
+
+            fn alignment() -> Alignment {
+                Alignment::Right
+            }
+        }
+    )*};
+}
+
+impl_ascii_table_display!(Seconds, PerKloc, Percent);
+
 impl<'a> TableDisplay for Cow<'a, str> {
     fn display_width(&self) -> usize {
         self.deref().display_width()
@@ -69,58 +143,144 @@ where
     }
 }
 
+pub struct Column<'a> {
+    header: &'a str,
+    alignment: Alignment,
+    // Longer values (including the header) are truncated with an ellipsis. `None` for no limit.
+    max_width: Option<usize>,
+}
+
+impl<'a> Column<'a> {
+    #[must_use]
+    pub fn new(header: &'a str, alignment: Alignment) -> Self {
+        Column {
+            header,
+            alignment,
+            max_width: None,
+        }
+    }
+
+    #[must_use]
+    pub fn max_width(mut self, max_width: usize) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+}
+
+pub struct Cell {
+    text: String,
+    width: usize,
+}
+
+impl Cell {
+    pub fn new(value: impl TableDisplay) -> Self {
+        Cell {
+            text: value.to_string(),
+            width: value.display_width(),
+        }
+    }
+}
+
 pub fn print_table<A, B>(
     headers: [&str; 2],
-    data: impl IntoIterator<Item = (A, B)> + Copy,
-    mut output: impl Write,
+    data: impl IntoIterator<Item = (A, B)> + Clone,
+    output: impl Write,
 ) -> Result<()>
 where
     A: TableDisplay,
     B: TableDisplay,
 {
-    let widths: [usize; 2] = data.into_iter().fold(
-        [headers[0].display_width(), headers[1].display_width()],
-        |widths, (a, b)| {
-            [
-                widths[0].max(a.display_width()),
-                widths[1].max(b.display_width()),
-            ]
-        },
-    );
+    let columns = [
+        Column::new(headers[0], A::alignment()),
+        Column::new(headers[1], B::alignment()),
+    ];
+    let rows: Vec<_> = data
+        .into_iter()
+        .map(|(a, b)| vec![Cell::new(a), Cell::new(b)])
+        .collect();
+    print_multi_column_table(&columns, &rows, output)
+}
 
-    match A::alignment() {
-        Alignment::Left => write!(output, " {0:<1$} ", headers[0], widths[0])?,
-        Alignment::Center => write!(output, " {0:^1$} ", headers[0], widths[0])?,
-        Alignment::Right => write!(output, " {0:>1$} ", headers[0], widths[0])?,
-    }
+pub fn print_multi_column_table(
+    columns: &[Column<'_>],
+    rows: &[Vec<Cell>],
+    mut output: impl Write,
+) -> Result<()> {
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let width = rows
+                .iter()
+                .map(|row| row[i].width)
+                .fold(column.header.display_width(), usize::max);
+            // At least the ellipsis is shown
+            column.max_width.map_or(width, |max| width.min(max.max(1)))
+        })
+        .collect();
 
-    write!(output, "|")?;
+    let header_cells: Vec<_> = columns.iter().map(|c| Cell::new(c.header)).collect();
+    write_row(&mut output, columns, &widths, &header_cells)?;
 
-    match B::alignment() {
-        Alignment::Left => writeln!(output, " {0:<1$} ", headers[1], widths[1])?,
-        Alignment::Center => writeln!(output, " {0:^1$} ", headers[1], widths[1])?,
-        Alignment::Right => writeln!(output, " {0:>1$} ", headers[1], widths[1])?,
+    for (i, (column, width)) in columns.iter().zip(&widths).enumerate() {
+        if i > 0 {
+            write!(output, "|")?;
+        }
+        match column.alignment {
+            Alignment::Left => write!(output, ":{0:-^1$}", "", width + 1)?,
+            Alignment::Center => write!(output, ":{0:-^1$}:", "", width - 1)?,
+            Alignment::Right => write!(output, "{0:-^1$}:", "", width + 1)?,
+        }
     }
+    writeln!(output)?;
 
-    match A::alignment() {
-        Alignment::Left => write!(output, ":{0:-^1$}", "", widths[0] + 1)?,
-        Alignment::Center => write!(output, ":{0:-^1$}:", "", widths[0] - 1)?,
-        Alignment::Right => write!(output, "{0:-^1$}:", "", widths[0] + 1)?,
+    for row in rows {
+        write_row(&mut output, columns, &widths, row)?;
     }
 
-    write!(output, "|")?;
+    Ok(())
+}
 
-    match B::alignment() {
-        Alignment::Left => writeln!(output, ":{0:-^1$}", "", widths[1] + 1)?,
-        Alignment::Center => writeln!(output, ":{0:-^1$}:", "", widths[1] - 1)?,
-        Alignment::Right => writeln!(output, "{0:-^1$}:", "", widths[1] + 1)?,
+fn write_row(
+    output: &mut impl Write,
+    columns: &[Column<'_>],
+    widths: &[usize],
+    cells: &[Cell],
+) -> Result<()> {
+    for (i, ((column, width), cell)) in columns.iter().zip(widths).zip(cells).enumerate() {
+        if i > 0 {
+            write!(output, "|")?;
+        }
+        let text = truncate(&cell.text, *width);
+        // Pad using the rendered text as `display_width` may overestimate (e.g. for `usize`).
+        let padding = width - text.display_width().min(*width);
+        let (left, right) = match column.alignment {
+            Alignment::Left => (0, padding),
+            Alignment::Center => (padding / 2, padding - padding / 2),
+            Alignment::Right => (padding, 0),
+        };
+        write!(output, " {0:2$}{1}{0:3$} ", "", text, left, right)?;
     }
+    writeln!(output)?;
+    Ok(())
+}
 
-    for (a, b) in data {
-        writeln!(output, " {0:1$} | {2:3$} ", a, widths[0], b, widths[1])?;
+// Shorten text wider than `width` to fit, ending it with an ellipsis.
+fn truncate(text: &str, width: usize) -> Cow<'_, str> {
+    if text.display_width() <= width {
+        return text.into();
     }
-
-    Ok(())
+    let mut truncated = String::new();
+    let mut truncated_width = 0;
+    for grapheme in text.graphemes(true) {
+        truncated_width += grapheme.width();
+        if truncated_width >= width {
+            break;
+        }
+        truncated.push_str(grapheme);
+    }
+    truncated.push('…');
+    truncated.into()
 }
 
 #[cfg(test)]
@@ -144,4 +304,188 @@ mod test {
         "#]];
         expected.assert_eq(&s);
     }
+
+    #[test]
+    fn print_multiple_columns() {
+        let columns = [
+            Column::new("Crate", Alignment::Left),
+            Column::new("A", Alignment::Right),
+            Column::new("Middle", Alignment::Center),
+        ];
+        let rows = vec![
+            vec![Cell::new("a"), Cell::new(1), Cell::new("x")],
+            vec![Cell::new("bbbbbbb"), Cell::new(1234), Cell::new("yyy")],
+        ];
+
+        let mut v = vec![];
+        print_multi_column_table(&columns, &rows, &mut v).unwrap();
+        let s = String::from_utf8(v).unwrap();
+        let expected = expect![[r"
+             Crate   |     A | Middle 
+            :--------|------:|:-----:
+             a       |     1 |   x    
+             bbbbbbb |  1234 |  yyy   
+        "]];
+        expected.assert_eq(&s);
+    }
+
+    #[test]
+    fn print_truncated_column() {
+        let columns = [
+            Column::new("Crate", Alignment::Left).max_width(8),
+            Column::new("Count", Alignment::Right).max_width(3),
+        ];
+        let rows = vec![
+            vec![Cell::new("a-really-long-name-1.0.0"), Cell::new(1)],
+            vec![Cell::new("exactly8"), Cell::new(22)],
+            vec![Cell::new("ünïcödé-ñame"), Cell::new(333)],
+        ];
+
+        let mut v = vec![];
+        print_multi_column_table(&columns, &rows, &mut v).unwrap();
+        let s = String::from_utf8(v).unwrap();
+        let expected = expect![[r"
+             Crate    | Co… 
+            :---------|----:
+             a-reall… |   1 
+             exactly8 |  22 
+             ünïcödé… | 333 
+        "]];
+        expected.assert_eq(&s);
+    }
+
+    #[test]
+    fn wide_characters() {
+        assert_eq!("クレート".display_width(), 8);
+        assert_eq!("ｆｕｌｌ".display_width(), 8);
+        assert_eq!("a漢b".display_width(), 4);
+        assert_eq!("e\u{301}".display_width(), 1);
+
+        let mut v = vec![];
+        print_table(["Crate", "Count"], [("クレート", 1), ("serde", 22)], &mut v).unwrap();
+        let s = String::from_utf8(v).unwrap();
+        let expected = expect![[r"
+             Crate    | Count 
+            :---------|------:
+             クレート |     1 
+             serde    |    22 
+        "]];
+        expected.assert_eq(&s);
+
+        let columns = [Column::new("Message", Alignment::Left).max_width(7)];
+        let rows = [
+            vec![Cell::new("漢字の警告メッセージ")],
+            vec![Cell::new("ascii text")],
+        ];
+        let mut v = vec![];
+        print_multi_column_table(&columns, &rows, &mut v).unwrap();
+        let s = String::from_utf8(v).unwrap();
+        let expected = expect![[r"
+             Message 
+            :--------
+             漢字の… 
+             ascii … 
+        "]];
+        expected.assert_eq(&s);
+    }
+
+    #[test]
+    fn seconds_display() {
+        for (duration, text) in [
+            (Duration::from_millis(0), "0.0s"),
+            (Duration::from_millis(240), "0.2s"),
+            (Duration::from_millis(1_230), "1.2s"),
+            (Duration::from_millis(59_990), "59.9s"),
+            (Duration::from_secs(61), "1m01s"),
+            (Duration::from_secs(184), "3m04s"),
+            (Duration::from_secs(6_001), "100m01s"),
+        ] {
+            let seconds = Seconds(duration);
+            assert_eq!(seconds.to_string(), text);
+            assert_eq!(seconds.display_width(), text.len());
+        }
+    }
+
+    #[test]
+    fn per_kloc_display() {
+        for (count, lines, text) in [
+            (0, 0, "-"),
+            (3, 0, "-"),
+            (0, 500, "0.00"),
+            (2, 500, "4.00"),
+            (1, 3, "333.33"),
+            (1, 1_500, "0.67"),
+        ] {
+            let density = PerKloc { count, lines };
+            assert_eq!(density.to_string(), text);
+            assert_eq!(density.display_width(), text.len());
+        }
+    }
+
+    #[test]
+    fn percent_display() {
+        for (part, whole, text) in [
+            (0, 0, "-"),
+            (1, 20, "5.0%"),
+            (423, 1_000, "42.3%"),
+            (2, 3, "66.7%"),
+            (7, 7, "100.0%"),
+        ] {
+            let percent = Percent { part, whole };
+            assert_eq!(percent.to_string(), text);
+            assert_eq!(percent.display_width(), text.len());
+        }
+    }
+
+    #[test]
+    fn print_percent_column() {
+        let mut v = vec![];
+        print_table(
+            ["Crate", "Fixed"],
+            [
+                ("a", Percent { part: 1, whole: 20 }),
+                (
+                    "b",
+                    Percent {
+                        part: 423,
+                        whole: 1_000,
+                    },
+                ),
+                ("c", Percent { part: 7, whole: 7 }),
+            ],
+            &mut v,
+        )
+        .unwrap();
+        let s = String::from_utf8(v).unwrap();
+        let expected = expect![[r"
+             Crate |  Fixed 
+            :------|-------:
+             a     |   5.0% 
+             b     |  42.3% 
+             c     | 100.0% 
+        "]];
+        expected.assert_eq(&s);
+    }
+
+    #[test]
+    fn print_seconds_column() {
+        let mut v = vec![];
+        print_table(
+            ["Crate", "Time"],
+            [
+                ("a", Seconds(Duration::from_millis(1_230))),
+                ("b", Seconds(Duration::from_secs(184))),
+            ],
+            &mut v,
+        )
+        .unwrap();
+        let s = String::from_utf8(v).unwrap();
+        let expected = expect![[r"
+             Crate |  Time 
+            :------|------:
+             a     |  1.2s 
+             b     | 3m04s 
+        "]];
+        expected.assert_eq(&s);
+    }
 }
@@ -3,7 +3,9 @@
 // previous display with spaces.
 
 use atty;
+use std::collections::VecDeque;
 use std::fmt::{self, Display};
+use std::str::FromStr;
 use std::time::{Duration, Instant};
 
 struct ProgressBarState {
@@ -11,10 +13,54 @@ struct ProgressBarState {
     current: usize,
     total: usize,
     displayed: bool,
+    tally: Option<Tally>,
+    color: bool,
+    last_draw: Instant,
+    // Redraws closer together than this are skipped unless all tasks are done
+    refresh_interval: Duration,
+    // A redraw was skipped so the display is out of date
+    pending: bool,
+    // When the last task finished
+    last_inc: Instant,
+    // How long each of the last `MOVING_AVERAGE_TASKS` tasks took, oldest first
+    recent: VecDeque<Duration>,
+    template: ProgressTemplate,
+}
+
+// The outcome of a single task, counted in the progress bar's badge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Succeeded,
+    Warned,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Tally {
+    succeeded: usize,
+    warned: usize,
+    failed: usize,
+}
+
+impl Tally {
+    fn record(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Succeeded => self.succeeded += 1,
+            Outcome::Warned => self.warned += 1,
+            Outcome::Failed => self.failed += 1,
+        }
+    }
 }
 
 const WIDTH: usize = 70;
 
+// The number of tasks whose average duration is used for the ETA. Tasks often speed up as the
+// run goes on (e.g. once shared dependencies are built), which an average over every task is
+// slow to reflect. Until this many are done the average over every task is used.
+const MOVING_AVERAGE_TASKS: usize = 10;
+
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
 impl ProgressBarState {
     pub fn new(total: usize) -> ProgressBarState {
         ProgressBarState {
@@ -22,33 +68,94 @@ impl ProgressBarState {
             current: 0,
             total,
             displayed: false,
+            tally: None,
+            color: false,
+            last_draw: Instant::now(),
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            pending: false,
+            last_inc: Instant::now(),
+            recent: VecDeque::with_capacity(MOVING_AVERAGE_TASKS),
+            template: ProgressTemplate::default(),
         }
     }
 
+    pub fn record(&mut self, outcome: Outcome) {
+        self.tally
+            .get_or_insert_with(Tally::default)
+            .record(outcome);
+    }
+
     pub fn inc(&mut self, message: &str) {
-        self.current += 1;
+        self.finish_task(Instant::now());
         self.redraw(message);
     }
 
+    fn finish_task(&mut self, now: Instant) {
+        self.current += 1;
+        if self.recent.len() == MOVING_AVERAGE_TASKS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(now.duration_since(self.last_inc));
+        self.last_inc = now;
+    }
+
+    // The estimated time until all tasks are done. Needs at least two tasks done.
+    fn eta(&self, now: Instant) -> Option<Duration> {
+        if self.current <= 1 {
+            return None;
+        }
+        let remaining = u32::try_from(self.total.saturating_sub(self.current)).unwrap_or(u32::MAX);
+        let per_task = if self.recent.len() == MOVING_AVERAGE_TASKS {
+            self.recent.iter().sum::<Duration>() / u32::try_from(MOVING_AVERAGE_TASKS).ok()?
+        } else {
+            now.duration_since(self.start) / u32::try_from(self.current).ok()?
+        };
+        per_task.checked_mul(remaining)
+    }
+
+    // Redraw unless the last draw was too recent, in which case it's left for `flush`.
     pub fn redraw(&mut self, message: &str) {
-        self.displayed = true;
+        if self.should_redraw(Instant::now()) {
+            self.draw(message);
+        } else {
+            self.pending = true;
+        }
+    }
+
+    // Make up for a skipped redraw once the last draw is no longer too recent.
+    pub fn flush(&mut self, message: &str) {
+        if self.pending && self.should_redraw(Instant::now()) {
+            self.draw(message);
+        }
+    }
 
-        let total: u128 = self.total.try_into().expect("total fits u64");
-        let current: u128 = self.current.try_into().expect("current <= total");
-        let elapsed_ms = Instant::now().duration_since(self.start).as_millis();
-        let ms_per_task = (current > 1).then(|| elapsed_ms / current);
-        let eta_ms = ms_per_task.map(|x| (total - current) * x);
-        let eta_duration =
-            eta_ms.map(|x| Duration::from_millis(x.try_into().expect("fits into u64")));
+    fn should_redraw(&self, now: Instant) -> bool {
+        !self.displayed
+            || self.current >= self.total
+            || now.duration_since(self.last_draw) >= self.refresh_interval
+    }
 
-        let display = self.display(message, eta_duration, WIDTH);
+    // Draw regardless of when the last draw was. Needed after the line has been cleared.
+    pub fn draw(&mut self, message: &str) {
+        self.displayed = true;
+        self.pending = false;
+        self.last_draw = Instant::now();
+
+        let now = Instant::now();
+        let display = self.display(
+            message,
+            self.eta(now),
+            now.duration_since(self.start),
+            WIDTH,
+        );
         eprint!("\r{}", display);
     }
 
     fn display<'a>(
-        &self,
+        &'a self,
         message: &'a str,
         eta: Option<Duration>,
+        elapsed: Duration,
         width: usize,
     ) -> ProgressBarDisplay<'a> {
         ProgressBarDisplay {
@@ -56,7 +163,13 @@ impl ProgressBarState {
             total: self.total,
             message,
             eta,
+            elapsed,
             width,
+            template: &self.template,
+            badge: self.tally.map(|tally| TallyBadge {
+                tally,
+                color: self.color,
+            }),
         }
     }
 }
@@ -78,77 +191,330 @@ fn num_digits(mut value: usize) -> usize {
     result
 }
 
+// Renders as `✓N !M ✗K`. The counts are colored green, yellow and red if `color` is set.
+struct TallyBadge {
+    tally: Tally,
+    color: bool,
+}
+
+impl TallyBadge {
+    // The number of columns used, ignoring color escape codes.
+    fn width(&self) -> usize {
+        num_digits(self.tally.succeeded).max(1)
+            + num_digits(self.tally.warned).max(1)
+            + num_digits(self.tally.failed).max(1)
+            + 5
+    }
+}
+
+impl fmt::Display for TallyBadge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = [
+            ("\x1b[32m", '✓', self.tally.succeeded),
+            ("\x1b[33m", '!', self.tally.warned),
+            ("\x1b[31m", '✗', self.tally.failed),
+        ];
+        for (i, (color, symbol, count)) in parts.into_iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            if self.color {
+                write!(f, "{color}{symbol}{count}\x1b[0m")?;
+            } else {
+                write!(f, "{symbol}{count}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// A part of a progress template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TemplatePart {
+    Text(String),
+    // The number of tasks done
+    Current,
+    Total,
+    // The message, padded or truncated to fill the rest of the width
+    Message,
+    // The outcome counts followed by a space. Empty until an outcome is recorded.
+    Tally,
+    // The bar without the brackets
+    Bar,
+    Eta,
+    // Tasks done per minute
+    Rate,
+    Percent,
+}
+
+const TEMPLATE_PLACEHOLDERS: [(&str, TemplatePart); 8] = [
+    ("n", TemplatePart::Current),
+    ("total", TemplatePart::Total),
+    ("name", TemplatePart::Message),
+    ("tally", TemplatePart::Tally),
+    ("bar", TemplatePart::Bar),
+    ("eta", TemplatePart::Eta),
+    ("rate", TemplatePart::Rate),
+    ("percent", TemplatePart::Percent),
+];
+
+pub const DEFAULT_PROGRESS_TEMPLATE: &str = "{n}/{total}: {tally}{name} [{bar}] {eta}";
+
+// The layout of the progress bar. Placeholders in braces (e.g. `{eta}`) are replaced with the
+// current values and `{{` and `}}` are literal braces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgressTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl Default for ProgressTemplate {
+    fn default() -> Self {
+        DEFAULT_PROGRESS_TEMPLATE
+            .parse()
+            .expect("default template is valid")
+    }
+}
+
+impl FromStr for ProgressTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some(end) = rest.find('}') else {
+                        return Err(format!("unclosed `{{` in progress template `{s}`"));
+                    };
+                    let name = &rest[..end];
+                    let Some((_, part)) = TEMPLATE_PLACEHOLDERS.iter().find(|(n, _)| *n == name)
+                    else {
+                        let expected: Vec<_> = TEMPLATE_PLACEHOLDERS
+                            .iter()
+                            .map(|(n, _)| format!("`{{{n}}}`"))
+                            .collect();
+                        return Err(format!(
+                            "unknown progress template placeholder `{{{name}}}` - expected one of {}",
+                            expected.join(", ")
+                        ));
+                    };
+                    if !text.is_empty() {
+                        parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(part.clone());
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("unmatched `}}` in progress template `{s}`")),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+        Ok(ProgressTemplate { parts })
+    }
+}
+
 struct ProgressBarDisplay<'a> {
     current: usize,
     total: usize,
     message: &'a str,
     eta: Option<Duration>,
+    // How long since the first draw
+    elapsed: Duration,
     width: usize,
+    badge: Option<TallyBadge>,
+    template: &'a ProgressTemplate,
 }
 
-impl fmt::Display for ProgressBarDisplay<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        const PROGRESS_LENGTH: usize = 20;
+const PROGRESS_LENGTH: usize = 20;
 
-        let total_width = num_digits(self.total);
+impl ProgressBarDisplay<'_> {
+    // The number of columns used by a part, ignoring color escape codes. The message is counted
+    // as empty as it fills the rest of the width.
+    fn part_width(&self, part: &TemplatePart) -> usize {
+        match part {
+            TemplatePart::Text(text) => text.chars().count(),
+            TemplatePart::Current | TemplatePart::Total => num_digits(self.total),
+            TemplatePart::Message => 0,
+            TemplatePart::Tally => self.badge.as_ref().map_or(0, |badge| badge.width() + 1),
+            TemplatePart::Bar => PROGRESS_LENGTH + 1,
+            TemplatePart::Eta | TemplatePart::Percent => 4,
+            TemplatePart::Rate => self.rate().chars().count(),
+        }
+    }
 
-        let message_width = self.width - (2 * total_width + 3) - (PROGRESS_LENGTH + 5) - 4;
+    fn rate(&self) -> String {
+        let mins = self.elapsed.as_secs_f64() / 60.0;
+        if mins > 0.0 {
+            #[allow(clippy::cast_precision_loss)]
+            let rate = self.current as f64 / mins;
+            format!("{rate:.1}/m")
+        } else {
+            "-/m".into()
+        }
+    }
 
-        let message = self.message.get(0..message_width).unwrap_or(self.message);
-        let progress_filled = PROGRESS_LENGTH * self.current / self.total;
-        let progress_remaining = PROGRESS_LENGTH - progress_filled;
+    fn write_part(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        part: &TemplatePart,
+        message_width: usize,
+    ) -> fmt::Result {
+        let total_width = num_digits(self.total);
+        match part {
+            TemplatePart::Text(text) => write!(f, "{text}"),
+            TemplatePart::Current => write!(f, "{0:1$}", self.current, total_width),
+            TemplatePart::Total => write!(f, "{0:1$}", self.total, total_width),
+            TemplatePart::Message => {
+                let message = self.message.get(0..message_width).unwrap_or(self.message);
+                write!(f, "{message:message_width$}")
+            }
+            TemplatePart::Tally => match &self.badge {
+                Some(badge) => write!(f, "{badge} "),
+                None => Ok(()),
+            },
+            TemplatePart::Bar => {
+                let progress_filled = match self.total {
+                    // With nothing to do the bar is complete.
+                    0 => PROGRESS_LENGTH,
+                    total => PROGRESS_LENGTH * self.current / total,
+                };
+                let progress_remaining = PROGRESS_LENGTH - progress_filled;
+                write!(
+                    f,
+                    "{0:=<1$}>{2: <3$}",
+                    "", progress_filled, "", progress_remaining
+                )
+            }
+            TemplatePart::Eta => {
+                let secs = self.eta.map(|x| x.as_secs());
+                let mins = secs.map(|x| (x + 30) / 60);
+                match mins {
+                    None => write!(f, "   "),
+                    Some(0) => write!(f, " <1m"),
+                    Some(x) if x > 999 => write!(f, "!!!m"),
+                    Some(x) => write!(f, "{:3}m", x),
+                }
+            }
+            TemplatePart::Rate => write!(f, "{}", self.rate()),
+            TemplatePart::Percent => {
+                let percent = match self.total {
+                    0 => 100,
+                    total => 100 * self.current / total,
+                };
+                write!(f, "{percent:3}%")
+            }
+        }
+    }
+}
 
-        write!(
-            f,
-            "{0:2$}/{1}: {3:4$} [{5:=<6$}>{7: <8$}] ",
-            self.current,
-            self.total,
-            total_width,
-            message,
-            message_width,
-            "",
-            progress_filled,
-            "",
-            progress_remaining
-        )?;
-
-        let secs = self.eta.map(|x| x.as_secs());
-        let mins = secs.map(|x| (x + 30) / 60);
-        match mins {
-            None => write!(f, "   ")?,
-            Some(0) => write!(f, " <1m")?,
-            Some(x) if x > 999 => write!(f, "!!!m")?,
-            Some(x) => write!(f, "{:3}m", x)?,
-        };
+impl fmt::Display for ProgressBarDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let used_width: usize = self
+            .template
+            .parts
+            .iter()
+            .map(|part| self.part_width(part))
+            .sum();
+        let message_width = self.width.saturating_sub(used_width);
 
+        for part in &self.template.parts {
+            self.write_part(f, part, message_width)?;
+        }
         Ok(())
     }
 }
 
+// Whether the progress bar is shown. `Auto` shows it when stderr is a terminal, which is
+// sometimes misdetected (e.g. CI with a pseudo-terminal or output through `tee`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProgressMode {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ProgressMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ProgressMode::Auto),
+            "always" => Ok(ProgressMode::Always),
+            "never" => Ok(ProgressMode::Never),
+            _ => Err(format!(
+                "unknown progress mode `{s}` - expected `auto`, `always` or `never`"
+            )),
+        }
+    }
+}
+
 #[derive(Default)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct ProgressBar {
     stdout_is_tty: bool,
-    stderr_is_tty: bool,
+    show_progress: bool,
+    color: bool,
+    // Send `println` output to stderr instead
+    stdout_to_stderr: bool,
+    refresh_interval: Duration,
+    template: ProgressTemplate,
     progress_bar: Option<ProgressBarState>,
 }
 
 impl ProgressBar {
     #[must_use]
-    pub fn new() -> Self {
+    pub fn new(mode: ProgressMode) -> Self {
         ProgressBar {
             stdout_is_tty: atty::is(atty::Stream::Stdout),
-            stderr_is_tty: atty::is(atty::Stream::Stderr),
+            show_progress: match mode {
+                ProgressMode::Auto => atty::is(atty::Stream::Stderr),
+                ProgressMode::Always => true,
+                ProgressMode::Never => false,
+            },
+            // See https://no-color.org
+            color: std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+            stdout_to_stderr: false,
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            template: ProgressTemplate::default(),
             progress_bar: None,
         }
     }
 
+    // Set the minimum time between redraws. Only takes effect for the next `display_progress`.
+    pub fn set_refresh_interval(&mut self, interval: Duration) {
+        self.refresh_interval = interval;
+    }
+
+    // Set the layout of the bar. Only takes effect for the next `display_progress`.
+    pub fn set_template(&mut self, template: ProgressTemplate) {
+        self.template = template;
+    }
+
+    // Nothing is displayed if there's nothing to do.
     pub fn display_progress(&mut self, total: usize, message: &str) {
-        if !self.stderr_is_tty {
+        if !self.show_progress || total == 0 {
             return;
         }
 
         let mut progress_bar = ProgressBarState::new(total);
-        progress_bar.redraw(message);
+        progress_bar.color = self.color;
+        progress_bar.refresh_interval = self.refresh_interval;
+        progress_bar.template = self.template.clone();
+        progress_bar.draw(message);
         self.progress_bar = Some(progress_bar);
     }
 
@@ -158,13 +524,30 @@ impl ProgressBar {
         }
     }
 
+    // Count the outcome of a task. The counts are shown on the next redraw.
+    pub fn record_outcome(&mut self, outcome: Outcome) {
+        if let Some(progress_bar) = &mut self.progress_bar {
+            progress_bar.record(outcome);
+        }
+    }
+
+    // Write everything to stderr so that stdout can be used for machine readable output.
+    pub fn print_to_stderr(&mut self) {
+        self.stdout_to_stderr = true;
+    }
+
     pub fn println(&mut self, progress_message: &str, message: impl Display) {
+        if self.stdout_to_stderr {
+            self.eprintln(progress_message, message);
+            return;
+        }
         if let Some(progress_bar) = &mut self.progress_bar {
             if self.stdout_is_tty {
                 println!("\r{0:1$}\r{2}", "", WIDTH, message);
-                progress_bar.redraw(progress_message);
+                progress_bar.draw(progress_message);
                 return;
             }
+            progress_bar.flush(progress_message);
         }
 
         println!("{}", message);
@@ -173,7 +556,7 @@ impl ProgressBar {
     pub fn eprintln(&mut self, progress_message: &str, message: impl Display) {
         if let Some(progress_bar) = &mut self.progress_bar {
             eprintln!("\r{0:1$}\r{2}", "", WIDTH, message);
-            progress_bar.redraw(progress_message);
+            progress_bar.draw(progress_message);
             return;
         }
         eprintln!("{}", message);
@@ -182,10 +565,106 @@ impl ProgressBar {
 
 #[cfg(test)]
 mod test {
-    use super::ProgressBarDisplay;
+    use super::{
+        Outcome, ProgressBar, ProgressBarDisplay, ProgressBarState, ProgressMode, ProgressTemplate,
+        Tally, TallyBadge,
+    };
 
     use expect_test::expect;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn redraw_throttling() {
+        let mut state = ProgressBarState::new(3);
+        state.refresh_interval = Duration::from_millis(100);
+        let start = state.last_draw;
+
+        // The first draw always happens.
+        assert!(state.should_redraw(start));
+
+        state.displayed = true;
+        state.current = 1;
+        assert!(!state.should_redraw(start + Duration::from_millis(99)));
+        assert!(state.should_redraw(start + Duration::from_millis(100)));
+
+        // The final state is always drawn.
+        state.current = 3;
+        assert!(state.should_redraw(start));
+
+        state.refresh_interval = Duration::ZERO;
+        state.current = 2;
+        assert!(state.should_redraw(Instant::now()));
+    }
+
+    #[test]
+    fn skipped_redraw_flushed() {
+        let mut state = ProgressBarState::new(3);
+        state.refresh_interval = Duration::from_hours(1);
+        state.draw("a");
+        let first_draw = state.last_draw;
+
+        state.inc("b");
+        assert!(state.pending);
+        assert_eq!(state.last_draw, first_draw);
+
+        // Still too soon
+        state.flush("b");
+        assert!(state.pending);
+
+        state.refresh_interval = Duration::ZERO;
+        state.flush("b");
+        assert!(!state.pending);
+
+        // Nothing to make up for
+        let last_draw = state.last_draw;
+        state.flush("b");
+        assert_eq!(state.last_draw, last_draw);
+    }
+
+    #[test]
+    fn progress_mode() {
+        let mut progress_bar = ProgressBar::new(ProgressMode::Never);
+        progress_bar.display_progress(3, "Starting...");
+        progress_bar.inc_progress("a");
+        progress_bar.record_outcome(Outcome::Succeeded);
+        assert!(progress_bar.progress_bar.is_none());
+
+        let mut progress_bar = ProgressBar::new(ProgressMode::Always);
+        progress_bar.display_progress(3, "Starting...");
+        progress_bar.inc_progress("a");
+        let state = progress_bar.progress_bar.as_ref().expect("always shown");
+        assert!(state.displayed);
+        assert_eq!(state.current, 1);
+
+        assert_eq!("never".parse(), Ok(ProgressMode::Never));
+        assert!("sometimes".parse::<ProgressMode>().is_err());
+    }
+
+    #[test]
+    fn eta_moving_average() {
+        let mut state = ProgressBarState::new(30);
+        let start = state.start;
+        state.last_inc = start;
+
+        // Slow tasks while the dependencies are built use the overall average.
+        let mut now = start;
+        for _ in 0..5 {
+            now += Duration::from_mins(1);
+            state.finish_task(now);
+        }
+        assert_eq!(state.eta(now), Some(Duration::from_mins(25)));
+
+        // Once enough tasks are done the ETA only reflects the last ones.
+        for _ in 0..10 {
+            now += Duration::from_secs(6);
+            state.finish_task(now);
+        }
+        assert_eq!(state.eta(now), Some(Duration::from_secs(15 * 6)));
+
+        now += Duration::from_secs(16);
+        state.finish_task(now);
+        assert_eq!(state.eta(now), Some(Duration::from_secs(14 * 7)));
+    }
 
     #[test]
     fn progress_bar_display() {
@@ -194,11 +673,116 @@ mod test {
             total: 100,
             message: "message",
             eta: Some(Duration::from_secs(123)),
+            elapsed: Duration::ZERO,
             width: 80,
+            badge: None,
+            template: &ProgressTemplate::default(),
         };
         let expected = expect![[
             r#" 30/100: message                                    [======>              ]   2m"#
         ]];
         expected.assert_eq(&format!("{}", bar_display));
     }
+
+    #[test]
+    fn progress_bar_display_nothing_to_do() {
+        let bar_display = ProgressBarDisplay {
+            current: 0,
+            total: 0,
+            message: "message",
+            eta: None,
+            elapsed: Duration::ZERO,
+            width: 80,
+            badge: None,
+            template: &ProgressTemplate::default(),
+        };
+        let expected = expect![[
+            r"0/0: message                                          [====================>]    "
+        ]];
+        expected.assert_eq(&bar_display.to_string());
+
+        ProgressBarState::new(0).redraw("message");
+    }
+
+    #[test]
+    fn tally_badge_display() {
+        let mut tally = Tally::default();
+        for outcome in [
+            Outcome::Succeeded,
+            Outcome::Warned,
+            Outcome::Succeeded,
+            Outcome::Failed,
+        ]
+        .iter()
+        .chain([Outcome::Succeeded; 10].iter())
+        {
+            tally.record(*outcome);
+        }
+
+        let badge = TallyBadge {
+            tally,
+            color: false,
+        };
+        assert_eq!(badge.to_string(), "✓12 !1 ✗1");
+        assert_eq!(badge.width(), badge.to_string().chars().count());
+
+        let badge = TallyBadge { tally, color: true };
+        assert_eq!(
+            badge.to_string(),
+            "\x1b[32m✓12\x1b[0m \x1b[33m!1\x1b[0m \x1b[31m✗1\x1b[0m"
+        );
+
+        let bar_display = ProgressBarDisplay {
+            current: 14,
+            total: 100,
+            message: "message",
+            eta: None,
+            elapsed: Duration::ZERO,
+            width: 80,
+            badge: Some(TallyBadge {
+                tally,
+                color: false,
+            }),
+            template: &ProgressTemplate::default(),
+        };
+        let expected = expect![[
+            r" 14/100: ✓12 !1 ✗1 message                          [==>                  ]    "
+        ]];
+        expected.assert_eq(&format!("{bar_display}"));
+    }
+
+    #[test]
+    fn progress_template() {
+        let template: ProgressTemplate = "{{{percent}}} {n}/{total} {name}| {tally}{rate} {eta}"
+            .parse()
+            .expect("valid template");
+        let mut tally = Tally::default();
+        tally.record(Outcome::Warned);
+        let bar_display = ProgressBarDisplay {
+            current: 5,
+            total: 20,
+            message: "message",
+            eta: Some(Duration::from_mins(4)),
+            elapsed: Duration::from_secs(150),
+            width: 42,
+            badge: Some(TallyBadge {
+                tally,
+                color: false,
+            }),
+            template: &template,
+        };
+        let expected = expect![[r"{ 25%}  5/20 message | ✓0 !1 ✗0 2.0/m   4m"]];
+        expected.assert_eq(&bar_display.to_string());
+
+        assert_eq!(
+            "{n} {nme}".parse::<ProgressTemplate>(),
+            Err(
+                "unknown progress template placeholder `{nme}` - expected one of `{n}`, \
+                 `{total}`, `{name}`, `{tally}`, `{bar}`, `{eta}`, `{rate}`, `{percent}`"
+                    .into()
+            )
+        );
+        assert!("{n".parse::<ProgressTemplate>().is_err());
+        assert!("n}".parse::<ProgressTemplate>().is_err());
+    }
 }
@@ -0,0 +1,67 @@
+// Measuring the resources used by child processes.
+
+use std::io;
+use std::process::{Child, ExitStatus};
+
+// Waits for `child` to exit, returning its exit status together with the peak resident set size
+// in bytes of it and its descendants. The peak is `None` on platforms where it can't be measured.
+pub fn wait_with_peak_rss(child: &mut Child) -> io::Result<(ExitStatus, Option<u64>)> {
+    imp::wait_with_peak_rss(child)
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::io;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{Child, ExitStatus};
+    use std::ptr::addr_of_mut;
+
+    pub fn wait_with_peak_rss(child: &mut Child) -> io::Result<(ExitStatus, Option<u64>)> {
+        let pid = libc::pid_t::try_from(child.id()).expect("pid fits pid_t");
+        let mut status = 0;
+        // SAFETY: `rusage` is plain old data so all zeroes is valid.
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+        loop {
+            // SAFETY: `status` and `usage` are valid for writes.
+            let result = unsafe { libc::wait4(pid, addr_of_mut!(status), 0, addr_of_mut!(usage)) };
+            if result != -1 {
+                break;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+
+        // `ru_maxrss` is in kilobytes on Linux and includes all waited for descendants.
+        let peak_rss = u64::try_from(usage.ru_maxrss).ok().map(|kb| kb * 1024);
+        Ok((ExitStatus::from_raw(status), peak_rss))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::io;
+    use std::process::{Child, ExitStatus};
+
+    pub fn wait_with_peak_rss(child: &mut Child) -> io::Result<(ExitStatus, Option<u64>)> {
+        child.wait().map(|status| (status, None))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use std::process::Command;
+
+    use super::wait_with_peak_rss;
+
+    #[test]
+    fn peak_rss_recorded() {
+        let mut child = Command::new("sh").arg("-c").arg("exit 3").spawn().unwrap();
+        let (status, peak_rss) = wait_with_peak_rss(&mut child).unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert!(peak_rss.unwrap() > 0);
+    }
+}
@@ -0,0 +1,122 @@
+// Word wrapping for rendered diagnostics.
+// Diagnostics have a gutter (e.g. `  |`, `2 |`, `  = `) that is repeated on wrapped lines so
+// that the output keeps its structure.
+
+use crate::markdown_formatting::TableDisplay;
+
+#[must_use]
+pub fn wrap_rendered(text: &str, width: usize) -> String {
+    let mut result = String::new();
+    for (i, line) in text.lines().enumerate() {
+        if i > 0 {
+            result.push('\n');
+        }
+        wrap_line(&mut result, line, width);
+    }
+    if text.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
+
+fn wrap_line(result: &mut String, line: &str, width: usize) {
+    if line.display_width() <= width {
+        result.push_str(line);
+        return;
+    }
+
+    let (gutter, rest) = split_gutter(line);
+    // Line numbers, `=` and `-->` aren't repeated on continuation lines.
+    let continuation: String = gutter
+        .chars()
+        .map(|c| {
+            if c.is_ascii_digit() || "=->".contains(c) {
+                ' '
+            } else {
+                c
+            }
+        })
+        .collect();
+    let gutter_width = gutter.display_width();
+
+    // Not enough room to wrap sensibly.
+    if gutter_width >= width / 2 {
+        result.push_str(line);
+        return;
+    }
+
+    result.push_str(gutter);
+    let mut current_width = gutter_width;
+    let mut line_empty = true;
+    for word in rest.split(' ') {
+        let word_width = word.display_width();
+        if !line_empty && current_width + 1 + word_width > width {
+            result.push('\n');
+            result.push_str(&continuation);
+            current_width = gutter_width;
+            line_empty = true;
+        }
+        if !line_empty {
+            result.push(' ');
+            current_width += 1;
+        }
+        result.push_str(word);
+        current_width += word_width;
+        line_empty = false;
+    }
+}
+
+// Splits a line into the diagnostic gutter and the remaining text.
+fn split_gutter(line: &str) -> (&str, &str) {
+    let prefix_len = line
+        .find(|c: char| !(c.is_whitespace() || c.is_ascii_digit() || "|=->".contains(c)))
+        .unwrap_or(line.len());
+    let prefix = &line[..prefix_len];
+
+    if prefix.contains(['|', '=', '>']) {
+        (prefix, &line[prefix_len..])
+    } else {
+        let indent_len = line.len() - line.trim_start().len();
+        line.split_at(indent_len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use expect_test::expect;
+    use indoc::indoc;
+
+    use super::wrap_rendered;
+
+    #[test]
+    fn wrap_keeps_gutter() {
+        let rendered = indoc! {"
+            warning: this is a long diagnostic message that needs wrapping
+             --> src/main.rs:2:14
+              |
+            2 |     let pi = some_function_with_a_long_name(first_argument, second_argument);
+              |
+              = help: consider using the constant directly instead of the approximate value
+        "};
+
+        let expected = expect![[r"
+            warning: this is a long diagnostic message that
+            needs wrapping
+             --> src/main.rs:2:14
+              |
+            2 |     let pi =
+              |     some_function_with_a_long_name(first_argument,
+              |     second_argument);
+              |
+              = help: consider using the constant directly
+                instead of the approximate value
+        "]];
+        expected.assert_eq(&wrap_rendered(rendered, 48));
+    }
+
+    #[test]
+    fn short_lines_unchanged() {
+        let rendered = "warning: short\n  |\n";
+        assert_eq!(wrap_rendered(rendered, 80), rendered);
+    }
+}
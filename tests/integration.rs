@@ -8,9 +8,10 @@ use tempfile::tempdir;
 
 use std::borrow::Cow;
 use std::ffi::OsStr;
+use std::fmt::Write as _;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::{Command, ExitStatus};
+use std::process::{Command, ExitStatus, Stdio};
 use std::str;
 
 // Random string unlikely to exist as a path or file name
@@ -92,6 +93,57 @@ fn test_dir() -> PathBuf {
 enum TesterOption<'a> {
     CheckAllows,
     Fix(&'a OsStr),
+    BuildRetries(usize),
+    LintsB(&'a str),
+    CompareWorkspaces(&'a str),
+    CountOnly,
+    AllowCode(&'a str),
+    Script(&'a OsStr),
+    Output(&'a OsStr),
+    Mem,
+    Offline,
+    CommonMessages,
+    ListCrates,
+    TargetTriple(&'a str),
+    Include(&'a str),
+    Tags(&'a OsStr),
+    Tag(&'a str),
+    ChangedSince(&'a str),
+    AllTargets,
+    Format(&'a str),
+    KeepFixArtifacts,
+    VerboseFix,
+    Rustflags(&'a str),
+    Cfg(&'a str),
+    CountDeps,
+    CargoArg(&'a str),
+    Env(&'a str),
+    ConfirmLargeGroup,
+    ManifestName(&'a str),
+    Density,
+    PerCrateTargetDir,
+    KeepTarget,
+    Warmup,
+    QuietInvalid,
+    DedupVersions,
+    Sample(usize),
+    Seed(u64),
+    Locked,
+    GenerateLockfile,
+    GitRef(&'a str),
+    KeepClone,
+    SplitOutput(&'a OsStr),
+    ByTarget,
+    CompareFix,
+    Stats,
+    ContextLevel(&'a str),
+    ShowGroups,
+    SpanStats,
+    MaxWarningsPerCrate(usize),
+    ExcludeMacroHits,
+    CheckDeterminism,
+    NoIncremental,
+    JsonDiagnosticsDir(&'a OsStr),
 }
 
 fn run_clippy_lint_tester(
@@ -100,17 +152,25 @@ fn run_clippy_lint_tester(
     lints: &[&str],
     options: &[TesterOption<'_>],
 ) -> CommandOutput {
-    fn clean(stream: Vec<u8>) -> String {
-        String::from_utf8(stream)
-            .expect("utf8 stdout")
-            .replace(test_dir().to_str().unwrap(), "TEST_DIR")
-            .replace(project_root().to_str().unwrap(), "PROJ_ROOT")
-            .replace(NON_EXISTING, "NON_EXISTING")
-    }
+    run_command(make_clippy_lint_tester_command(
+        clippy_workspace,
+        target_dir,
+        lints,
+        options,
+    ))
+}
 
+#[allow(clippy::too_many_lines)]
+fn make_clippy_lint_tester_command(
+    clippy_workspace: &ClippyWorkspace<'_>,
+    target_dir: &TargetDir<'_>,
+    lints: &[&str],
+    options: &[TesterOption<'_>],
+) -> Command {
     let exe = Path::new(env!("CARGO_BIN_EXE_clippy_lint_tester"));
     let mut command = Command::new(exe);
     command
+        .env_remove("CLIPPY_SOURCE")
         .arg(&clippy_workspace.to_arg())
         .arg(&target_dir.to_arg())
         .args(lints);
@@ -123,10 +183,175 @@ fn run_clippy_lint_tester(
             TesterOption::Fix(fix_dir) => {
                 command.arg("--fix").arg(fix_dir);
             }
+            TesterOption::BuildRetries(retries) => {
+                command.arg("--build-retries").arg(retries.to_string());
+            }
+            TesterOption::CompareWorkspaces(path) => {
+                command.arg("--compare-workspaces").arg(path);
+            }
+            TesterOption::LintsB(lint) => {
+                command.arg("--lints-b").arg(lint);
+            }
+            TesterOption::CountOnly => {
+                command.arg("--count-only");
+            }
+            TesterOption::AllowCode(code) => {
+                command.arg("--allow-code").arg(code);
+            }
+            TesterOption::Script(path) => {
+                command.arg("--script").arg(path);
+            }
+            TesterOption::Output(path) => {
+                command.arg("--output").arg(path);
+            }
+            TesterOption::Mem => {
+                command.arg("--mem");
+            }
+            TesterOption::Offline => {
+                command.arg("--offline");
+            }
+            TesterOption::CommonMessages => {
+                command.arg("--common-messages");
+            }
+            TesterOption::ListCrates => {
+                command.arg("--list-crates");
+            }
+            TesterOption::TargetTriple(triple) => {
+                command.arg("--target").arg(triple);
+            }
+            TesterOption::ChangedSince(since) => {
+                command.arg("--changed-since").arg(since);
+            }
+            TesterOption::Include(pattern) => {
+                command.arg("--include").arg(pattern);
+            }
+            TesterOption::Tags(path) => {
+                command.arg("--tags").arg(path);
+            }
+            TesterOption::Tag(tag) => {
+                command.arg("--tag").arg(tag);
+            }
+            TesterOption::AllTargets => {
+                command.arg("--all-targets");
+            }
+            TesterOption::Format(format) => {
+                command.arg("--format").arg(format);
+            }
+            TesterOption::KeepFixArtifacts => {
+                command.arg("--keep-fix-artifacts");
+            }
+            TesterOption::VerboseFix => {
+                command.arg("--verbose-fix");
+            }
+            TesterOption::Rustflags(flags) => {
+                command.arg("--rustflags").arg(flags);
+            }
+            TesterOption::Cfg(cfg) => {
+                command.arg("--cfg").arg(cfg);
+            }
+            TesterOption::CountDeps => {
+                command.arg("--count-deps");
+            }
+            TesterOption::CargoArg(arg) => {
+                command.arg("--cargo-arg").arg(arg);
+            }
+            TesterOption::Env(var) => {
+                command.arg("--env").arg(var);
+            }
+            TesterOption::ConfirmLargeGroup => {
+                command.arg("--confirm-large-group");
+            }
+            TesterOption::ManifestName(name) => {
+                command.arg("--manifest-name").arg(name);
+            }
+            TesterOption::Density => {
+                command.arg("--density");
+            }
+            TesterOption::PerCrateTargetDir => {
+                command.arg("--per-crate-target-dir");
+            }
+            TesterOption::KeepTarget => {
+                command.arg("--keep-target");
+            }
+            TesterOption::Warmup => {
+                command.arg("--warmup");
+            }
+            TesterOption::Locked => {
+                command.arg("--locked");
+            }
+            TesterOption::GenerateLockfile => {
+                command.arg("--generate-lockfile");
+            }
+            TesterOption::Sample(count) => {
+                command.arg("--sample").arg(count.to_string());
+            }
+            TesterOption::Seed(seed) => {
+                command.arg("--seed").arg(seed.to_string());
+            }
+            TesterOption::DedupVersions => {
+                command.arg("--dedup-versions");
+            }
+            TesterOption::QuietInvalid => {
+                command.arg("--quiet-invalid");
+            }
+            TesterOption::GitRef(git_ref) => {
+                command.arg("--git-ref").arg(git_ref);
+            }
+            TesterOption::KeepClone => {
+                command.arg("--keep-clone");
+            }
+            TesterOption::SplitOutput(dir) => {
+                command.arg("--split-output").arg(dir);
+            }
+            TesterOption::ByTarget => {
+                command.arg("--by-target");
+            }
+            TesterOption::CompareFix => {
+                command.arg("--compare-fix");
+            }
+            TesterOption::Stats => {
+                command.arg("--stats");
+            }
+            TesterOption::ContextLevel(level) => {
+                command.arg("--context-level").arg(level);
+            }
+            TesterOption::MaxWarningsPerCrate(max) => {
+                command.arg("--max-warnings-per-crate").arg(max.to_string());
+            }
+            TesterOption::SpanStats => {
+                command.arg("--span-stats");
+            }
+            TesterOption::ExcludeMacroHits => {
+                command.arg("--exclude-macro-hits");
+            }
+            TesterOption::ShowGroups => {
+                command.arg("--show-groups");
+            }
+            TesterOption::CheckDeterminism => {
+                command.arg("--check-determinism");
+            }
+            TesterOption::NoIncremental => {
+                command.arg("--no-incremental");
+            }
+            TesterOption::JsonDiagnosticsDir(dir) => {
+                command.arg("--json-diagnostics-dir").arg(dir);
+            }
         }
         if *option == TesterOption::CheckAllows {}
     }
 
+    command
+}
+
+fn run_command(mut command: Command) -> CommandOutput {
+    fn clean(stream: Vec<u8>) -> String {
+        String::from_utf8(stream)
+            .expect("utf8 stdout")
+            .replace(test_dir().to_str().unwrap(), "TEST_DIR")
+            .replace(project_root().to_str().unwrap(), "PROJ_ROOT")
+            .replace(NON_EXISTING, "NON_EXISTING")
+    }
+
     let output = command.output().expect("Command succeeds");
 
     CommandOutput {
@@ -136,6 +361,134 @@ fn run_clippy_lint_tester(
     }
 }
 
+// A fake Clippy workspace together with a `cargo` shim that is put first on the `PATH`.
+// Building Clippy succeeds unless `$STATE/build.sh` exists, in which case it's run instead, and
+// `clippy-driver -W help` prints `SHIM_LINT_HELP` after running `$STATE/help.sh` if it exists and
+// `clippy-driver --version` prints `clippy 0.1.0 (shim)`.
+// Every other run reports a compiled artifact unless the crate's `src/lib.rs` starts with
+// `#![cfg(`.
+// Running Clippy executes `script` which can use:
+// - `$STATE` a scratch directory that persists between runs
+// - `warned LINT` to check if `--warn LINT` was passed
+// - `emit LINT LINE [MESSAGE] [FILE]` to output a warning for `LINT` at `FILE:LINE:1`. The message
+//   defaults to the lint name and the file to `src/main.rs`. Set `LEVEL` to output another level
+//   (e.g. `LEVEL=error emit E0425 1`) and `KIND` and `NAME` to report it for another target than
+//   `bin a` (e.g. `KIND=lib emit clippy::approx_constant 1`). The span covers one line and byte
+//   unless `SPAN_LINES` and `SPAN_BYTES` are set. Set `MACRO` to report it in an expansion of that
+//   macro.
+#[cfg(unix)]
+struct CargoShim {
+    dir: tempfile::TempDir,
+}
+
+#[cfg(unix)]
+const SHIM_PRELUDE: &str = r#"
+ARGS=" $* "
+case "$ARGS" in *" build "*) [ -e "$STATE/build.sh" ] && . "$STATE/build.sh"; exit 0 ;; esac
+case "$ARGS" in *" metadata "*) printf '{"target_directory":"%s/target"}\n' "$STATE"; exit 0 ;; esac
+case "$ARGS" in *" clippy-driver -- --version "*) echo "clippy 0.1.0 (shim)"; exit 0 ;; esac
+case "$ARGS" in *" clippy-driver "*) [ -e "$STATE/help.sh" ] && . "$STATE/help.sh"; cat "$STATE/lint_help.txt"; exit 0 ;; esac
+
+# Cargo doesn't compile anything for a crate whose lib is disabled by a `cfg`.
+grep -qs '^#!\[cfg(' src/lib.rs || printf '{"reason":"compiler-artifact","package_id":"a","manifest_path":"Cargo.toml","target":{"name":"a","kind":["bin"],"src_path":"src/main.rs"},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":[],"filenames":[],"executable":null,"fresh":false}\n'
+
+warned() {
+    case "$ARGS" in *" --warn $1 "*) return 0 ;; esac
+    return 1
+}
+
+emit() {
+    span=$(printf '"file_name":"%s","byte_start":0,"byte_end":%s,"line_start":%s,"line_end":%s,"column_start":1,"column_end":2,"is_primary":true,"text":[],"label":null,"suggested_replacement":null,"suggestion_applicability":null' "${4:-src/main.rs}" "${SPAN_BYTES:-1}" "$2" "$(($2 + ${SPAN_LINES:-1} - 1))")
+    code="{\"code\":\"$1\",\"explanation\":null}"
+    [ -z "$NO_CODE" ] || code=null
+    expansion=null
+    [ -z "$MACRO" ] || expansion="{\"span\":{$span,\"expansion\":null},\"macro_decl_name\":\"$MACRO!\",\"def_site_span\":null}"
+    printf '{"reason":"compiler-message","package_id":"a","manifest_path":"Cargo.toml","target":{"name":"%s","kind":["%s"],"src_path":"src/main.rs"},"message":{"message":"%s","code":%s,"level":"%s","spans":[{%s,"expansion":%s}],"children":[],"rendered":"%s: %s\\n"}}\n' "${NAME:-a}" "${KIND:-bin}" "${3:-$1}" "$code" "${LEVEL:-warning}" "$span" "$expansion" "${LEVEL:-warning}" "$1"
+}
+"#;
+
+#[cfg(unix)]
+const SHIM_LINT_HELP: &str = "
+Lint checks provided by plugins loaded by this crate:
+
+                                       name  default  meaning
+                                       ----  -------  -------
+    clippy::absurd-extreme-comparisons  deny     checks for comparisons where one side of the relation is...
+               clippy::approx-constant  deny     the approximate of a known float constant
+               clippy::needless-return  warn     using a return statement like `return expr;`
+                     clippy::dbg-macro  allow    `dbg!` macro is intended as a debugging tool
+
+Lint groups provided by plugins loaded by this crate:
+
+                   name  sub-lints
+                   ----  ---------
+            clippy::all  clippy::absurd-extreme-comparisons, clippy::approx-constant, clippy::needless-return
+    clippy::correctness  clippy::absurd-extreme-comparisons, clippy::approx-constant
+          clippy::style  clippy::needless-return
+    clippy::restriction  clippy::dbg-macro
+";
+
+#[cfg(unix)]
+impl CargoShim {
+    fn new(script: &str) -> Self {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("rust-toolchain"),
+            "[toolchain]\nchannel = \"shim\"\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("lint_help.txt"), SHIM_LINT_HELP).unwrap();
+
+        let bin_dir = dir.path().join("bin");
+        fs::create_dir(&bin_dir).unwrap();
+        let cargo = bin_dir.join("cargo");
+        fs::write(
+            &cargo,
+            format!(
+                "#!/bin/sh\nSTATE={}\n{}\n{}",
+                dir.path().display(),
+                SHIM_PRELUDE,
+                script
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+
+        CargoShim { dir }
+    }
+
+    fn workspace(&self) -> &str {
+        self.dir.path().to_str().unwrap()
+    }
+
+    // `PATH` with the shim first.
+    fn path(&self) -> std::ffi::OsString {
+        let mut path = std::ffi::OsString::from(self.dir.path().join("bin"));
+        path.push(":");
+        path.push(std::env::var_os("PATH").unwrap_or_default());
+        path
+    }
+
+    fn run(
+        &self,
+        target_dir: &TargetDir<'_>,
+        lints: &[&str],
+        options: &[TesterOption<'_>],
+    ) -> CommandOutput {
+        let path = self.path();
+        let mut command = make_clippy_lint_tester_command(
+            &ClippyWorkspace::Custom(self.workspace()),
+            target_dir,
+            lints,
+            options,
+        );
+        command.env("PATH", path);
+        run_command(command)
+    }
+}
+
 #[test]
 fn success() {
     let output = run_clippy_lint_tester(
@@ -195,6 +548,8 @@ fn clippy_workspace_build_failure() {
 
         # Summary
 
+        Build check - no lints requested, so only build failures are reported
+
         ## Build failures
 
         Total: 1
@@ -232,27 +587,6 @@ fn lints_invalid() {
     assert_eq!(output.status.code(), Some(1));
 }
 
-#[test]
-fn lint_groups_not_supported() {
-    let output = run_clippy_lint_tester(
-        &ClippyWorkspace::Default,
-        &TargetDir::Default,
-        &["correctness"],
-        &[],
-    );
-
-    let expected_stdout = expect![[r#""#]];
-    let expected_stderr = expect![[r#"
-        Compiling Clippy
-        Checking lint names
-        Error: Lints not found: `correctness`
-    "#]];
-
-    expected_stderr.assert_eq(&output.stderr);
-    expected_stdout.assert_eq(&output.stdout);
-    assert_eq!(output.status.code(), Some(1));
-}
-
 #[test]
 fn target_has_non_crates() {
     let output = run_clippy_lint_tester(
@@ -268,6 +602,8 @@ fn target_has_non_crates() {
 
         # Summary
 
+        Build check - no lints requested, so only build failures are reported
+
         ## Build failures
 
         Total: 0
@@ -334,6 +670,8 @@ fn clippy_workspace_relative_path() {
 
         # Summary
 
+        Build check - no lints requested, so only build failures are reported
+
         ## Build failures
 
         Total: 0
@@ -361,6 +699,8 @@ fn target_relative_path() {
 
         # Summary
 
+        Build check - no lints requested, so only build failures are reported
+
         ## Build failures
 
         Total: 0
@@ -498,3 +838,3794 @@ fn test_fix() {
 
     fix_dir.close().unwrap();
 }
+
+#[cfg(unix)]
+#[test]
+fn build_retried_after_lock_error() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        if [ ! -e "$STATE/attempted" ]; then
+            touch "$STATE/attempted"
+            echo "error: failed to acquire package cache lock" >&2
+            exit 101
+        fi
+    "#});
+
+    let output = shim.run(&TargetDir::Default, &[], &[TesterOption::BuildRetries(1)]);
+
+    let expected_stdout = expect![[r"
+        a - build failed due to lock contention, retrying
+
+        # Summary
+
+        Build check - no lints requested, so only build failures are reported
+
+        ## Build failures
+
+        Total: 0
+    "]];
+    let expected_stderr = expect![[r"
+        Compiling Clippy
+        Linting crates
+    "]];
+
+    expected_stderr.assert_eq(&output.stderr);
+    expected_stdout.assert_eq(&output.stdout);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn build_not_retried_after_compile_error() {
+    // What Cargo writes to stderr with `--message-format=json` after waiting on the target dir.
+    // The compiler's errors are on stdout.
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        echo "    Blocking waiting for file lock on build directory" >&2
+        echo "error: could not compile \`a\` (bin \"a\") due to 1 previous error" >&2
+        exit 101
+    "#});
+
+    let output = shim.run(&TargetDir::Default, &[], &[TesterOption::BuildRetries(1)]);
+
+    assert!(output.stdout.contains("a - build failed\n"));
+    assert!(!output.stdout.contains("retrying"));
+    assert_eq!(output.status.code(), Some(0));
+
+    // Lock contention together with a failed compile
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        echo "error: failed to acquire package cache lock" >&2
+        echo "error: could not compile \`a\` (bin \"a\") due to 1 previous error" >&2
+        exit 101
+    "#});
+
+    let output = shim.run(&TargetDir::Default, &[], &[TesterOption::BuildRetries(1)]);
+
+    assert!(output.stdout.contains("a - build failed\n"));
+    assert!(!output.stdout.contains("retrying"));
+}
+
+#[cfg(unix)]
+#[test]
+fn clippy_build_retried_after_network_error() {
+    let shim = CargoShim::new("");
+    fs::write(
+        shim.dir.path().join("build.sh"),
+        indoc::indoc! {r#"
+            echo >> "$STATE/builds"
+            if [ "$(wc -l < "$STATE/builds")" -eq 1 ]; then
+                echo "error: failed to download from \`https://static.crates.io/crates/a/1.0.0/download\`" >&2
+                exit 101
+            fi
+        "#},
+    )
+    .unwrap();
+
+    let output = shim.run(&TargetDir::Default, &[], &[]);
+
+    let expected_stderr = expect![[r"
+        Compiling Clippy
+        Building Clippy failed due to a network error, retrying
+        Linting crates
+    "]];
+    expected_stderr.assert_eq(&output.stderr);
+    assert_eq!(output.status.code(), Some(0));
+    let builds = fs::read_to_string(shim.dir.path().join("builds")).unwrap();
+    assert_eq!(builds.lines().count(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn lint_names_check_retried() {
+    let shim = CargoShim::new("emit clippy::approx_constant 1");
+    fs::write(
+        shim.dir.path().join("help.sh"),
+        indoc::indoc! {r#"
+            echo >> "$STATE/help_runs"
+            if [ "$(wc -l < "$STATE/help_runs")" -eq 1 ]; then
+                echo "error: failed to open the lock file" >&2
+                exit 101
+            fi
+        "#},
+    )
+    .unwrap();
+
+    let output = shim.run(&TargetDir::Default, &["approx_constant"], &[]);
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(output.stdout.contains("warning: clippy::approx_constant"));
+    let runs = fs::read_to_string(shim.dir.path().join("help_runs")).unwrap();
+    assert_eq!(runs.lines().count(), 2);
+
+    // Only one retry
+    fs::write(
+        shim.dir.path().join("help.sh"),
+        indoc::indoc! {r#"
+            echo "error: failed to open the lock file" >&2
+            exit 101
+        "#},
+    )
+    .unwrap();
+    let output = shim.run(&TargetDir::Default, &["approx_constant"], &[]);
+    let expected_stderr = expect![[r"
+        Compiling Clippy
+        Checking lint names
+        Error: Command to list lints failed after 2 attempts
+        stderr: error: failed to open the lock file
+    "]];
+    expected_stderr.assert_eq(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[cfg(unix)]
+#[test]
+fn lint_names_check_malformed_help() {
+    let shim = CargoShim::new("emit clippy::approx_constant 1");
+    fs::write(
+        shim.dir.path().join("lint_help.txt"),
+        "Lints from plugins:\n\n    clippy::approx-constant  deny  a lint\n",
+    )
+    .unwrap();
+
+    let output = shim.run(&TargetDir::Default, &["approx_constant"], &[]);
+    let expected_stderr = expect![[r"
+        Compiling Clippy
+        Checking lint names
+        Error: Couldn't find Clippy's lints in the output of `clippy-driver -W help` - expected a line starting with `Lint checks provided by plugins` or `Lint checks loaded by this crate`
+        output: Lints from plugins:
+
+            clippy::approx-constant  deny  a lint
+    "]];
+    expected_stderr.assert_eq(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[cfg(unix)]
+#[test]
+fn clippy_build_not_retried_after_compile_error() {
+    let shim = CargoShim::new("");
+    fs::write(
+        shim.dir.path().join("build.sh"),
+        indoc::indoc! {r#"
+            echo >> "$STATE/builds"
+            echo "warning: spurious network error (2 tries remaining)" >&2
+            echo "error[E0425]: cannot find value \`x\` in this scope" >&2
+            echo "error: could not compile \`clippy_lints\`" >&2
+            exit 101
+        "#},
+    )
+    .unwrap();
+
+    let output = shim.run(&TargetDir::Default, &[], &[]);
+
+    let expected_stderr = expect![[r"
+        Compiling Clippy
+        Error: Failed to build Clippy
+        stderr: warning: spurious network error (2 tries remaining)
+        error[E0425]: cannot find value `x` in this scope
+        error: could not compile `clippy_lints`
+
+    "]];
+    expected_stderr.assert_eq(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    let builds = fs::read_to_string(shim.dir.path().join("builds")).unwrap();
+    assert_eq!(builds.lines().count(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn lints_b() {
+    let shim = CargoShim::new(indoc::indoc! {r"
+        if warned clippy::approx_constant; then
+            emit clippy::approx_constant 2
+        fi
+        if warned clippy::absurd_extreme_comparisons; then
+            emit clippy::absurd_extreme_comparisons 2
+            emit clippy::absurd_extreme_comparisons 3
+        fi
+    "});
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::LintsB("absurd_extreme_comparisons")],
+    );
+
+    let expected_stdout = expect![[r"
+
+        ---> a/src/main.rs:2:1
+        warning: clippy::approx_constant
+
+        ---> a/src/main.rs:2:1
+        warning: clippy::absurd_extreme_comparisons
+
+        ---> a/src/main.rs:3:1
+        warning: clippy::absurd_extreme_comparisons
+
+        # Summary
+
+        ## Warnings
+
+        - A: clippy::approx_constant
+        - B: clippy::absurd_extreme_comparisons
+
+        Total: A 1, B 2
+
+         Crate | A | B | Delta 
+        :------|--:|--:|------:
+         a     | 1 | 2 |    +1 
+    "]];
+    let expected_stderr = expect![[r"
+        Compiling Clippy
+        Checking lint names
+        Linting crates
+    "]];
+
+    expected_stderr.assert_eq(&output.stderr);
+    expected_stdout.assert_eq(&output.stdout);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn lints_b_build_failure() {
+    // `b` only fails to build with the B lints.
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        if warned clippy::approx_constant; then
+            emit clippy::approx_constant 2
+        fi
+        if warned clippy::needless_return; then
+            case "$PWD" in */b) exit 101 ;; esac
+            emit clippy::needless_return 3
+        fi
+    "#});
+    let target = make_target(&["a", "b"]);
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant"],
+        &[TesterOption::LintsB("needless_return")],
+    );
+
+    let summary = &output.stdout[output.stdout.find("# Summary").unwrap()..];
+    let expected_summary = expect![[r"
+        # Summary
+
+        ## Warnings
+
+        - A: clippy::approx_constant
+        - B: clippy::needless_return
+
+        Total: A 2, B 1
+
+         Crate | A | B | Delta 
+        :------|--:|--:|------:
+         a     | 1 | 1 |     0 
+         b     | 1 | 0 |    -1 
+
+        ## Build changes
+
+        Newly failing with B: 1
+
+        - b
+    "]];
+    expected_summary.assert_eq(summary);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn compare_workspaces() {
+    // Workspace B has an extra `needless_return` warning, can't build `b` and fixes `c`.
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$ARGS" in *"$STATE/b/Cargo.toml"*) B=1 ;; *) B= ;; esac
+        crate=$(basename "$PWD")
+        if [ "$crate" = a ]; then
+            emit clippy::approx_constant 2
+            if [ -n "$B" ]; then
+                emit clippy::needless_return 3
+            fi
+        fi
+        if [ "$crate/$B" = b/1 ] || [ "$crate/$B" = c/ ]; then
+            echo "error[E0425]: cannot find value \`x\` in this scope" >&2
+            exit 101
+        fi
+    "#});
+    let workspace_b = shim.dir.path().join("b");
+    fs::create_dir(&workspace_b).unwrap();
+    fs::write(
+        workspace_b.join("rust-toolchain"),
+        "[toolchain]\nchannel = \"shim\"\n",
+    )
+    .unwrap();
+    let target = make_target(&["a", "b", "c"]);
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant", "needless_return"],
+        &[
+            TesterOption::CountOnly,
+            TesterOption::CompareWorkspaces(workspace_b.to_str().unwrap()),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+
+    let summary = output.stdout.split("# Summary").nth(1).unwrap();
+    let summary = summary.replace(shim.workspace(), "WORKSPACE");
+    let expected_summary = expect![[r"
+
+
+        ## Build failures
+
+        Total: 1
+
+        - c
+
+        ## Warnings
+
+        - A: WORKSPACE
+        - B: WORKSPACE/b
+
+        Total: A 1, B 2
+
+         Crate | A | B | Delta 
+        :------|--:|--:|------:
+         a     | 1 | 2 |    +1 
+
+        ## Warnings by lint
+
+         Lint                    | A | B | Delta 
+        :------------------------|--:|--:|------:
+         clippy::approx_constant | 1 | 1 |     0 
+         clippy::needless_return | 0 | 1 |    +1 
+
+        ## Build changes
+
+        Newly failing with B: 1
+
+        - b
+
+        Newly passing with B: 1
+
+        - c
+    "]];
+    expected_summary.assert_eq(&summary);
+}
+
+#[cfg(unix)]
+#[test]
+fn compare_workspaces_tags_b_warnings() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$ARGS" in *"$STATE/b/Cargo.toml"*) emit clippy::needless_return 3 ;; esac
+        emit clippy::approx_constant 2
+    "#});
+    let workspace_b = shim.dir.path().join("b");
+    fs::create_dir(&workspace_b).unwrap();
+    fs::write(
+        workspace_b.join("rust-toolchain"),
+        "[toolchain]\nchannel = \"shim\"\n",
+    )
+    .unwrap();
+    let target = make_target(&["a"]);
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant", "needless_return"],
+        &[TesterOption::CompareWorkspaces(
+            workspace_b.to_str().unwrap(),
+        )],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+
+    let warnings = output.stdout.split("# Summary").next().unwrap();
+    let expected_warnings = expect![[r"
+
+        ---> a/src/main.rs:2:1
+        warning: clippy::approx_constant
+
+        ---> a/src/main.rs:3:1 (B)
+        warning: clippy::needless_return
+
+        ---> a/src/main.rs:2:1 (B)
+        warning: clippy::approx_constant
+
+    "]];
+    expected_warnings.assert_eq(warnings);
+}
+
+#[cfg(unix)]
+#[test]
+fn count_only() {
+    let shim = CargoShim::new(indoc::indoc! {r"
+        emit clippy::approx_constant 2
+        emit clippy::needless_return 3
+        emit clippy::approx_constant 4
+    "});
+
+    let full = shim.run(&TargetDir::Default, &["approx_constant"], &[]);
+    let count_only = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::CountOnly],
+    );
+
+    let expected_stdout = expect![[r"
+
+        # Summary
+
+        ## Warnings
+
+        Total: 2
+
+         Crate | Count 
+        :------|------:
+         a     |     2 
+    "]];
+
+    expected_stdout.assert_eq(&count_only.stdout);
+    assert!(full.stdout.ends_with(&count_only.stdout));
+    assert!(full.stdout.contains("---> a/src/main.rs:4:1"));
+    assert_eq!(count_only.status.code(), Some(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn check_determinism() {
+    // `flaky` loses a warning when linted again, as a nondeterministic lint would.
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        echo "$(basename "$PWD") ${ARGS#* --target-dir }" | cut -d ' ' -f 1,2 >> "$STATE/linted"
+        emit clippy::approx_constant 1
+        case "$PWD" in */flaky)
+            if [ ! -e "$STATE/flaky_linted" ]; then
+                touch "$STATE/flaky_linted"
+                emit clippy::approx_constant 2
+            fi
+        esac
+    "#});
+    let target = make_target(&["flaky", "stable"]);
+    let target_path = target.path().to_str().unwrap();
+
+    let output = shim.run(
+        &TargetDir::Custom(target_path),
+        &["approx_constant"],
+        &[TesterOption::CheckDeterminism],
+    );
+    assert!(output.stdout.contains(indoc::indoc! {"
+        ## Nondeterministic warnings
+
+        Total: 1 (warning counts differ when linted twice)
+
+        - flaky - 2 then 1
+    "}));
+    assert!(output
+        .stdout
+        .contains("flaky - warning count differs between runs: 2 then 1\n"));
+    assert!(!output.stdout.contains("- stable -"));
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        fs::read_to_string(shim.dir.path().join("linted")).unwrap(),
+        format!(
+            "flaky {target_path}/_target\nflaky {target_path}/_target/determinism\n\
+            stable {target_path}/_target\nstable {target_path}/_target/determinism\n"
+        )
+    );
+
+    // Nothing is flagged once the results are stable.
+    let output = shim.run(
+        &TargetDir::Custom(target_path),
+        &["approx_constant"],
+        &[TesterOption::CheckDeterminism],
+    );
+    assert!(output.stdout.contains(
+        "## Nondeterministic warnings\n\nTotal: 0 (warning counts differ when linted twice)\n"
+    ));
+    assert!(!output.stdout.contains("differs between runs"));
+}
+
+#[cfg(unix)]
+#[test]
+fn context_level() {
+    // Like Clippy the other lints only warn when `clippy::all` isn't allowed.
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        emit clippy::approx_constant 2
+        case "$ARGS" in *" --allow clippy::all "*) ;; *)
+            emit clippy::needless_return 3
+            emit unused_variables 5
+            printf '{"reason":"compiler-message","package_id":"a","manifest_path":"Cargo.toml","target":{"name":"a","kind":["bin"],"src_path":"src/main.rs"},"message":{"message":"multiple versions","code":{"code":"clippy::multiple_crate_versions","explanation":null},"level":"warning","spans":[],"children":[],"rendered":"warning: clippy::multiple_crate_versions\\n"}}\n'
+        esac
+    "#});
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::ContextLevel("warn")],
+    );
+    assert!(
+        output.stdout.contains(indoc::indoc! {"
+            ---> a/src/main.rs:3:1 (context)
+            warning: clippy::needless_return
+        "}),
+        "{}",
+        output.stdout
+    );
+    // Warnings without a span have no location.
+    assert!(output
+        .stdout
+        .contains("\n(context)\nwarning: clippy::multiple_crate_versions\n"));
+    assert!(!output.stdout.contains("unused_variables"));
+    assert!(output.stdout.contains("Total: 1\n"));
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = shim.run(&TargetDir::Default, &["approx_constant"], &[]);
+    assert!(!output.stdout.contains("(context)"));
+    assert!(output.stdout.contains("Total: 1\n"));
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::ContextLevel("deny")],
+    );
+    assert!(output
+        .stderr
+        .contains("unknown context level `deny` - expected `allow` or `warn`"));
+}
+
+#[cfg(unix)]
+#[test]
+fn show_groups() {
+    let shim = CargoShim::new(indoc::indoc! {r"
+        emit clippy::doc_markdown 2
+        emit clippy::approx_constant 3
+    "});
+    fs::write(
+        shim.dir.path().join("lint_help.txt"),
+        indoc::indoc! {"
+            Lint checks provided by plugins loaded by this crate:
+
+                clippy::approx-constant  deny   the approximate of a known float constant
+                clippy::doc-markdown     allow  checks for the presence of `_`, `::` or camel-case words
+
+            Lint groups provided by plugins loaded by this crate:
+
+                clippy::correctness  clippy::approx-constant
+                clippy::pedantic  clippy::doc-markdown
+        "},
+    )
+    .unwrap();
+    let split_dir = tempdir().unwrap();
+    let split_dir = split_dir.path().join("split");
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["doc_markdown", "approx_constant"],
+        &[
+            TesterOption::ShowGroups,
+            TesterOption::SplitOutput(split_dir.as_os_str()),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(
+        output.stdout.contains(indoc::indoc! {"
+            ---> a/src/main.rs:2:1 [pedantic]
+            warning: clippy::doc_markdown
+
+            ---> a/src/main.rs:3:1 [correctness]
+            warning: clippy::approx_constant
+        "}),
+        "{}",
+        output.stdout
+    );
+    let expected_index = expect![[r"
+        # Warnings by lint
+
+        Total: 2
+
+         Lint                    | Group       | Count | File                
+        :------------------------|:------------|------:|:--------------------
+         clippy::approx_constant | correctness |     1 | approx_constant.txt 
+         clippy::doc_markdown    | pedantic    |     1 | doc_markdown.txt    
+    "]];
+    expected_index.assert_eq(&fs::read_to_string(split_dir.join("index.md")).unwrap());
+
+    let output = shim.run(&TargetDir::Default, &["doc_markdown"], &[]);
+    assert!(output.stdout.contains("---> a/src/main.rs:2:1\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn span_stats() {
+    let shim = CargoShim::new(indoc::indoc! {r"
+        emit clippy::approx_constant 2
+        SPAN_LINES=3 SPAN_BYTES=40 emit clippy::approx_constant 4
+        SPAN_BYTES=5 emit clippy::approx_constant 9
+        SPAN_LINES=7 SPAN_BYTES=2 emit clippy::needless_return 12
+    "});
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::CountOnly, TesterOption::SpanStats],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(
+        output.stdout.contains(indoc::indoc! {"
+            ## Span sizes
+
+            Total: 3 (the primary span of each warning)
+
+             Size  | Min | Median | Max 
+            :------|----:|-------:|----:
+             Lines |   1 |      1 |   3 
+             Bytes |   1 |      5 |  40 
+        "}),
+        "{}",
+        output.stdout
+    );
+
+    // The median of an even number of spans is the mean of the middle two.
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant", "needless_return"],
+        &[TesterOption::CountOnly, TesterOption::SpanStats],
+    );
+    assert!(
+        output.stdout.contains(indoc::indoc! {"
+             Size  | Min | Median | Max 
+            :------|----:|-------:|----:
+             Lines |   1 |      2 |   7 
+             Bytes |   1 |    3.5 |  40 
+        "}),
+        "{}",
+        output.stdout
+    );
+
+    let output = shim.run(&TargetDir::Default, &["approx_constant"], &[]);
+    assert!(!output.stdout.contains("## Span sizes"));
+}
+
+#[cfg(unix)]
+#[test]
+fn exclude_macro_hits() {
+    let shim = CargoShim::new(indoc::indoc! {r"
+        emit clippy::approx_constant 2
+        MACRO=vec emit clippy::approx_constant 4
+        MACRO=format emit clippy::approx_constant 6
+    "});
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::CountOnly, TesterOption::ExcludeMacroHits],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(
+        output.stdout.contains("## Warnings\n\nTotal: 1\n"),
+        "{}",
+        output.stdout
+    );
+    assert!(
+        output.stdout.contains(indoc::indoc! {"
+            ## Macro-only warnings
+
+            Total: 2 (not counted as their spans are all in macro expansions)
+
+             Crate | Count 
+            :------|------:
+             a     |     2 
+        "}),
+        "{}",
+        output.stdout
+    );
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::CountOnly],
+    );
+    assert!(
+        output.stdout.contains("## Warnings\n\nTotal: 3\n"),
+        "{}",
+        output.stdout
+    );
+    assert!(!output.stdout.contains("## Macro-only warnings"));
+}
+
+#[cfg(unix)]
+#[test]
+fn max_warnings_per_crate() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        for line in 1 2 3 4 5; do
+            emit clippy::approx_constant $line
+        done
+        if [ "$(basename "$PWD")" = b ]; then
+            emit clippy::approx_constant 6
+        fi
+    "#});
+    let target = make_target(&["a", "b"]);
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant"],
+        &[TesterOption::MaxWarningsPerCrate(2)],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(
+        output.stdout.contains(indoc::indoc! {"
+            ---> a/src/main.rs:2:1
+            warning: clippy::approx_constant
+
+            ... and 3 more warnings
+        "}),
+        "{}",
+        output.stdout
+    );
+    assert!(output.stdout.contains("... and 4 more warnings\n"));
+    assert!(!output.stdout.contains("---> a/src/main.rs:3:1"));
+    let expected_summary = indoc::indoc! {"
+        Total: 11
+
+         Crate | Count 
+        :------|------:
+         a     |     5 
+         b     |     6 
+    "};
+    assert!(
+        output.stdout.contains(expected_summary),
+        "{}",
+        output.stdout
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn check_allows_configured_codes() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$ARGS" in *" --forbid "*)
+            emit E0453 1
+            emit E0999 3
+            emit E0999 4
+            printf '{"reason":"compiler-message","package_id":"a","manifest_path":"Cargo.toml","target":{"name":"a","kind":["bin"],"src_path":"src/main.rs"},"message":{"message":"unknown lint","code":{"code":"unknown_lints","explanation":null},"level":"warning","spans":[],"children":[],"rendered":"warning: unknown lint: `clippy::approx_constant`\\n"}}\n'
+        esac
+    "#});
+
+    let default_codes = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::CheckAllows, TesterOption::CountOnly],
+    );
+    let configured_codes = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[
+            TesterOption::CheckAllows,
+            TesterOption::CountOnly,
+            TesterOption::AllowCode("E0999"),
+        ],
+    );
+
+    assert!(default_codes.stdout.contains("## Allows\n\nTotal: 1\n"));
+    assert!(configured_codes.stdout.contains("## Allows\n\nTotal: 2\n"));
+    assert!(configured_codes.stdout.contains("---> a/src/main.rs:4:1"));
+    assert!(!configured_codes.stdout.contains("---> a/src/main.rs:1:1"));
+
+    // A diagnostic without a span is shown without a location.
+    let spanless_code = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[
+            TesterOption::CheckAllows,
+            TesterOption::CountOnly,
+            TesterOption::AllowCode("unknown_lints"),
+        ],
+    );
+    assert_eq!(
+        spanless_code.status.code(),
+        Some(0),
+        "{}",
+        spanless_code.stderr
+    );
+    assert!(spanless_code.stdout.contains("## Allows\n\nTotal: 1\n"));
+    assert!(spanless_code
+        .stdout
+        .contains("Allow found\nwarning: unknown lint: `clippy::approx_constant`"));
+}
+
+#[cfg(unix)]
+#[test]
+fn no_incremental() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        echo "${CARGO_INCREMENTAL-unset}" >> "$STATE/incremental"
+        emit clippy::approx_constant 2
+    "#});
+    let script_dir = tempdir().unwrap();
+    let script_path = script_dir.path().join("reproduce.sh");
+
+    let output = shim.run(&TargetDir::Default, &["approx_constant"], &[]);
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    let incremental = fs::read_to_string(shim.dir.path().join("incremental")).unwrap();
+    assert_eq!(incremental, "unset\n");
+
+    fs::remove_file(shim.dir.path().join("incremental")).unwrap();
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[
+            TesterOption::NoIncremental,
+            TesterOption::Script(script_path.as_os_str()),
+            TesterOption::Format("jsonl"),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    let incremental = fs::read_to_string(shim.dir.path().join("incremental")).unwrap();
+    assert_eq!(incremental, "0\n");
+    // Recorded in the summary so that runs can be compared like for like.
+    let summary: serde_json::Value =
+        serde_json::from_str(output.stdout.lines().last().unwrap()).unwrap();
+    assert_eq!(summary["summary"]["no_incremental"], true);
+    let script = fs::read_to_string(&script_path).unwrap();
+    assert!(
+        script.contains(" && CARGO_INCREMENTAL=0 cargo +shim "),
+        "{script}"
+    );
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[
+            TesterOption::NoIncremental,
+            TesterOption::Env("CARGO_INCREMENTAL=1"),
+        ],
+    );
+    assert!(output
+        .stderr
+        .contains("Error: `--env CARGO_INCREMENTAL=...` conflicts with `--no-incremental`"));
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[cfg(unix)]
+#[test]
+fn reproduction_script() {
+    let shim = CargoShim::new(indoc::indoc! {r"
+        emit clippy::approx_constant 2
+    "});
+    let script_dir = tempdir().unwrap();
+    let script_path = script_dir.path().join("reproduce.sh");
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::Script(script_path.as_os_str())],
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    let script = fs::read_to_string(&script_path)
+        .unwrap()
+        .replace(shim.workspace(), "WORKSPACE")
+        .replace(test_dir().to_str().unwrap(), "TEST_DIR");
+
+    let expected_script = expect![[r"
+        #!/bin/sh
+        # Generated by clippy_lint_tester
+        # `set -e` isn't used so that every crate is run even if some fail to build.
+
+        # Build Clippy
+        cargo +shim build --manifest-path=WORKSPACE/Cargo.toml --release || exit 1
+
+        (cd TEST_DIR/targets/default/a && cargo +shim --quiet run --manifest-path=WORKSPACE/Cargo.toml --release --bin cargo-clippy -- -- --quiet --message-format=json --target-dir TEST_DIR/targets/default/_target -- --cap-lints warn --allow 'clippy::all' --warn 'clippy::approx_constant')
+    "]];
+    expected_script.assert_eq(&script);
+
+    // The script reproduces the warning.
+    let mut path = std::ffi::OsString::from(shim.dir.path().join("bin"));
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    let script_output = Command::new(&script_path)
+        .env("PATH", path)
+        .output()
+        .unwrap();
+    assert!(script_output.status.success());
+    assert!(str::from_utf8(&script_output.stdout)
+        .unwrap()
+        .contains(r#""code":"clippy::approx_constant""#));
+}
+
+#[cfg(unix)]
+#[test]
+fn compressed_output() {
+    use std::io::Read;
+
+    let shim = CargoShim::new(indoc::indoc! {r"
+        emit clippy::approx_constant 2
+    "});
+    let output_dir = tempdir().unwrap();
+    let output_path = output_dir.path().join("summary.md.gz");
+
+    let stdout_summary = shim.run(&TargetDir::Default, &["approx_constant"], &[]);
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::Output(output_path.as_os_str())],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!output.stdout.contains("# Summary"));
+
+    let mut summary = String::new();
+    flate2::read::GzDecoder::new(fs::File::open(&output_path).unwrap())
+        .read_to_string(&mut summary)
+        .unwrap();
+
+    assert!(summary.starts_with("\n# Summary\n"));
+    assert!(stdout_summary.stdout.ends_with(&summary));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn peak_memory() {
+    // Use enough memory to be visible in MiB.
+    let shim = CargoShim::new(indoc::indoc! {r"
+        head -c 8388608 /dev/zero | tr '\0' 'x' > /dev/null
+        x=$(head -c 4194304 /dev/zero | tr '\0' 'x')
+    "});
+
+    let output = shim.run(&TargetDir::Default, &[], &[TesterOption::Mem]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let memory_section = output.stdout.split("## Peak memory\n").nth(1).unwrap();
+    let row = Regex::new(r"(?m)^ a +\| +(\d+) $").unwrap();
+    let mib: u64 = row.captures(memory_section).unwrap()[1].parse().unwrap();
+    assert!(mib > 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn offline() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$ARGS" in *" --offline "*) ;; *) exit 2 ;; esac
+        echo "error: could not compile \`a\`" >&2
+        exit 101
+    "#});
+
+    let output = shim.run(&TargetDir::Default, &[], &[TesterOption::Offline]);
+
+    let expected_stdout = expect![[r"
+
+        a - build failed
+        Command used: `cd TEST_DIR/targets/default/a && cargo +shim --quiet run --manifest-path=WORKSPACE/Cargo.toml --release --offline --bin cargo-clippy -- -- --offline --quiet --message-format=json --target-dir TEST_DIR/targets/default/_target -- --cap-lints warn --allow 'clippy::all'`
+
+        # Summary
+
+        Build check - no lints requested, so only build failures are reported
+
+        ## Build failures
+
+        Total: 1
+
+        - a
+    "]];
+    expected_stdout.assert_eq(&output.stdout.replace(shim.workspace(), "WORKSPACE"));
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn common_messages() {
+    let shim = CargoShim::new(indoc::indoc! {r"
+        emit clippy::approx_constant 2 'approximate value of `f32::consts::PI` found'
+        emit clippy::approx_constant 3 'approximate value of `f64::consts::PI` found'
+        emit clippy::approx_constant 4 'approximate value of `f64::consts::E` found'
+        emit clippy::approx_constant 5 'constant in `if` condition'
+        emit clippy::needless_return 6 'unneeded `return` statement'
+    "});
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::CountOnly, TesterOption::CommonMessages],
+    );
+
+    let expected_stdout = expect![[r"
+
+        # Summary
+
+        ## Warnings
+
+        Total: 4
+
+         Crate | Count 
+        :------|------:
+         a     |     4 
+
+        ## Common messages
+
+        Distinct: 2
+
+         Message                        | Count | Share 
+        :-------------------------------|------:|------:
+         approximate value of `_` found |     3 | 75.0% 
+         constant in `if` condition     |     1 | 25.0% 
+    "]];
+    expected_stdout.assert_eq(&output.stdout);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn list_crates() {
+    let shim = CargoShim::new(r#"echo "$PWD" >> "$STATE/linted""#);
+
+    let output = shim.run(
+        &TargetDir::Named("non_crates"),
+        &[],
+        &[TesterOption::ListCrates],
+    );
+
+    let expected_stdout = expect![[r"
+        - a.txt
+        - b
+
+        Total: 2
+    "]];
+    let expected_stderr = expect![""];
+
+    expected_stderr.assert_eq(&output.stderr);
+    expected_stdout.assert_eq(&output.stdout);
+    assert_eq!(output.status.code(), Some(0));
+
+    // The listed paths are the ones processed by a real run.
+    let output = shim.run(&TargetDir::Named("non_crates"), &[], &[]);
+    assert!(output.stdout.contains("non_crates/a.txt - not a crate"));
+    assert!(output.stdout.contains("non_crates/b - not a crate"));
+
+    let output = shim.run(&TargetDir::Default, &[], &[TesterOption::ListCrates]);
+    assert_eq!(output.stdout, "- a\n\nTotal: 1\n");
+    assert!(!shim.dir.path().join("linted").exists());
+    shim.run(&TargetDir::Default, &[], &[]);
+    let linted = fs::read_to_string(shim.dir.path().join("linted")).unwrap();
+    assert!(linted.ends_with("targets/default/a\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn target_triple_forwarded() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$ARGS" in
+            *" --target i686-unknown-linux-gnu "*) emit clippy::approx_constant 2 ;;
+            *" --target "*)
+                echo "error[E0463]: can't find crate for \`std\`" >&2
+                echo "  = note: the \`$4\` target may not be installed" >&2
+                exit 101
+                ;;
+        esac
+    "#});
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[
+            TesterOption::CountOnly,
+            TesterOption::TargetTriple("i686-unknown-linux-gnu"),
+        ],
+    );
+    assert!(output.stdout.contains("## Warnings\n\nTotal: 1\n"));
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[
+            TesterOption::CountOnly,
+            TesterOption::TargetTriple("thumbv7em-none-eabihf"),
+        ],
+    );
+
+    let expected_stdout = expect![[r"
+
+        a - build failed (target not installed)
+
+        # Summary
+
+        ## Target not installed
+
+        Total: 1
+
+        - a
+
+        ## Warnings
+
+        Total: 0
+    "]];
+    expected_stdout.assert_eq(&output.stdout);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn clippy_source_from_env() {
+    let shim = CargoShim::new("emit clippy::approx_constant 2");
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_clippy_lint_tester"));
+    command
+        .env("CLIPPY_SOURCE", shim.workspace())
+        .env("PATH", shim.path())
+        .arg(TargetDir::Default.to_arg());
+    let output = run_command(command);
+
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(output.stdout.contains("Total: 0\n"), "{}", output.stdout);
+
+    // The positional takes precedence.
+    let mut command = make_clippy_lint_tester_command(
+        &ClippyWorkspace::Custom(shim.workspace()),
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[],
+    );
+    command
+        .env("CLIPPY_SOURCE", NON_EXISTING)
+        .env("PATH", shim.path());
+    let output = run_command(command);
+
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(output.stdout.contains("---> a/src/main.rs:2:1\n"));
+}
+
+// Lints with the Clippy installed with the stable toolchain. Skipped if rustup or the stable Clippy
+// isn't installed.
+#[test]
+fn use_installed() {
+    let installed = Command::new("cargo")
+        .args(["+stable", "clippy", "--version"])
+        .output()
+        .is_ok_and(|output| output.status.success());
+    if !installed {
+        eprintln!("Skipping: `cargo +stable clippy` is unavailable");
+        return;
+    }
+
+    let target = make_target(&["a"]);
+    fs::write(
+        target.path().join("a/Cargo.toml"),
+        "[package]\nname = \"a\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+    )
+    .unwrap();
+    fs::write(
+        target.path().join("a/src/main.rs"),
+        "fn main() {\n    let x = 3.14;\n    println!(\"{x}\");\n}\n",
+    )
+    .unwrap();
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_clippy_lint_tester"));
+    command
+        .env_remove("CLIPPY_SOURCE")
+        .args(["--use-installed", "stable", "--count-only"])
+        .arg(target.path())
+        .arg("approx_constant");
+    let output = run_command(command);
+
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(!output.stderr.contains("Compiling Clippy"));
+    assert!(
+        output.stdout.contains(" a     |     1 \n"),
+        "{}",
+        output.stdout
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn duplicate_lints_tested_once() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$ARGS" in
+            *" --warn clippy::approx_constant "*" --warn clippy::approx_constant "*) exit 1 ;;
+        esac
+        if warned clippy::approx_constant; then
+            emit clippy::approx_constant 2
+        fi
+    "#});
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant", "clippy::approx-constant"],
+        &[TesterOption::CountOnly],
+    );
+
+    let expected_stdout = expect![[r"
+
+        # Summary
+
+        ## Warnings
+
+        Total: 1
+
+         Crate | Count 
+        :------|------:
+         a     |     1 
+    "]];
+    let expected_stderr = expect![[r"
+        Compiling Clippy
+        Checking lint names
+        warning: `clippy::approx-constant` is the same lint as `approx_constant` - it will only be tested once
+        Linting crates
+    "]];
+    expected_stdout.assert_eq(&output.stdout);
+    expected_stderr.assert_eq(&output.stderr);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+// A target dir with an empty crate for each name.
+fn make_target(names: &[&str]) -> tempfile::TempDir {
+    let target = tempdir().unwrap();
+    for name in names {
+        let crate_dir = target.path().join(name);
+        fs::create_dir_all(crate_dir.join("src")).unwrap();
+        fs::write(crate_dir.join("Cargo.toml"), "").unwrap();
+        fs::write(crate_dir.join("src/main.rs"), "").unwrap();
+    }
+    target
+}
+
+#[cfg(unix)]
+#[test]
+fn include_filters_crates() {
+    let shim = CargoShim::new(r#"basename "$PWD" >> "$STATE/linted""#);
+    let target = make_target(&["rand-0.8.4", "serde-1.0.130", "serde_json-1.0.68"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+
+    let output = shim.run(&target_dir, &[], &[TesterOption::Include("ser*")]);
+
+    assert!(output.stderr.contains("2 of 3 crates match `--include`\n"));
+    assert_eq!(output.status.code(), Some(0));
+    let linted = fs::read_to_string(shim.dir.path().join("linted")).unwrap();
+    assert_eq!(linted, "serde-1.0.130\nserde_json-1.0.68\n");
+
+    let output = shim.run(
+        &target_dir,
+        &[],
+        &[
+            TesterOption::Include("*-0.8.*"),
+            TesterOption::Include("serde_*"),
+            TesterOption::ListCrates,
+        ],
+    );
+    assert_eq!(
+        output.stdout,
+        "- rand-0.8.4\n- serde_json-1.0.68\n\nTotal: 2\n"
+    );
+    assert_eq!(output.stderr, "2 of 3 crates match `--include`\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn tags() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        basename "$PWD" >> "$STATE/linted"
+        case "$PWD" in
+            */tokio) emit clippy::approx_constant 1; emit clippy::approx_constant 2 ;;
+            */async-std) emit clippy::needless_return 1 ;;
+            */serde) emit clippy::approx_constant 1 ;;
+        esac
+    "#});
+    let target = make_target(&["async-std", "futures", "libm", "serde", "tokio"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+    let tags_dir = tempdir().unwrap();
+    let tags_path = tags_dir.path().join("tags.toml");
+    fs::write(
+        &tags_path,
+        indoc::indoc! {r#"
+            async-std = ["async"]
+            futures = ["async", "no_std"]
+            serde = ["no_std"]
+            tokio = ["async"]
+        "#},
+    )
+    .unwrap();
+
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant", "needless_return"],
+        &[
+            TesterOption::Tags(tags_path.as_os_str()),
+            TesterOption::Tag("async"),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(output
+        .stderr
+        .contains("warning: 1 crates have no tags: libm\n3 of 5 crates match `--tag`\n"));
+    let linted = fs::read_to_string(shim.dir.path().join("linted")).unwrap();
+    assert_eq!(linted, "async-std\nfutures\ntokio\n");
+    let summary = &output.stdout[output.stdout.find("## Warnings by tag").unwrap()..];
+    let expected = expect![[r"
+        ## Warnings by tag
+
+         Tag    | Linted | Warned | Warnings 
+        :-------|-------:|-------:|---------:
+         async  |      3 |      2 |        3 
+         no_std |      1 |      0 |        0 
+
+         Tag   | Lint                    | Count 
+        :------|:------------------------|------:
+         async | clippy::approx_constant |     2 
+         async | clippy::needless_return |     1 
+    "]];
+    expected.assert_eq(summary);
+
+    // JSON tags without a filter
+    let tags_path = tags_dir.path().join("tags.json");
+    fs::write(
+        &tags_path,
+        r#"{"serde": ["no_std"], "futures": ["no_std"]}"#,
+    )
+    .unwrap();
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant"],
+        &[TesterOption::Tags(tags_path.as_os_str())],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(output
+        .stderr
+        .contains("warning: 3 crates have no tags: async-std, libm, tokio\n"));
+    let summary = &output.stdout[output.stdout.find("## Warnings by tag").unwrap()..];
+    let expected = expect![[r"
+        ## Warnings by tag
+
+         Tag    | Linted | Warned | Warnings 
+        :-------|-------:|-------:|---------:
+         no_std |      2 |      1 |        1 
+    "]];
+    expected.assert_eq(summary);
+
+    let output = shim.run(&target_dir, &[], &[TesterOption::Tag("async")]);
+    assert!(output.stderr.contains("Error: `--tag` requires `--tags`"));
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[cfg(unix)]
+#[test]
+fn target_path_normalized() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        echo "$(basename "$PWD") ${ARGS#* --target-dir }" | cut -d ' ' -f 1,2 >> "$STATE/linted"
+    "#});
+    let target = make_target(&["a", "b"]);
+    fs::create_dir(target.path().join("_target")).unwrap();
+    let target_path = target.path().to_str().unwrap();
+    let linted_path = shim.dir.path().join("linted");
+    let expected = format!("a {target_path}/_target\nb {target_path}/_target\n");
+
+    let output = shim.run(&TargetDir::Custom(target_path), &[], &[]);
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert_eq!(fs::read_to_string(&linted_path).unwrap(), expected);
+    fs::remove_file(&linted_path).unwrap();
+
+    let dotted = format!("{target_path}/a/../.");
+    let output = shim.run(&TargetDir::Custom(&dotted), &[], &[]);
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert_eq!(fs::read_to_string(&linted_path).unwrap(), expected);
+    fs::remove_file(&linted_path).unwrap();
+
+    // Relative to the working dir
+    let mut command = make_clippy_lint_tester_command(
+        &ClippyWorkspace::Custom(shim.workspace()),
+        &TargetDir::Custom("../"),
+        &[],
+        &[],
+    );
+    command
+        .env("PATH", shim.path())
+        .current_dir(target.path().join("b"));
+    let output = run_command(command);
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert_eq!(fs::read_to_string(&linted_path).unwrap(), expected);
+}
+
+#[cfg(unix)]
+#[test]
+fn changed_since() {
+    let shim = CargoShim::new(r#"basename "$PWD" >> "$STATE/linted""#);
+    let target = make_target(&["new", "old"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+    let linted_path = shim.dir.path().join("linted");
+    let set_mtime = |path: &Path, secs| {
+        filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(secs, 0)).unwrap();
+    };
+    for name in ["Cargo.toml", "src/main.rs"] {
+        set_mtime(&target.path().join("old").join(name), 1_000_000_000);
+        set_mtime(&target.path().join("new").join(name), 1_000_000_000);
+    }
+    set_mtime(&target.path().join("new/src/main.rs"), 1_500_000_000);
+
+    let output = shim.run(
+        &target_dir,
+        &[],
+        &[TesterOption::ChangedSince("1200000000")],
+    );
+    assert!(
+        output
+            .stderr
+            .contains("1 of 2 crates changed since `--changed-since`\n"),
+        "{}",
+        output.stderr
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read_to_string(&linted_path).unwrap(), "new\n");
+    fs::remove_file(&linted_path).unwrap();
+
+    // A marker file's modification time is used. Linting touched `new` so both are older.
+    let marker = shim.dir.path().join("marker");
+    fs::write(&marker, "").unwrap();
+    set_mtime(&target.path().join("old/src/main.rs"), 2_000_000_000);
+    let output = shim.run(
+        &target_dir,
+        &[],
+        &[TesterOption::ChangedSince(marker.to_str().unwrap())],
+    );
+    assert!(output
+        .stderr
+        .contains("1 of 2 crates changed since `--changed-since`\n"));
+    assert_eq!(fs::read_to_string(&linted_path).unwrap(), "old\n");
+
+    let output = shim.run(
+        &target_dir,
+        &[],
+        &[TesterOption::ChangedSince(NON_EXISTING)],
+    );
+    assert!(output.stderr.contains(
+        "Error: `--changed-since NON_EXISTING` is neither a timestamp nor an existing file"
+    ));
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[cfg(unix)]
+#[test]
+fn all_targets() {
+    // The warning on line 5 is in a test module.
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        emit clippy::approx_constant 2
+        case "$ARGS" in *" --all-targets "*)
+            emit clippy::approx_constant 5
+            if [ -e "$STATE/fail_all_targets" ]; then exit 101; fi
+        ;; esac
+    "#});
+
+    let output = shim.run(&TargetDir::Default, &["approx_constant"], &[]);
+    assert!(output.stdout.contains("Total: 1\n"));
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::AllTargets],
+    );
+    assert!(output.stdout.contains("---> a/src/main.rs:5:1\n"));
+    assert!(output.stdout.contains("Total: 2\n"));
+
+    fs::write(shim.dir.path().join("fail_all_targets"), "").unwrap();
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::AllTargets, TesterOption::CountOnly],
+    );
+    assert!(output.stdout.contains("a - build failed\n"));
+    assert!(output
+        .stdout
+        .contains("a - retrying without `--all-targets`\n"));
+    let summary = &output.stdout[output.stdout.find("# Summary").unwrap()..];
+    let expected_summary = expect![[r"
+        # Summary
+
+        ## Default targets only
+
+        Total: 1 (failed to build with `--all-targets`)
+
+        - a
+
+        ## Warnings
+
+        Total: 1
+
+         Crate | Count 
+        :------|------:
+         a     |     1 
+    "]];
+    expected_summary.assert_eq(summary);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn jsonl_format() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$PWD" in
+            */broken) exit 101 ;;
+            */warns)
+                emit clippy::approx_constant 2
+                emit clippy::approx_constant 3
+                emit clippy::absurd_extreme_comparisons 4
+            ;;
+        esac
+    "#});
+    let target = make_target(&["broken", "clean", "warns"]);
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant", "absurd_extreme_comparisons"],
+        &[TesterOption::Format("jsonl")],
+    );
+
+    for line in output.stdout.lines() {
+        serde_json::from_str::<serde_json::Value>(line).unwrap();
+    }
+    let expected_stdout = expect![[r#"
+        {"crate":"broken","lints":null,"status":"build_failed","warning_count":0}
+        {"crate":"clean","lints":{},"status":"success","warning_count":0}
+        {"crate":"warns","lints":{"clippy::absurd_extreme_comparisons":1,"clippy::approx_constant":2},"status":"success","warning_count":3}
+        {"summary":{"build_failures":["broken"],"crates":3,"lints":["clippy::absurd_extreme_comparisons","clippy::approx_constant"],"missing_target":[],"no_incremental":false,"warning_count":3,"warning_counts":{"warns":3}}}
+    "#]];
+    expected_stdout.assert_eq(&output.stdout);
+    assert!(output.stderr.contains("---> warns/src/main.rs:2:1\n"));
+    assert_eq!(output.status.code(), Some(0));
+}
+
+// The `--format dashboard` schema. Unknown fields are rejected so that this must be updated with
+// the schema.
+#[derive(serde::Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct DashboardRecord {
+    clippy_version: Option<String>,
+    crates: usize,
+    lints: Vec<DashboardLint>,
+    schema_version: u32,
+}
+
+#[derive(serde::Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct DashboardLint {
+    crates: usize,
+    examples: Vec<String>,
+    name: String,
+    warnings: usize,
+}
+
+#[cfg(unix)]
+#[test]
+fn format_dashboard() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$PWD" in
+            */broken) exit 101 ;;
+            */one) emit clippy::approx_constant 1 ;;
+            */many)
+                for line in 1 2 3 4; do
+                    emit clippy::approx_constant $line
+                done
+                emit clippy::needless_return 5
+                ;;
+        esac
+    "#});
+    let target = make_target(&["broken", "clean", "many", "one"]);
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant", "needless_return", "dbg_macro"],
+        &[TesterOption::Format("dashboard")],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(output.stderr.contains("---> many/src/main.rs:5:1\n"));
+
+    let record: DashboardRecord = serde_json::from_str(&output.stdout).unwrap();
+    let example = |crate_name: &str, line: usize, lint: &str| {
+        format!("---> {crate_name}/src/main.rs:{line}:1\nwarning: {lint}")
+    };
+    assert_eq!(
+        record,
+        DashboardRecord {
+            clippy_version: Some("clippy 0.1.0 (shim)".into()),
+            crates: 3,
+            lints: vec![
+                DashboardLint {
+                    crates: 2,
+                    examples: vec![
+                        example("many", 1, "clippy::approx_constant"),
+                        example("many", 2, "clippy::approx_constant"),
+                        example("many", 3, "clippy::approx_constant"),
+                    ],
+                    name: "clippy::approx_constant".into(),
+                    warnings: 5,
+                },
+                DashboardLint {
+                    crates: 0,
+                    examples: vec![],
+                    name: "clippy::dbg_macro".into(),
+                    warnings: 0,
+                },
+                DashboardLint {
+                    crates: 1,
+                    examples: vec![example("many", 5, "clippy::needless_return")],
+                    name: "clippy::needless_return".into(),
+                    warnings: 1,
+                },
+            ],
+            schema_version: 1,
+        }
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn stats() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$PWD" in
+            */ice)
+                printf 'error: internal compiler error: unexpected panic\n\nnote: the compiler unexpectedly panicked. this is a bug.\n' >&2
+                exit 101
+                ;;
+            */broken) exit 101 ;;
+            */one) emit clippy::approx_constant 1 ;;
+            */three | */three_too)
+                emit clippy::approx_constant 1
+                emit clippy::approx_constant 2
+                emit clippy::approx_constant 3
+                ;;
+        esac
+    "#});
+    let target = make_target(&["broken", "clean", "ice", "one", "three", "three_too"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+    // Durations vary between runs.
+    let duration = Regex::new(r"(Duration|Building dependencies|Linting): \d+\.\ds").unwrap();
+
+    let output = shim.run(&target_dir, &["approx_constant"], &[TesterOption::Stats]);
+    let expected_stdout = expect![[r"
+        # Summary
+
+        ## Stats
+
+        - Crates: 6
+        - Linted: 4
+        - Build failures: 2
+        - ICEs: 1
+        - Warnings: 7
+        - Mean warnings per crate: 1.8
+        - Median warnings per crate: 2.0
+        - Most warnings: 3 (three)
+        - Duration: TIME
+        - Building dependencies: TIME
+        - Linting: TIME
+
+        ## Build failures
+    "]];
+    let stdout = duration.replace_all(&output.stdout, "$1: TIME");
+    let start = stdout.find("# Summary").unwrap();
+    let end = stdout.find("## Build failures").unwrap() + "## Build failures\n".len();
+    expected_stdout.assert_eq(&stdout[start..end]);
+    assert!(output.stdout.contains("ice - build failed (ICE)\n"));
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant"],
+        &[TesterOption::Stats, TesterOption::Format("jsonl")],
+    );
+    let summary: serde_json::Value =
+        serde_json::from_str(output.stdout.lines().last().unwrap()).unwrap();
+    let stats = &summary["summary"]["stats"];
+    assert_eq!(stats["linted"], 4);
+    assert_eq!(stats["ices"], 1);
+    assert_eq!(stats["mean_warnings"], 1.75);
+    assert_eq!(stats["median_warnings"], 2.0);
+    assert_eq!(
+        stats["max_warnings"],
+        serde_json::json!({"count": 3, "crate": "three"})
+    );
+    assert!(stats["duration_secs"].is_f64());
+
+    // Without `--stats` the summary is unchanged.
+    let output = shim.run(&target_dir, &["approx_constant"], &[]);
+    assert!(!output.stdout.contains("## Stats"));
+}
+
+#[cfg(unix)]
+#[test]
+fn stats_dependency_build_time() {
+    // A dependency takes about 0.4s to build then the crate itself about 0.2s.
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        sleep 0.4
+        printf '{"reason":"compiler-artifact","package_id":"dep","target":{"name":"dep","kind":["lib"],"src_path":"/registry/dep/src/lib.rs"},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":[],"filenames":[],"executable":null,"fresh":false}\n'
+        sleep 0.2
+        emit clippy::approx_constant 1
+    "#});
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::Stats, TesterOption::Format("jsonl")],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    let summary: serde_json::Value =
+        serde_json::from_str(output.stdout.lines().last().unwrap()).unwrap();
+    let stats = &summary["summary"]["stats"];
+    let dependency_build_secs = stats["dependency_build_secs"].as_f64().unwrap();
+    let lint_secs = stats["lint_secs"].as_f64().unwrap();
+    assert!(dependency_build_secs >= 0.4, "{stats}");
+    assert!(lint_secs >= 0.2, "{stats}");
+    assert!(
+        dependency_build_secs + lint_secs <= stats["duration_secs"].as_f64().unwrap(),
+        "{stats}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn nothing_compiled() {
+    let shim = CargoShim::new("");
+    let target = make_target(&["gated", "plain"]);
+    fs::remove_file(target.path().join("gated/src/main.rs")).unwrap();
+    fs::write(
+        target.path().join("gated/src/lib.rs"),
+        "#![cfg(feature = \"enabled\")]\npub fn f() {}\n",
+    )
+    .unwrap();
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant"],
+        &[],
+    );
+    assert!(output.stdout.contains("gated - nothing compiled\n"));
+    assert!(!output.stdout.contains("plain - nothing compiled"));
+    assert!(output.stdout.contains(indoc::indoc! {"
+        ## Nothing compiled
+
+        Total: 1 (no targets were built - not counted as clean)
+
+        - gated
+    "}));
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn build_check() {
+    let shim = CargoShim::new("emit clippy::approx_constant 1");
+    let target = make_target(&["a"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+    let note = "Build check - no lints requested, so only build failures are reported\n";
+
+    let output = shim.run(&target_dir, &[], &[]);
+    assert!(
+        output
+            .stdout
+            .contains(&format!("# Summary\n\n{note}\n## Build failures\n")),
+        "{}",
+        output.stdout
+    );
+    assert!(!output.stdout.contains("## Warnings"));
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = shim.run(&target_dir, &["approx_constant"], &[]);
+    assert!(!output.stdout.contains(note), "{}", output.stdout);
+    assert!(output.stdout.contains("## Warnings"));
+}
+
+#[cfg(unix)]
+#[test]
+fn summary_from_json() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$PWD" in
+            */broken) exit 101 ;;
+            */few) emit clippy::approx_constant 2 ;;
+            */many)
+                emit clippy::approx_constant 2
+                emit clippy::approx_constant 3
+                emit clippy::approx_constant 4
+            ;;
+        esac
+    "#});
+    let target = make_target(&["broken", "few", "many"]);
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant"],
+        &[TesterOption::Format("jsonl")],
+    );
+    let saved_dir = tempdir().unwrap();
+    let saved = saved_dir.path().join("run.jsonl");
+    fs::write(&saved, &output.stdout).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_clippy_lint_tester"))
+        .env_remove("CLIPPY_SOURCE")
+        .arg("--from-json")
+        .arg(&saved)
+        .args(["--sort", "count"])
+        .output()
+        .unwrap();
+    let expected_stdout = expect![[r"
+
+        # Summary
+
+        ## Build failures
+
+        Total: 1
+
+        - broken
+
+        ## Warnings
+
+        Total: 4
+
+         Crate | Count 
+        :------|------:
+         many  |     3 
+         few   |     1 
+    "]];
+    expected_stdout.assert_eq(&String::from_utf8(output.stdout).unwrap());
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn fix_artifacts_removed() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$ARGS" in
+            *" --fix "*)
+                mkdir -p target/debug
+                cp src/main.rs src/main.rs.orig
+                echo "// fixed" > src/main.rs
+            ;;
+            *) emit clippy::needless_return 2 ;;
+        esac
+    "#});
+    let target = make_target(&["a"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+
+    let fix_dir = tempdir().unwrap();
+    let output = shim.run(
+        &target_dir,
+        &["needless_return"],
+        &[TesterOption::Fix(fix_dir.path().as_os_str())],
+    );
+    assert!(output.stdout.contains("a - fix succeeded\n"));
+    let fixed = fix_dir.path().join("a");
+    assert_eq!(
+        fs::read_to_string(fixed.join("src/main.rs")).unwrap(),
+        "// fixed\n"
+    );
+    assert!(!fixed.join("target").exists());
+    assert!(!fixed.join("src/main.rs.orig").exists());
+    assert!(!target.path().join("a/target").exists());
+    assert!(target.path().join("a/src/main.rs").exists());
+
+    let fix_dir = tempdir().unwrap();
+    shim.run(
+        &target_dir,
+        &["needless_return"],
+        &[
+            TesterOption::Fix(fix_dir.path().as_os_str()),
+            TesterOption::KeepFixArtifacts,
+        ],
+    );
+    let fixed = fix_dir.path().join("a");
+    assert!(fixed.join("target").is_dir());
+    assert!(fixed.join("src/main.rs.orig").is_file());
+}
+
+#[cfg(unix)]
+#[test]
+fn fix_same_named_crates() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$ARGS" in
+            *" --fix "*) echo "// fixed" >> src/main.rs ;;
+            *) emit clippy::needless_return 2 ;;
+        esac
+    "#});
+
+    // Two packages of a git target in dirs named `a`
+    let repos = tempdir().unwrap();
+    let work = repos.path().join("work");
+    for (dir, name) in [("crates", "a"), ("tools", "a_tool")] {
+        let crate_dir = work.join(dir).join("a");
+        fs::create_dir_all(crate_dir.join("src")).unwrap();
+        fs::write(
+            crate_dir.join("Cargo.toml"),
+            format!("[package]\nname = \"{name}\"\nversion = \"0.1.0\"\n"),
+        )
+        .unwrap();
+        fs::write(crate_dir.join("src/main.rs"), format!("// {dir}\n")).unwrap();
+    }
+    git(&work, &["init", "--quiet"]);
+    git(&work, &["add", "."]);
+    git(&work, &["commit", "--quiet", "-m", "a"]);
+    git(
+        repos.path(),
+        &["clone", "--quiet", "--bare", "work", "repo.git"],
+    );
+    let bare = repos.path().join("repo.git");
+    let target_dir = TargetDir::Custom(bare.to_str().unwrap());
+
+    let fix_dir = tempdir().unwrap();
+    let output = shim.run(
+        &target_dir,
+        &["needless_return"],
+        &[TesterOption::Fix(fix_dir.path().as_os_str())],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(
+        output.stdout.contains(&format!(
+            "a - fixed in `{}` as another crate has the same name\n",
+            fix_dir.path().join("a-2").display()
+        )),
+        "{}",
+        output.stdout
+    );
+    assert_eq!(
+        fs::read_to_string(fix_dir.path().join("a/src/main.rs")).unwrap(),
+        "// crates\n// fixed\n"
+    );
+    assert_eq!(
+        fs::read_to_string(fix_dir.path().join("a-2/src/main.rs")).unwrap(),
+        "// tools\n// fixed\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn dependency_warnings_not_counted() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        emit clippy::approx_constant 2
+        emit clippy::approx_constant 3 "" /home/user/.cargo/registry/src/dep-1.0.0/src/lib.rs
+        emit clippy::approx_constant 4 "" ../dep/src/lib.rs
+    "#});
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::CountOnly],
+    );
+    assert!(
+        output.stdout.contains(" a     |     1 \n"),
+        "{}",
+        output.stdout
+    );
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::CountOnly, TesterOption::CountDeps],
+    );
+    assert!(
+        output.stdout.contains(" a     |     3 \n"),
+        "{}",
+        output.stdout
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn cargo_args() {
+    // Only warn if `--locked` is passed to Cargo rather than rustc.
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$ARGS" in *" --locked "*" -- --cap-lints "*)
+            emit clippy::approx_constant 2
+        ;; esac
+    "#});
+    let script_dir = tempdir().unwrap();
+    let script = script_dir.path().join("rerun.sh");
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[
+            TesterOption::CargoArg("--locked"),
+            TesterOption::Script(script.as_os_str()),
+            TesterOption::CountOnly,
+        ],
+    );
+    assert!(
+        output.stdout.contains(" a     |     1 \n"),
+        "{}",
+        output.stdout
+    );
+    assert!(fs::read_to_string(&script)
+        .unwrap()
+        .contains(" --locked --quiet --message-format=json "));
+
+    let output = shim.run(
+        &TargetDir::Default,
+        &["approx_constant"],
+        &[TesterOption::CargoArg("--target-dir=/tmp")],
+    );
+    let expected_stderr = expect![[r"
+        Error: `--cargo-arg --target-dir=/tmp` conflicts with the arguments set by clippy_lint_tester
+    "]];
+    expected_stderr.assert_eq(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[cfg(unix)]
+#[test]
+fn lint_groups() {
+    let shim = CargoShim::new(indoc::indoc! {r"
+        if warned clippy::absurd_extreme_comparisons; then
+            emit clippy::absurd_extreme_comparisons 2
+        fi
+        if warned clippy::needless_return; then
+            emit clippy::needless_return 3
+        fi
+    "});
+    let target = make_target(&["a"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+
+    let output = shim.run(&target_dir, &["correctness"], &[TesterOption::CountOnly]);
+    assert!(output
+        .stderr
+        .contains("`clippy::correctness` expanded to 2 lints\nTesting 2 lints\n"));
+    assert!(
+        output.stdout.contains(" a     |     1 \n"),
+        "{}",
+        output.stdout
+    );
+
+    // Replace the lint list with a restriction group of 51 lints.
+    let names: Vec<_> = (0..51).map(|i| format!("clippy::lint-{i}")).collect();
+    let mut help = String::from("Lint checks provided by plugins loaded by this crate:\n\n");
+    for name in &names {
+        writeln!(help, "    {name}  allow  a lint").unwrap();
+    }
+    help.push_str("\nLint groups provided by plugins loaded by this crate:\n\n");
+    writeln!(help, "    clippy::restriction  {}", names.join(", ")).unwrap();
+    fs::write(shim.dir.path().join("lint_help.txt"), help).unwrap();
+
+    let output = shim.run(&target_dir, &["restriction"], &[]);
+    let expected_stderr = expect![[r#"
+        Compiling Clippy
+        Checking lint names
+        `clippy::restriction` expanded to 51 lints
+        Error: `clippy::restriction` expands to more than 50 lints - pass `--confirm-large-group` to test all of them
+    "#]];
+    expected_stderr.assert_eq(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = shim.run(
+        &target_dir,
+        &["restriction"],
+        &[TesterOption::ConfirmLargeGroup],
+    );
+    assert!(output.stderr.contains("Testing 51 lints\n"));
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn env_vars() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        echo "$SHIM_TEST_VAR" >> "$STATE/vars"
+        case "$PWD" in */broken) exit 101 ;; esac
+    "#});
+    let target = make_target(&["broken", "clean"]);
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant"],
+        &[TesterOption::Env("SHIM_TEST_VAR=a=b c")],
+    );
+    assert_eq!(
+        fs::read_to_string(shim.dir.path().join("vars")).unwrap(),
+        "a=b c\na=b c\n"
+    );
+    // The command printed for the failed build reproduces the environment.
+    assert!(
+        output.stdout.contains(" && SHIM_TEST_VAR='a=b c' "),
+        "{}",
+        output.stdout
+    );
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant"],
+        &[TesterOption::Env("SHIM_TEST_VAR")],
+    );
+    let expected_stderr = expect![[r"
+        Error: `--env SHIM_TEST_VAR` must be of the form `KEY=VALUE`
+    "]];
+    expected_stderr.assert_eq(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[cfg(unix)]
+#[test]
+fn manifest_name() {
+    let shim = CargoShim::new(r#"echo "$*" >> "$STATE/args""#);
+    let target = tempdir().unwrap();
+    let crate_dir = target.path().join("a/rust");
+    fs::create_dir_all(crate_dir.join("src")).unwrap();
+    fs::write(crate_dir.join("Cargo.toml"), "").unwrap();
+    fs::write(crate_dir.join("src/main.rs"), "").unwrap();
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+
+    let output = shim.run(&target_dir, &[], &[]);
+    assert!(
+        output.stdout.contains("/a - not a crate\n"),
+        "{}",
+        output.stdout
+    );
+
+    let output = shim.run(
+        &target_dir,
+        &[],
+        &[TesterOption::ManifestName("rust/Cargo.toml")],
+    );
+    assert!(!output.stdout.contains("not a crate"), "{}", output.stdout);
+    assert_eq!(output.status.code(), Some(0));
+    let args = fs::read_to_string(shim.dir.path().join("args")).unwrap();
+    assert!(args.contains(" --manifest-path rust/Cargo.toml "), "{args}");
+}
+
+#[cfg(unix)]
+#[test]
+fn quiet_invalid() {
+    let shim = CargoShim::new("");
+    let target = make_target(&["a"]);
+    fs::write(target.path().join("a.txt"), "").unwrap();
+    fs::create_dir(target.path().join("b")).unwrap();
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant"],
+        &[TesterOption::QuietInvalid],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    let expected_stdout = expect![[r"
+
+        # Summary
+
+        ## Not crates
+
+        Total: 2
+
+        ## Warnings
+
+        Total: 0
+    "]];
+    expected_stdout.assert_eq(&output.stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn lockfiles() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$ARGS" in *" generate-lockfile "*)
+            echo "$(basename "$PWD")" >> "$STATE/generated"
+            case "$PWD" in */broken) exit 101 ;; esac
+            touch Cargo.lock
+            exit 0
+            ;;
+        esac
+        case "$ARGS" in *" --locked "*)
+            if [ ! -e Cargo.lock ]; then
+                echo "error: the lock file $PWD/Cargo.lock needs to be updated but --locked was passed to prevent this" >&2
+                exit 101
+            fi
+            ;;
+        esac
+    "#});
+    let target = make_target(&["a", "b", "broken"]);
+    fs::write(target.path().join("a/Cargo.lock"), "").unwrap();
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+
+    let output = shim.run(&target_dir, &["approx_constant"], &[TesterOption::Locked]);
+    assert!(
+        output
+            .stdout
+            .contains("b - build failed (lockfile out of date)\n"),
+        "{}",
+        output.stdout
+    );
+    assert!(output
+        .stdout
+        .contains("## Lockfile failures\n\nTotal: 2\n\n- b\n- broken\n"));
+    assert!(!shim.dir.path().join("generated").exists());
+
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant"],
+        &[TesterOption::Locked, TesterOption::GenerateLockfile],
+    );
+    assert!(output
+        .stdout
+        .contains("broken - failed to generate the lockfile\n"));
+    assert!(output
+        .stdout
+        .contains("## Lockfile failures\n\nTotal: 1\n\n- broken\n"));
+    assert_eq!(
+        fs::read_to_string(shim.dir.path().join("generated")).unwrap(),
+        "b\nbroken\n"
+    );
+    assert!(target.path().join("b/Cargo.lock").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn dedup_versions() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        echo "$(basename "$PWD")" >> "$STATE/runs"
+    "#});
+    let target = make_target(&["serde-1.0.9", "serde-1.0.10", "syn-2.0.0-rc.1", "other"]);
+    // The manifest's version is used over the dir name's
+    fs::write(
+        target.path().join("other/Cargo.toml"),
+        "[package]\nname = \"syn\"\nversion = \"2.0.0\"\n",
+    )
+    .unwrap();
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+    let runs_path = shim.dir.path().join("runs");
+
+    shim.run(&target_dir, &["approx_constant"], &[]);
+    assert_eq!(
+        fs::read_to_string(&runs_path).unwrap(),
+        "other\nserde-1.0.10\nserde-1.0.9\nsyn-2.0.0-rc.1\n"
+    );
+    fs::remove_file(&runs_path).unwrap();
+
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant"],
+        &[TesterOption::DedupVersions],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        fs::read_to_string(&runs_path).unwrap(),
+        "other\nserde-1.0.10\n"
+    );
+    assert!(output
+        .stderr
+        .contains("Skipping `serde-1.0.9` - `serde-1.0.10` is a newer version\n"));
+    assert!(output
+        .stderr
+        .contains("Skipping `syn-2.0.0-rc.1` - `other` is a newer version\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn sample() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        echo "$(basename "$PWD")" >> "$STATE/runs"
+    "#});
+    let target = make_target(&["a", "b", "c", "d", "e", "skipped"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+    let runs_path = shim.dir.path().join("runs");
+
+    for _ in 0..2 {
+        let output = shim.run(
+            &target_dir,
+            &["approx_constant"],
+            &[
+                TesterOption::Include("?"),
+                TesterOption::Sample(2),
+                TesterOption::Seed(42),
+            ],
+        );
+        assert_eq!(output.status.code(), Some(0));
+        assert!(
+            output
+                .stderr
+                .contains("Sampled 2 of 5 crates with `--seed 42`: d, e\n"),
+            "{}",
+            output.stderr
+        );
+        assert_eq!(fs::read_to_string(&runs_path).unwrap(), "d\ne\n");
+        fs::remove_file(&runs_path).unwrap();
+    }
+
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant"],
+        &[TesterOption::Sample(2)],
+    );
+    assert!(output
+        .stderr
+        .contains("Sampled 2 of 6 crates with `--seed "));
+    assert_eq!(fs::read_to_string(&runs_path).unwrap().lines().count(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn fix_errors_reported() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$ARGS" in
+            *" --fix "*)
+                LEVEL=error emit E0502 3 "cannot borrow" src/lib.rs
+                LEVEL=error emit E0502 7 "cannot borrow" src/lib.rs
+                LEVEL=error emit E0425 1
+                LEVEL=error NO_CODE=1 emit compile_error 2 "unsupported target" src/lib.rs
+                echo "error: could not compile \`a\`" >&2
+                exit 101
+                ;;
+            *) emit clippy::approx_constant 1 ;;
+        esac
+    "#});
+    let target = make_target(&["a"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+
+    let fix_dir = tempdir().unwrap();
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant"],
+        &[
+            TesterOption::Fix(fix_dir.path().as_os_str()),
+            TesterOption::CountOnly,
+        ],
+    );
+    assert!(
+        output.stdout.contains(
+            "a - fix failed with E0502 in src/lib.rs, E0425 in src/main.rs, unsupported target in \
+            src/lib.rs\n"
+        ),
+        "{}",
+        output.stdout
+    );
+    assert!(!output.stdout.contains("could not compile"));
+    assert!(output.stdout.contains(
+        "## Fix failures\n\nTotal: 1\n\n- a - E0502 in src/lib.rs, E0425 in src/main.rs, unsupported \
+        target in src/lib.rs\n"
+    ));
+
+    let fix_dir = tempdir().unwrap();
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant"],
+        &[
+            TesterOption::Fix(fix_dir.path().as_os_str()),
+            TesterOption::VerboseFix,
+        ],
+    );
+    assert!(output.stdout.contains("error: could not compile `a`"));
+}
+
+#[cfg(unix)]
+#[test]
+fn compare_fix() {
+    // Fixing `approx_constant` leaves a `needless_return` behind.
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        case "$ARGS" in
+            *" --fix "*) touch fixed ;;
+            *)
+                if warned clippy::approx_constant && [ ! -f fixed ]; then
+                    emit clippy::approx_constant 1
+                fi
+                if warned clippy::absurd_extreme_comparisons; then
+                    emit clippy::absurd_extreme_comparisons 2
+                fi
+                if warned clippy::needless_return && [ -f fixed ]; then
+                    emit clippy::needless_return 3
+                fi
+                ;;
+        esac
+    "#});
+    let target = make_target(&["a"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+
+    let fix_dir = tempdir().unwrap();
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant"],
+        &[
+            TesterOption::Fix(fix_dir.path().as_os_str()),
+            TesterOption::CompareFix,
+            TesterOption::CountOnly,
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(
+        output
+            .stdout
+            .contains("a - fix added warnings: clippy::needless_return +1\n"),
+        "{}",
+        output.stdout
+    );
+    assert!(output
+        .stdout
+        .contains("## Fix side-effects\n\nTotal: 1\n\n- a - clippy::needless_return +1\n"));
+
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant"],
+        &[TesterOption::CompareFix],
+    );
+    assert!(output.stderr.contains("`--compare-fix` requires `--fix`"));
+}
+
+#[cfg(unix)]
+#[test]
+fn rustflags() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        echo "$RUSTFLAGS" >> "$STATE/rustflags"
+        case " $RUSTFLAGS " in *" --cfg special "*) emit clippy::approx_constant 1 ;; esac
+    "#});
+    let target = make_target(&["a"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+    let rustflags_file = target.path().join("_target/clippy_lint_tester-rustflags");
+    // `None` runs without any `RUSTFLAGS` in the environment
+    let run_with_ambient = |ambient: Option<&str>, options: &[TesterOption<'_>]| {
+        let mut command = make_clippy_lint_tester_command(
+            &ClippyWorkspace::Custom(shim.workspace()),
+            &target_dir,
+            &["approx_constant"],
+            options,
+        );
+        command.env("PATH", shim.path());
+        match ambient {
+            Some(rustflags) => command.env("RUSTFLAGS", rustflags),
+            None => command.env_remove("RUSTFLAGS"),
+        };
+        run_command(command)
+    };
+    let with_flags = [
+        TesterOption::Rustflags("-Cdebuginfo=0"),
+        TesterOption::Cfg("special"),
+    ];
+
+    // Nothing is recorded without `--rustflags` or `--cfg`.
+    let output = run_with_ambient(None, &[]);
+    assert!(output.stdout.contains("## Warnings\n\nTotal: 0\n"));
+    assert!(!output.stderr.contains("RUSTFLAGS"), "{}", output.stderr);
+    assert!(!target.path().join("_target").exists());
+
+    // A target dir built by a run using the ambient `RUSTFLAGS`
+    fs::create_dir(target.path().join("_target")).unwrap();
+    let output = run_with_ambient(Some("-Copt-level=1"), &with_flags);
+    assert!(output.stdout.contains("## Warnings\n\nTotal: 1\n"));
+    assert!(output.stderr.contains(
+        "warning: `RUSTFLAGS` changed from `-Copt-level=1` to `-Cdebuginfo=0 --cfg special` since \
+        the last run"
+    ));
+    assert_eq!(
+        fs::read_to_string(shim.dir.path().join("rustflags")).unwrap(),
+        "\n-Cdebuginfo=0 --cfg special\n"
+    );
+
+    let output = run_with_ambient(Some("-Copt-level=1"), &with_flags);
+    assert!(!output.stderr.contains("RUSTFLAGS"), "{}", output.stderr);
+
+    // A run without flags builds with the ambient `RUSTFLAGS` so the record is removed.
+    run_with_ambient(None, &[]);
+    assert!(!rustflags_file.exists());
+    let output = run_with_ambient(None, &with_flags);
+    assert!(output.stderr.contains(
+        "warning: `RUSTFLAGS` changed from `` to `-Cdebuginfo=0 --cfg special` since the last run"
+    ));
+    assert!(rustflags_file.exists());
+
+    let output = run_with_ambient(
+        None,
+        &[
+            TesterOption::Cfg("special"),
+            TesterOption::Env("RUSTFLAGS=x"),
+        ],
+    );
+    assert!(output
+        .stderr
+        .contains("`--env RUSTFLAGS=...` conflicts with `--rustflags` and `--cfg`"));
+}
+
+#[cfg(unix)]
+#[test]
+fn json_diagnostics_dir() {
+    let shim = CargoShim::new(indoc::indoc! {r"
+        case $PWD in */warns)
+            emit clippy::approx_constant 2
+            emit clippy::needless_return 3
+        esac
+    "});
+    let target = make_target(&["clean", "warns"]);
+    let json_dir = tempdir().unwrap();
+    let json_dir = json_dir.path().join("json");
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant"],
+        &[TesterOption::JsonDiagnosticsDir(json_dir.as_os_str())],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    // The warnings are still shown.
+    assert!(output.stdout.contains("---> warns/src/main.rs:2:1\n"));
+
+    assert_eq!(fs::read_to_string(json_dir.join("clean.json")).unwrap(), "");
+    let contents = fs::read_to_string(json_dir.join("warns.json")).unwrap();
+    let messages: Vec<serde_json::Value> = contents
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(messages.len(), 1, "{contents}");
+    assert_eq!(messages[0]["reason"], "compiler-message");
+    assert_eq!(
+        messages[0]["message"]["code"]["code"],
+        "clippy::approx_constant"
+    );
+    assert_eq!(messages[0]["message"]["spans"][0]["line_start"], 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn split_output() {
+    let shim = CargoShim::new(indoc::indoc! {r"
+        emit clippy::approx_constant 2
+        emit clippy::absurd_extreme_comparisons 3
+        emit clippy::approx_constant 4 'approx' src/lib.rs
+    "});
+    let target = make_target(&["a", "b"]);
+    let split_dir = tempdir().unwrap();
+    let split_dir = split_dir.path().join("split");
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant", "absurd_extreme_comparisons"],
+        &[
+            TesterOption::CountOnly,
+            TesterOption::SplitOutput(split_dir.as_os_str()),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert!(output.stdout.contains("## Warnings\n\nTotal: 6\n"));
+
+    let mut files: Vec<_> = fs::read_dir(&split_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "absurd_extreme_comparisons.txt",
+            "approx_constant.txt",
+            "index.md"
+        ]
+    );
+
+    let expected_index = expect![[r"
+        # Warnings by lint
+
+        Total: 6
+
+         Lint                               | Count | File                           
+        :-----------------------------------|------:|:-------------------------------
+         clippy::absurd_extreme_comparisons |     2 | absurd_extreme_comparisons.txt 
+         clippy::approx_constant            |     4 | approx_constant.txt            
+    "]];
+    expected_index.assert_eq(&fs::read_to_string(split_dir.join("index.md")).unwrap());
+
+    let expected_approx_constant = expect![[r"
+        ---> a/src/main.rs:2:1
+        warning: clippy::approx_constant
+
+        ---> a/src/lib.rs:4:1
+        warning: clippy::approx_constant
+
+        ---> b/src/main.rs:2:1
+        warning: clippy::approx_constant
+
+        ---> b/src/lib.rs:4:1
+        warning: clippy::approx_constant
+    "]];
+    expected_approx_constant
+        .assert_eq(&fs::read_to_string(split_dir.join("approx_constant.txt")).unwrap());
+    let expected_absurd_extreme_comparisons = expect![[r"
+        ---> a/src/main.rs:3:1
+        warning: clippy::absurd_extreme_comparisons
+
+        ---> b/src/main.rs:3:1
+        warning: clippy::absurd_extreme_comparisons
+    "]];
+    expected_absurd_extreme_comparisons
+        .assert_eq(&fs::read_to_string(split_dir.join("absurd_extreme_comparisons.txt")).unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn by_target() {
+    let shim = CargoShim::new(indoc::indoc! {r"
+        KIND=lib emit clippy::approx_constant 1 approx src/lib.rs
+        emit clippy::approx_constant 2
+        emit clippy::approx_constant 3
+        # The lib's module reported again for the bin
+        emit clippy::approx_constant 1 approx src/lib.rs
+        KIND=example NAME=ex emit clippy::approx_constant 1 approx examples/ex.rs
+    "});
+    let target = make_target(&["a"]);
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant"],
+        &[TesterOption::CountOnly, TesterOption::ByTarget],
+    );
+
+    let expected_stdout = expect![[r"
+
+        # Summary
+
+        ## Warnings
+
+        Total: 4
+
+         Crate | Count 
+        :------|------:
+         a     |     4 
+
+        ## Warnings by target
+
+         Crate | Target     | Count 
+        :------|:-----------|------:
+         a     | bin a      |     2 
+         a     | example ex |     1 
+         a     | lib        |     1 
+    "]];
+    expected_stdout.assert_eq(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+}
+
+#[cfg(unix)]
+#[test]
+fn density() {
+    let shim = CargoShim::new(indoc::indoc! {r"
+        emit clippy::approx_constant 2
+        emit clippy::approx_constant 3
+    "});
+    let target = make_target(&["a"]);
+    let crate_dir = target.path().join("a");
+    fs::write(crate_dir.join("src/main.rs"), "fn main() {}\n".repeat(300)).unwrap();
+    fs::create_dir(crate_dir.join("tests")).unwrap();
+    fs::write(crate_dir.join("tests/t.rs"), "\n".repeat(200)).unwrap();
+    // Not Rust source so not counted
+    fs::write(crate_dir.join("README.md"), "\n".repeat(1000)).unwrap();
+
+    let output = shim.run(
+        &TargetDir::Custom(target.path().to_str().unwrap()),
+        &["approx_constant"],
+        &[TesterOption::Density, TesterOption::CountOnly],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    let expected_stdout = expect![[r"
+
+        # Summary
+
+        ## Warnings
+
+        Total: 2
+
+         Crate | Count | Lines | Per kloc 
+        :------|------:|------:|---------:
+         a     |     2 |   500 |     4.00 
+    "]];
+    expected_stdout.assert_eq(&output.stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn per_crate_target_dir() {
+    // Building `a` leaves the target dir in a state that breaks the build of `b`.
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        TARGET_DIR=$(echo "$ARGS" | sed 's/.* --target-dir \([^ ]*\) .*/\1/')
+        if [ -e "$TARGET_DIR/poisoned" ]; then
+            echo "error[E0514]: found crate \`p\` compiled by an incompatible version of rustc" >&2
+            exit 101
+        fi
+        case "$PWD" in */a) mkdir -p "$TARGET_DIR" && touch "$TARGET_DIR/poisoned" ;; esac
+    "#});
+    let target = make_target(&["a", "b"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+
+    let output = shim.run(&target_dir, &["approx_constant"], &[]);
+    assert!(output
+        .stdout
+        .contains("## Build failures\n\nTotal: 1\n\n- b\n"));
+    fs::remove_dir_all(target.path().join("_target")).unwrap();
+
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant"],
+        &[TesterOption::PerCrateTargetDir],
+    );
+    assert!(
+        !output.stdout.contains("Build failures"),
+        "{}",
+        output.stdout
+    );
+    assert!(!target.path().join("_target/a").exists());
+
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant"],
+        &[TesterOption::PerCrateTargetDir, TesterOption::KeepTarget],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert!(target.path().join("_target/a/poisoned").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn list_lints() {
+    let shim = CargoShim::new("");
+    let list_lints = |groups: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_clippy_lint_tester"));
+        command
+            .env_remove("CLIPPY_SOURCE")
+            .env("PATH", shim.path())
+            .arg(shim.workspace())
+            .arg("--list-lints")
+            .args(groups);
+        run_command(command)
+    };
+
+    let output = list_lints(&[]);
+    let expected_stdout = expect![[r"
+         Lint                               | Group               | Default 
+        :-----------------------------------|:--------------------|:--------
+         clippy::absurd_extreme_comparisons | clippy::correctness | deny    
+         clippy::approx_constant            | clippy::correctness | deny    
+         clippy::needless_return            | clippy::style       | warn    
+         clippy::dbg_macro                  | clippy::restriction | allow   
+
+        Total: 4
+    "]];
+    expected_stdout.assert_eq(&output.stdout);
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = list_lints(&["correctness", "clippy::style"]);
+    let expected_stdout = expect![[r"
+         Lint                               | Group               | Default 
+        :-----------------------------------|:--------------------|:--------
+         clippy::absurd_extreme_comparisons | clippy::correctness | deny    
+         clippy::approx_constant            | clippy::correctness | deny    
+         clippy::needless_return            | clippy::style       | warn    
+
+        Total: 3
+    "]];
+    expected_stdout.assert_eq(&output.stdout);
+
+    let output = list_lints(&["pedantic"]);
+    let expected_stderr = expect![[r"
+        Compiling Clippy
+        Error: Lint groups not found: `clippy::pedantic`
+    "]];
+    expected_stderr.assert_eq(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[cfg(unix)]
+#[test]
+fn warmup() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        if warned clippy::approx_constant; then
+            echo "lint $(basename "$PWD")" >> "$STATE/runs"
+        else
+            echo "warmup $(basename "$PWD")" >> "$STATE/runs"
+        fi
+    "#});
+    let target = make_target(&["a", "b"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+    let runs_path = shim.dir.path().join("runs");
+
+    let output = shim.run(&target_dir, &["approx_constant"], &[]);
+    assert!(!output.stderr.contains("Warmed up"));
+    assert_eq!(fs::read_to_string(&runs_path).unwrap(), "lint a\nlint b\n");
+    fs::remove_file(&runs_path).unwrap();
+
+    let output = shim.run(&target_dir, &["approx_constant"], &[TesterOption::Warmup]);
+    assert!(
+        output.stderr.contains("Warmed up 2 crates in "),
+        "{}",
+        output.stderr
+    );
+    assert_eq!(
+        fs::read_to_string(&runs_path).unwrap(),
+        "warmup a\nwarmup b\nlint a\nlint b\n"
+    );
+}
+
+// Waits up to 30 seconds for `path` to be created.
+#[cfg(unix)]
+fn wait_for_file(path: &Path) {
+    let start = std::time::Instant::now();
+    while !path.exists() {
+        assert!(
+            start.elapsed() < std::time::Duration::from_secs(30),
+            "`{}` wasn't created",
+            path.display()
+        );
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+}
+
+#[cfg(unix)]
+fn send_sigint(pid: u32) {
+    let status = Command::new("kill")
+        .args(["-INT", &pid.to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupted() {
+    // Each crate waits until `release` exists so the interrupt arrives mid-crate.
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        if warned clippy::approx_constant; then
+            echo $$ > "$STATE/pid"
+            touch "$STATE/started_$(basename "$PWD")"
+            while [ ! -e "$STATE/release" ]; do sleep 0.05; done
+            emit clippy::approx_constant 1
+        fi
+    "#});
+    let target = make_target(&["a", "b", "c"]);
+    let target_dir = TargetDir::Custom(target.path().to_str().unwrap());
+    let state = shim.dir.path();
+
+    let mut command = make_clippy_lint_tester_command(
+        &ClippyWorkspace::Custom(shim.workspace()),
+        &target_dir,
+        &["approx_constant"],
+        &[],
+    );
+    let child = command
+        .env("PATH", shim.path())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    wait_for_file(&state.join("started_a"));
+    send_sigint(child.id());
+    // The current crate isn't interrupted by the first Ctrl-C.
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    fs::write(state.join("release"), "").unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(130), "{stderr}");
+    assert!(
+        stderr.contains("Stopping after the current crate"),
+        "{stderr}"
+    );
+    assert!(
+        stdout.contains("Stopped early - 1 of 3 crates linted"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("\n a     |     1 \n"), "{stdout}");
+    assert!(!state.join("started_b").exists());
+    assert!(!state.join("started_c").exists());
+
+    // A second Ctrl-C interrupts the current crate and exits.
+    fs::remove_file(state.join("release")).unwrap();
+    fs::remove_file(state.join("started_a")).unwrap();
+    let child = command.spawn().unwrap();
+    wait_for_file(&state.join("started_a"));
+    send_sigint(child.id());
+    // Signals sent together may be delivered as one.
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    send_sigint(child.id());
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130));
+    assert!(!String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("# Summary"));
+    let shim_pid = fs::read_to_string(state.join("pid")).unwrap();
+    let start = std::time::Instant::now();
+    while Command::new("kill")
+        .args(["-0", shim_pid.trim()])
+        .stderr(Stdio::null())
+        .status()
+        .unwrap()
+        .success()
+    {
+        assert!(
+            start.elapsed() < std::time::Duration::from_secs(30),
+            "the running crate wasn't interrupted"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+}
+
+// Runs git in `dir` with a fixed identity, panicking if it fails.
+#[cfg(unix)]
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {args:?}");
+}
+
+#[cfg(unix)]
+#[test]
+fn git_target() {
+    let shim = CargoShim::new(indoc::indoc! {r#"
+        echo "$(basename "$PWD")" >> "$STATE/runs"
+    "#});
+    let runs_path = shim.dir.path().join("runs");
+
+    // A workspace whose second member is only added after the `v1` tag
+    let repos = tempdir().unwrap();
+    let work = repos.path().join("work");
+    let add_crate = |name: &str| {
+        let crate_dir = work.join("crates").join(name);
+        fs::create_dir_all(crate_dir.join("src")).unwrap();
+        fs::write(
+            crate_dir.join("Cargo.toml"),
+            format!("[package]\nname = \"{name}\"\nversion = \"0.1.0\"\n"),
+        )
+        .unwrap();
+        fs::write(crate_dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+    };
+    fs::create_dir(&work).unwrap();
+    fs::write(
+        work.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"crates/*\"]\n",
+    )
+    .unwrap();
+    add_crate("a");
+    git(&work, &["init", "--quiet"]);
+    git(&work, &["add", "."]);
+    git(&work, &["commit", "--quiet", "-m", "a"]);
+    git(&work, &["tag", "v1"]);
+    add_crate("b");
+    git(&work, &["add", "."]);
+    git(&work, &["commit", "--quiet", "-m", "b"]);
+    git(
+        repos.path(),
+        &["clone", "--quiet", "--bare", "work", "repo.git"],
+    );
+    let bare = repos.path().join("repo.git");
+    let target_dir = TargetDir::Custom(bare.to_str().unwrap());
+
+    let output = shim.run(&target_dir, &["approx_constant"], &[]);
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert_eq!(fs::read_to_string(&runs_path).unwrap(), "a\nb\n");
+    fs::remove_file(&runs_path).unwrap();
+
+    let output = shim.run(
+        &target_dir,
+        &["approx_constant"],
+        &[TesterOption::GitRef("v1"), TesterOption::KeepClone],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert_eq!(fs::read_to_string(&runs_path).unwrap(), "a\n");
+    let clone_path = output
+        .stderr
+        .lines()
+        .find_map(|line| {
+            line.strip_prefix("Keeping the clone at `")?
+                .strip_suffix('`')
+        })
+        .expect("clone path printed");
+    let clone_path = Path::new(clone_path);
+    assert!(clone_path.join("crates/a/Cargo.toml").is_file());
+    assert!(!clone_path.join("crates/b").exists());
+    fs::remove_dir_all(clone_path.parent().unwrap()).unwrap();
+
+    let output = shim.run(
+        &TargetDir::Custom(make_target(&["a"]).path().to_str().unwrap()),
+        &["approx_constant"],
+        &[TesterOption::KeepClone],
+    );
+    assert!(output
+        .stderr
+        .contains("`--keep-clone` requires the target to be a git repository"));
+}
+
+#[test]
+fn clippy_source_missing() {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_clippy_lint_tester"));
+    command.env_remove("CLIPPY_SOURCE");
+    let output = run_command(command);
+
+    let expected_stderr = expect![[r"
+        Error: Missing the path to the Clippy source. Pass it as the first argument or set `CLIPPY_SOURCE`
+    "]];
+    expected_stderr.assert_eq(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+// A minimal stand-in for crates.io. Every listing page has two crates and every download
+// takes a little while so concurrent downloads overlap. Downloads of the crates in `missing` 404.
+// The archives of the crates in `archive_dirs` unpack to those dirs instead of `name-version`.
+// The index has the versions 1.0.0, 1.2.3 and 2.0.0 of every crate, with the wrong checksums for
+// the crates in `bad_checksums`. With `shifted_listing` set every page after the first starts a
+// crate earlier, as if a crate moved up during the listing, so the crate at the end of the previous
+// page is listed twice.
+struct MockCratesIo {
+    url: String,
+    shifted_listing: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    missing: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    bad_checksums: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    archive_dirs: std::sync::Arc<ArchiveDirs>,
+    listing_times: std::sync::Arc<std::sync::Mutex<Vec<std::time::Instant>>>,
+    max_active_downloads: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    downloads: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl MockCratesIo {
+    fn start() -> Self {
+        use std::io::{BufRead, BufReader, Write};
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::sync::{Arc, Mutex};
+        use std::time::{Duration, Instant};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let listing_times = Arc::new(Mutex::new(Vec::new()));
+        let active_downloads = Arc::new(AtomicUsize::new(0));
+        let max_active_downloads = Arc::new(AtomicUsize::new(0));
+        let downloads = Arc::new(AtomicUsize::new(0));
+        let shifted_listing = Arc::new(AtomicBool::new(false));
+        let missing = Arc::new(Mutex::new(Vec::new()));
+        let bad_checksums = Arc::new(Mutex::new(Vec::new()));
+        let archive_dirs = Arc::new(Mutex::new(std::collections::HashMap::new()));
+
+        let server = MockCratesIo {
+            url,
+            shifted_listing: Arc::clone(&shifted_listing),
+            missing: Arc::clone(&missing),
+            bad_checksums: Arc::clone(&bad_checksums),
+            archive_dirs: Arc::clone(&archive_dirs),
+            listing_times: Arc::clone(&listing_times),
+            max_active_downloads: Arc::clone(&max_active_downloads),
+            downloads: Arc::clone(&downloads),
+        };
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let listing_times = Arc::clone(&listing_times);
+                let active_downloads = Arc::clone(&active_downloads);
+                let max_active_downloads = Arc::clone(&max_active_downloads);
+                let downloads = Arc::clone(&downloads);
+                let shifted_listing = Arc::clone(&shifted_listing);
+                let missing = Arc::clone(&missing);
+                let bad_checksums = Arc::clone(&bad_checksums);
+                let archive_dirs = Arc::clone(&archive_dirs);
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut header = String::new();
+                    while reader.read_line(&mut header).unwrap() > 2 {
+                        header.clear();
+                    }
+                    let path = request_line.split(' ').nth(1).unwrap().to_string();
+
+                    let crate_name = path.split('/').nth(2).unwrap_or_default();
+                    if missing
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .any(|name| name == crate_name)
+                    {
+                        write!(
+                            stream,
+                            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        )
+                        .unwrap();
+                        return;
+                    }
+
+                    let body = if let Some(query) = path.strip_prefix("/api/crates?") {
+                        listing_times.lock().unwrap().push(Instant::now());
+                        let shifted = shifted_listing.load(Ordering::SeqCst);
+                        mock_listing_page(query, shifted)
+                    } else if let Some(index_path) = path.strip_prefix("/index/") {
+                        let name = index_path.rsplit('/').next().unwrap();
+                        let bad = bad_checksums.lock().unwrap().iter().any(|bad| bad == name);
+                        mock_index_file(&archive_dirs, name, bad)
+                    } else {
+                        downloads.fetch_add(1, Ordering::SeqCst);
+                        let active = active_downloads.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_active_downloads.fetch_max(active, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(300));
+                        active_downloads.fetch_sub(1, Ordering::SeqCst);
+                        let archive_name =
+                            path.rsplit('/').next().unwrap().trim_end_matches(".crate");
+                        let version = &archive_name[crate_name.len() + 1..];
+                        mock_archive(&archive_dirs, crate_name, version)
+                    };
+
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .unwrap();
+                    stream.write_all(&body).unwrap();
+                });
+            }
+        });
+
+        server
+    }
+
+    fn downloads(&self) -> usize {
+        self.downloads.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    fn download_crates_command(&self, target: &Path, cache_dir: &Path) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_download_crates"));
+        command
+            .arg(target)
+            .arg("--cache-dir")
+            .arg(cache_dir)
+            .env("CRATES_IO_API_URL", format!("{}/api", self.url))
+            .env("CRATES_IO_STATIC_URL", &self.url)
+            .env("CRATES_IO_INDEX_URL", format!("{}/index", self.url));
+        command
+    }
+}
+
+// A page of the listing of the most downloaded crates, named `c1`, `c2`, ...
+fn mock_listing_page(query: &str, shifted: bool) -> Vec<u8> {
+    let page: usize = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("page="))
+        .unwrap()
+        .parse()
+        .unwrap();
+    let first = if page > 1 && shifted {
+        page * 2 - 2
+    } else {
+        page * 2 - 1
+    };
+    let crates: Vec<_> = [first, first + 1]
+        .iter()
+        .map(|n| format!(r#"{{"name":"c{n}","max_version":"1.0.0","max_stable_version":null}}"#))
+        .collect();
+    format!(r#"{{"crates":[{}]}}"#, crates.join(",")).into_bytes()
+}
+
+type ArchiveDirs = std::sync::Mutex<std::collections::HashMap<String, Vec<String>>>;
+
+fn mock_archive(archive_dirs: &ArchiveDirs, name: &str, version: &str) -> Vec<u8> {
+    match archive_dirs.lock().unwrap().get(name) {
+        Some(dirs) => make_crate_archive(&dirs.iter().map(String::as_str).collect::<Vec<_>>()),
+        None => make_crate_archive(&[&format!("{name}-{version}")]),
+    }
+}
+
+// The crate's file in the sparse index, with an entry for each version the mock serves.
+fn mock_index_file(archive_dirs: &ArchiveDirs, name: &str, bad_checksum: bool) -> Vec<u8> {
+    use std::fmt::Write;
+    let mut file = String::new();
+    for version in ["1.0.0", "1.2.3", "2.0.0"] {
+        let checksum = if bad_checksum {
+            "0".repeat(64)
+        } else {
+            sha256_hex(&mock_archive(archive_dirs, name, version))
+        };
+        writeln!(
+            file,
+            r#"{{"name":"{name}","vers":"{version}","cksum":"{checksum}"}}"#
+        )
+        .unwrap();
+    }
+    file.into_bytes()
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    use std::fmt::Write;
+    sha2::Sha256::digest(bytes)
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            write!(hex, "{byte:02x}").unwrap();
+            hex
+        })
+}
+
+fn make_crate_archive(dir_names: &[&str]) -> Vec<u8> {
+    let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    let mut builder = tar::Builder::new(encoder);
+    for dir_name in dir_names {
+        for (file, contents) in [
+            (
+                "Cargo.toml",
+                "[package]\nname = \"c\"\nversion = \"1.0.0\"\n",
+            ),
+            ("src/lib.rs", "#![allow(clippy::all)]\n"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(
+                    &mut header,
+                    format!("{dir_name}/{file}"),
+                    contents.as_bytes(),
+                )
+                .unwrap();
+        }
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+#[test]
+fn download_crates_listing_is_rate_limited_and_downloads_overlap() {
+    let server = MockCratesIo::start();
+    let target = tempdir().unwrap();
+    let cache_dir = tempdir().unwrap();
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "5", "--download-jobs", "4"]);
+    let output = run_command(command);
+
+    assert!(output.status.success(), "{}", output.stderr);
+    assert!(output.stdout.contains("Listed 5 crates in "));
+    assert!(output.stdout.contains("Downloaded 5 crates in "));
+    for n in 1..=5 {
+        let lib = target.path().join(format!("c{n}-1.0.0/src/lib.rs"));
+        assert!(fs::read_to_string(lib)
+            .unwrap()
+            .starts_with("/* cleaned by clippy_lint_tester"));
+    }
+
+    let listing_times = server.listing_times.lock().unwrap();
+    assert_eq!(listing_times.len(), 3);
+    for pair in listing_times.windows(2) {
+        assert!(pair[1].duration_since(pair[0]) >= std::time::Duration::from_secs(1));
+    }
+    assert!(
+        server
+            .max_active_downloads
+            .load(std::sync::atomic::Ordering::SeqCst)
+            > 1
+    );
+}
+
+#[test]
+fn download_crates_cache() {
+    let server = MockCratesIo::start();
+    let cache_dir = tempdir().unwrap();
+
+    // Cache miss: the archives are downloaded and stored.
+    let target = tempdir().unwrap();
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "2"]);
+    let output = run_command(command);
+    assert!(output.status.success(), "{}", output.stderr);
+    assert_eq!(server.downloads(), 2);
+    let mut cached: Vec<_> = fs::read_dir(cache_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    cached.sort();
+    assert_eq!(cached, ["c1-1.0.0.crate", "c2-1.0.0.crate"]);
+
+    // Cache hit: nothing is downloaded for a new target.
+    let target = tempdir().unwrap();
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "2"]);
+    let output = run_command(command);
+    assert!(output.status.success(), "{}", output.stderr);
+    assert_eq!(server.downloads(), 2);
+    assert!(target.path().join("c1-1.0.0/src/lib.rs").is_file());
+    assert!(target.path().join("c2-1.0.0/src/lib.rs").is_file());
+
+    // A corrupt archive in the cache is downloaded again.
+    let c1 = cache_dir.path().join("c1-1.0.0.crate");
+    let mut corrupt = fs::read(&c1).unwrap();
+    corrupt.truncate(corrupt.len() / 2);
+    fs::write(&c1, corrupt).unwrap();
+    let target = tempdir().unwrap();
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "2"]);
+    let output = run_command(command);
+    assert!(output.status.success(), "{}", output.stderr);
+    assert_eq!(server.downloads(), 3);
+    assert_eq!(fs::read(&c1).unwrap(), make_crate_archive(&["c1-1.0.0"]));
+
+    // So is a well-formed archive that doesn't match the registry's checksum.
+    fs::write(&c1, make_crate_archive(&["tampered"])).unwrap();
+    let target = tempdir().unwrap();
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "2"]);
+    let output = run_command(command);
+    assert!(output.status.success(), "{}", output.stderr);
+    assert_eq!(server.downloads(), 4);
+    assert_eq!(fs::read(&c1).unwrap(), make_crate_archive(&["c1-1.0.0"]));
+    assert!(target.path().join("c1-1.0.0/src/lib.rs").is_file());
+}
+
+#[test]
+fn download_crates_checksum_mismatch() {
+    let server = MockCratesIo::start();
+    server.bad_checksums.lock().unwrap().push("c2".into());
+    let target = tempdir().unwrap();
+    let cache_dir = tempdir().unwrap();
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "2"]);
+    let output = run_command(command);
+
+    assert!(output.status.success(), "{}", output.stderr);
+    assert!(
+        output.stdout.contains(
+            "error: Failed to download 'c2' - Downloaded crate 'c2' doesn't match the registry \
+            checksum"
+        ),
+        "{}",
+        output.stdout
+    );
+    assert!(
+        output
+            .stdout
+            .contains("Failed to download 1 crates:\n- Checksum (1): c2\n"),
+        "{}",
+        output.stdout
+    );
+    assert!(target.path().join("c1-1.0.0/src/lib.rs").is_file());
+    assert!(!target.path().join("c2-1.0.0").exists());
+    // The mismatching archive isn't cached.
+    assert!(!cache_dir.path().join("c2-1.0.0.crate").exists());
+}
+
+#[test]
+fn download_crates_force() {
+    let server = MockCratesIo::start();
+    let cache_dir = tempdir().unwrap();
+    let target = tempdir().unwrap();
+    fs::write(target.path().join("old.txt"), "").unwrap();
+    fs::create_dir_all(target.path().join("old-1.0.0/src")).unwrap();
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "1"]);
+    let output = run_command(command);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output
+        .stderr
+        .contains("Target exists and not empty - use `--force` to clear it"));
+    assert!(target.path().join("old.txt").exists());
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "1", "--force"]);
+    let output = run_command(command);
+    assert!(output.status.success(), "{}", output.stderr);
+    assert!(output.stdout.contains("Target directory cleared"));
+    let mut entries: Vec<_> = fs::read_dir(target.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, ["c1-1.0.0"]);
+}
+
+#[test]
+fn download_crates_exclude_file() {
+    let server = MockCratesIo::start();
+    let target = tempdir().unwrap();
+    let cache_dir = tempdir().unwrap();
+    let exclude_file = cache_dir.path().join("exclude.txt");
+    fs::write(
+        &exclude_file,
+        "# Too big to download\nc1\n\n  c3  # breaks the downloader\n#c4\n",
+    )
+    .unwrap();
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command
+        .args(["-n", "3", "-x", "c4", "--exclude-file"])
+        .arg(&exclude_file);
+    let output = run_command(command);
+
+    assert!(output.status.success(), "{}", output.stderr);
+    let mut entries: Vec<_> = fs::read_dir(target.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, ["c2-1.0.0", "c5-1.0.0", "c6-1.0.0"]);
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command
+        .args(["-n", "1", "--force", "--exclude-file"])
+        .arg(cache_dir.path().join(NON_EXISTING));
+    let output = run_command(command);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        output.stderr.contains("Failed to read exclude file"),
+        "{}",
+        output.stderr
+    );
+    // The target isn't cleared for a bad argument.
+    assert!(target.path().join("c2-1.0.0").exists());
+}
+
+#[test]
+fn download_crates_dry_run() {
+    let server = MockCratesIo::start();
+    let target = tempdir().unwrap();
+    let cache_dir = tempdir().unwrap();
+    fs::write(target.path().join("kept.txt"), "").unwrap();
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "3", "-x", "c2", "--dry-run"]);
+    let output = run_command(command);
+
+    assert!(output.status.success(), "{}", output.stderr);
+    assert!(
+        output
+            .stdout
+            .contains("Would download:\n- c1-1.0.0\n- c3-1.0.0\n- c4-1.0.0\n\nTotal: 3\n"),
+        "{}",
+        output.stdout
+    );
+    assert_eq!(server.downloads(), 0);
+    // The target isn't cleared, even though it isn't empty.
+    let entries: Vec<_> = fs::read_dir(target.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(entries, ["kept.txt"]);
+
+    // The crates skipped for being listed twice are shown too.
+    server
+        .shifted_listing
+        .store(true, std::sync::atomic::Ordering::SeqCst);
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "3", "--dry-run"]);
+    let output = run_command(command);
+
+    assert!(output.status.success(), "{}", output.stderr);
+    assert!(
+        output.stdout.contains(
+            "Would download:\n- c1-1.0.0\n- c2-1.0.0\n- c3-1.0.0\n\nTotal: 3\n\n\
+            Skipped as listed twice:\n- c2\n"
+        ),
+        "{}",
+        output.stdout
+    );
+    assert_eq!(server.downloads(), 0);
+}
+
+#[test]
+fn download_crates_no_clean() {
+    let server = MockCratesIo::start();
+    let cache_dir = tempdir().unwrap();
+    let target = tempdir().unwrap();
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "1", "--no-clean"]);
+    let output = run_command(command);
+    assert!(output.status.success(), "{}", output.stderr);
+
+    let crate_dir = target.path().join("c1-1.0.0");
+    assert_eq!(
+        fs::read_to_string(crate_dir.join("src/lib.rs")).unwrap(),
+        "#![allow(clippy::all)]\n"
+    );
+    assert!(!crate_dir.join("src/lib.rs.orig").exists());
+    assert!(!crate_dir.join("Cargo.toml.bak").exists());
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "1", "--no-clean", "--verify-clean", "--force"]);
+    let output = run_command(command);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output
+        .stderr
+        .contains("`--verify-clean` can't be used with `--no-clean`"));
+}
+
+#[test]
+fn download_crates_continues_after_failure() {
+    let server = MockCratesIo::start();
+    server.missing.lock().unwrap().push("c2".into());
+    let target = tempdir().unwrap();
+    let cache_dir = tempdir().unwrap();
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "3"]);
+    let output = run_command(command);
+
+    assert!(output.status.success(), "{}", output.stderr);
+    assert!(output.stdout.contains("Downloaded 2 crates in "));
+    assert!(
+        output
+            .stdout
+            .contains("Failed to download 1 crates:\n- Not found (1): c2\n"),
+        "{}",
+        output.stdout
+    );
+    assert!(target.path().join("c1-1.0.0/src/lib.rs").is_file());
+    assert!(!target.path().join("c2-1.0.0").exists());
+    assert!(target.path().join("c3-1.0.0/src/lib.rs").is_file());
+}
+
+#[test]
+fn download_crates_redownload_failed() {
+    let server = MockCratesIo::start();
+    server.missing.lock().unwrap().push("c2".into());
+    let target = tempdir().unwrap();
+    let cache_dir = tempdir().unwrap();
+    let report = cache_dir.path().join("failures.txt");
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "3", "--failure-report"]).arg(&report);
+    let output = run_command(command);
+    assert!(output.status.success(), "{}", output.stderr);
+    assert!(!target.path().join("c2-1.0.0").exists());
+    let expected_report = expect![[r"
+        # Crates that failed to download. Retry them with `--redownload-failed`.
+        c2 1.0.0 # Not found
+    "]];
+    expected_report.assert_eq(&fs::read_to_string(&report).unwrap());
+
+    // The retry only fetches the failed crate.
+    server.missing.lock().unwrap().clear();
+    let downloads = server.downloads();
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command
+        .arg("--redownload-failed")
+        .arg(&report)
+        .arg("--failure-report")
+        .arg(&report);
+    let output = run_command(command);
+    assert!(output.status.success(), "{}", output.stderr);
+    assert!(
+        output.stdout.contains("Resolved 1 of 1 failed crates\n"),
+        "{}",
+        output.stdout
+    );
+    assert_eq!(server.downloads(), downloads + 1);
+    let mut entries: Vec<_> = fs::read_dir(target.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, ["c1-1.0.0", "c2-1.0.0", "c3-1.0.0"]);
+    assert!(target.path().join("c2-1.0.0/src/lib.rs").is_file());
+    let expected_report = expect![[r"
+        # Crates that failed to download. Retry them with `--redownload-failed`.
+    "]];
+    expected_report.assert_eq(&fs::read_to_string(&report).unwrap());
+
+    // Crates already in the target aren't downloaded again.
+    fs::write(&report, "c1 1.0.0 # Network\n").unwrap();
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.arg("--redownload-failed").arg(&report);
+    let output = run_command(command);
+    assert!(output.status.success(), "{}", output.stderr);
+    assert!(output
+        .stdout
+        .contains("Skipping 'c1'. Already downloaded.\n"));
+    assert!(output.stdout.contains("Resolved 0 of 0 failed crates\n"));
+    assert_eq!(server.downloads(), downloads + 1);
+}
+
+#[test]
+fn download_crates_from_lock() {
+    let server = MockCratesIo::start();
+    let target = tempdir().unwrap();
+    let cache_dir = tempdir().unwrap();
+    // Already downloaded
+    fs::create_dir(target.path().join("c1-1.0.0")).unwrap();
+    fs::write(target.path().join("c1-1.0.0/kept.txt"), "").unwrap();
+    let lockfile = cache_dir.path().join("Cargo.lock");
+    fs::write(
+        &lockfile,
+        indoc::indoc! {r#"
+            version = 3
+
+            [[package]]
+            name = "c1"
+            version = "1.0.0"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+
+            [[package]]
+            name = "c1"
+            version = "2.0.0"
+            source = "sparse+https://index.crates.io/"
+
+            [[package]]
+            name = "c2"
+            version = "1.2.3"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+            dependencies = ["c1 2.0.0"]
+
+            [[package]]
+            name = "c2"
+            version = "1.2.3"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+
+            [[package]]
+            name = "c3"
+            version = "0.5.0"
+            source = "git+https://github.com/example/c3#0123456789abcdef"
+
+            [[package]]
+            name = "c4"
+            version = "1.0.0"
+            source = "registry+https://example.com/index"
+
+            [[package]]
+            name = "project"
+            version = "0.1.0"
+            dependencies = ["c2", "c3", "c4"]
+        "#},
+    )
+    .unwrap();
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.arg("--from-lock").arg(&lockfile);
+    let output = run_command(command);
+
+    assert!(output.status.success(), "{}", output.stderr);
+    assert!(output
+        .stdout
+        .contains("Skipping 'c1'. Already downloaded.\n"));
+    assert_eq!(server.downloads(), 2);
+    let mut entries: Vec<_> = fs::read_dir(target.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, ["c1-1.0.0", "c1-2.0.0", "c2-1.2.3"]);
+    assert!(target.path().join("c1-1.0.0/kept.txt").is_file());
+    assert!(target.path().join("c2-1.2.3/src/lib.rs").is_file());
+}
+
+#[test]
+fn download_crates_unconventional_archive_dir() {
+    let server = MockCratesIo::start();
+    server
+        .archive_dirs
+        .lock()
+        .unwrap()
+        .insert("c1".into(), vec!["unusual".into()]);
+    server
+        .archive_dirs
+        .lock()
+        .unwrap()
+        .insert("c2".into(), vec!["c2-1.0.0".into(), "extra".into()]);
+    let target = tempdir().unwrap();
+    let cache_dir = tempdir().unwrap();
+
+    let mut command = server.download_crates_command(target.path(), cache_dir.path());
+    command.args(["-n", "2"]);
+    let output = run_command(command);
+
+    assert!(output.status.success(), "{}", output.stderr);
+    assert!(output.stdout.contains("Downloaded 1 crates in "));
+    // The crate is cleaned where it was unpacked.
+    assert!(fs::read_to_string(target.path().join("unusual/src/lib.rs"))
+        .unwrap()
+        .starts_with("/* cleaned by clippy_lint_tester"));
+    assert!(!target.path().join("c1-1.0.0").exists());
+    assert!(
+        output.stdout.contains(
+            "error: Failed to download 'c2' - Unexpected layout of crate 'c2': the archive has \
+            multiple top-level directories: `c2-1.0.0`, `extra`"
+        ),
+        "{}",
+        output.stdout
+    );
+    assert!(!target.path().join("c2-1.0.0").exists());
+    assert!(!target.path().join("extra").exists());
+}
+
+#[test]
+fn clean_source_report() {
+    let target = make_target(&["a"]);
+    let crate_dir = target.path().join("a");
+    fs::write(
+        crate_dir.join("src/lib.rs"),
+        indoc::indoc! {"
+            #![deny(warnings)]
+            #![allow(
+                clippy::all,
+            )]
+
+            #[allow(dead_code)]
+            fn f() {}
+        "},
+    )
+    .unwrap();
+    let report_path = target.path().join("report.txt");
+
+    let output = run_command({
+        let mut command = Command::new(env!("CARGO_BIN_EXE_clean_source"));
+        command.arg(&crate_dir).arg("--report").arg(&report_path);
+        command
+    });
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    let expected_report = expect![[r"
+        src/lib.rs:1:0 #![deny(warnings)]
+        src/lib.rs:2:0 #![allow( clippy::all, )]
+        src/lib.rs:6:0 #[allow(dead_code)]
+
+        Total: 3
+    "]];
+    expected_report.assert_eq(&fs::read_to_string(&report_path).unwrap());
+    assert!(fs::read_to_string(crate_dir.join("src/lib.rs"))
+        .unwrap()
+        .contains("/* cleaned by clippy_lint_tester #![deny(warnings)] */"));
+}
+
+#[test]
+fn clean_source_clean_to() {
+    // Every file under `dir` with its contents
+    fn read_tree(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files: Vec<_> = walkdir::WalkDir::new(dir)
+            .into_iter()
+            .map(Result::unwrap)
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| {
+                let path = entry.path().strip_prefix(dir).unwrap().to_path_buf();
+                (path, fs::read(entry.path()).unwrap())
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    let target = make_target(&["a"]);
+    let crate_dir = target.path().join("a");
+    fs::write(
+        crate_dir.join("src/lib.rs"),
+        "#![deny(warnings)]\n\n#[allow(dead_code)]\nfn f() {}\n",
+    )
+    .unwrap();
+    let original = read_tree(&crate_dir);
+    let clean_to = target.path().join("cleaned");
+    let report_path = target.path().join("report.txt");
+    let clean_source = || {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_clean_source"));
+        command
+            .arg(&crate_dir)
+            .arg("--clean-to")
+            .arg(&clean_to)
+            .arg("--report")
+            .arg(&report_path);
+        run_command(command)
+    };
+
+    let output = clean_source();
+    assert_eq!(output.status.code(), Some(0), "{}", output.stderr);
+    assert_eq!(read_tree(&crate_dir), original);
+    let cleaned = read_tree(&clean_to);
+    let cleaned_paths: Vec<_> = cleaned.iter().map(|(path, _)| path.clone()).collect();
+    let original_paths: Vec<_> = original.iter().map(|(path, _)| path.clone()).collect();
+    assert_eq!(cleaned_paths, original_paths);
+    assert!(fs::read_to_string(clean_to.join("src/lib.rs"))
+        .unwrap()
+        .contains("/* cleaned by clippy_lint_tester #![deny(warnings)] */"));
+    let expected_report = expect![[r"
+        src/lib.rs:1:0 #![deny(warnings)]
+        src/lib.rs:3:0 #[allow(dead_code)]
+
+        Total: 2
+    "]];
+    expected_report.assert_eq(&fs::read_to_string(&report_path).unwrap());
+
+    // An earlier copy isn't overwritten.
+    let output = clean_source();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        output.stderr.contains("already exists"),
+        "{}",
+        output.stderr
+    );
+}